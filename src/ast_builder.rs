@@ -0,0 +1,449 @@
+use pest::iterators::Pair;
+
+use super::structs::{
+  ast::*,
+  parser::Rule,
+};
+
+// Builds the syntax tree of a parsed program
+pub fn build_program(pair: Pair<Rule>) -> Program {
+  // program <id> ; <declarations> begin <body> end
+  let span = span_of(&pair);
+  let mut name = None;
+  let mut module = Module::default();
+  let mut body = None;
+  let (mut begin, mut end) = (Span::default(), Span::default());
+  for inner_pair in pair.into_inner() {
+      match inner_pair.as_rule() {
+          Rule::id => name = Some(build_ident(inner_pair)),
+          Rule::body => body = Some(build_block(inner_pair)),
+          Rule::beginKeyword => begin = span_of(&inner_pair),
+          Rule::endKeyword => end = span_of(&inner_pair),
+          _ => add_declaration(&mut module, inner_pair),
+      }
+  }
+
+  Program {
+      name: name.unwrap(),
+      module,
+      body: body.unwrap(),
+      begin,
+      end,
+      span,
+  }
+}
+
+// Builds the declarations of an imported file
+pub fn build_module(pair: Pair<Rule>) -> Module {
+  let mut module = Module::default();
+  for inner_pair in pair.into_inner() {
+      add_declaration(&mut module, inner_pair);
+  }
+  module
+}
+
+// Builds one input of the REPL: declarations, then statements, then an
+// expression that is printed
+pub fn build_repl_input(pair: Pair<Rule>) -> (Module, Block) {
+  let mut module = Module::default();
+  let mut body = Block {
+      vars: Vec::new(),
+      stmts: Vec::new(),
+      span: span_of(&pair),
+  };
+  for inner_pair in pair.into_inner() {
+      match inner_pair.as_rule() {
+          Rule::statement => body.stmts.push(build_statement(inner_pair)),
+          Rule::expression => {
+              let expr = build_expression(inner_pair);
+              let span = expr.span;
+              body.stmts.push(Stmt { kind: StmtKind::Print(vec![PrintElement::Expr(expr)]), span });
+          }
+          _ => add_declaration(&mut module, inner_pair),
+      }
+  }
+  (module, body)
+}
+
+pub fn span_of(pair: &Pair<Rule>) -> Span {
+  let (line, col) = pair.as_span().start_pos().line_col();
+  let (end_line, end_col) = pair.as_span().end_pos().line_col();
+  Span { line, col, end_line, end_col }
+}
+
+// Span from the start of the first node to the end of the second
+fn join(start: Span, end: Span) -> Span {
+  Span {
+      line: start.line,
+      col: start.col,
+      end_line: end.end_line,
+      end_col: end.end_col,
+  }
+}
+
+fn build_ident(pair: Pair<Rule>) -> Ident {
+  Ident::new(pair.as_str().to_string(), span_of(&pair))
+}
+
+fn add_declaration(module: &mut Module, pair: Pair<Rule>) {
+  match pair.as_rule() {
+      Rule::imports => {
+          // import "<file>" ;
+          let span = span_of(&pair);
+          let path = pair.into_inner()
+              .find(|inner_pair| inner_pair.as_rule() == Rule::string)
+              .unwrap()
+              .into_inner()
+              .as_str()
+              .to_string();
+          module.imports.push(Import { path, module: None, span });
+      }
+      Rule::types => {
+          // type <id> = record { <field>, ... } ;
+          let span = span_of(&pair);
+          let mut inner_pairs = pair.into_inner().skip(1);
+          let name = build_ident(inner_pairs.next().unwrap());
+          let fields = inner_pairs
+              .filter(|inner_pair| inner_pair.as_rule() == Rule::field)
+              .map(|field| {
+                  let mut field_pairs = field.into_inner();
+                  (build_ident(field_pairs.next().unwrap()), build_ident(field_pairs.next().unwrap()))
+              })
+              .collect();
+          module.types.push(TypeDecl::Record(RecordDecl { name, fields, span }));
+      }
+      Rule::enums => {
+          // enum <id> { <id>, ... } ;
+          let span = span_of(&pair);
+          let mut ids = pair.into_inner()
+              .filter(|inner_pair| inner_pair.as_rule() == Rule::id)
+              .map(build_ident);
+          let name = ids.next().unwrap();
+          module.types.push(TypeDecl::Enum(EnumDecl { name, variants: ids.collect(), span }));
+      }
+      Rule::consts => module.consts.push(build_const(pair)),
+      Rule::vars => {
+          // The REPL can declare globals in several inputs, the first keyword is kept
+          if module.vars.is_empty() {
+              module.vars_keyword = span_of(&pair.clone().into_inner().next().unwrap());
+          }
+          module.vars.extend(build_vars(pair));
+      }
+      Rule::funcs => module.funcs.push(build_func(pair)),
+      _ => {}
+  }
+}
+
+fn build_const(pair: Pair<Rule>) -> ConstDecl {
+  // const <id> : <typeVar> = <expression> ;
+  let span = span_of(&pair);
+  let mut inner_pairs = pair.into_inner().skip(1);
+  let name = build_ident(inner_pairs.next().unwrap());
+  let const_type = build_ident(inner_pairs.next().unwrap());
+  let value = build_expression(inner_pairs.nth(1).unwrap());
+  ConstDecl { name, const_type, value, span }
+}
+
+fn build_vars(pair: Pair<Rule>) -> Vec<VarDecl> {
+  // vars <id_list> : <typeVar> (= <expression>)? , ... ;
+  pair.into_inner()
+      .filter(|inner_pair| inner_pair.as_rule() == Rule::var_group)
+      .map(|var_group| {
+          let span = span_of(&var_group);
+          let mut inner_pairs = var_group.into_inner();
+          let names = inner_pairs.next().unwrap().into_inner().map(build_ident).collect();
+          let var_type = build_ident(inner_pairs.next().unwrap());
+          let init = inner_pairs.next().map(|var_init| build_expression(var_init.into_inner().nth(1).unwrap()));
+          VarDecl { names, var_type, init, span }
+      })
+      .collect()
+}
+
+fn build_func(pair: Pair<Rule>) -> FuncDecl {
+  // void <id> ( <parameters>? ) { <consts> <vars> <statements> } ;
+  let span = span_of(&pair);
+  let mut function = FuncDecl {
+      name: Ident::new(String::new(), span),
+      params: Vec::new(),
+      consts: Vec::new(),
+      vars: Vec::new(),
+      body: Vec::new(),
+      signature: String::new(),
+      span,
+  };
+  for inner_pair in pair.into_inner() {
+      match inner_pair.as_rule() {
+          Rule::id => function.name = build_ident(inner_pair),
+          Rule::parameters => {
+              for id_type_list in inner_pair.into_inner() {
+                  let mut param_pairs = id_type_list.into_inner();
+                  function.params.push((build_ident(param_pairs.next().unwrap()), build_ident(param_pairs.next().unwrap())));
+              }
+          }
+          Rule::func_body => {
+              for body_pair in inner_pair.into_inner() {
+                  match body_pair.as_rule() {
+                      Rule::consts => function.consts.push(build_const(body_pair)),
+                      Rule::vars => function.vars.extend(build_vars(body_pair)),
+                      Rule::statement => function.body.push(build_statement(body_pair)),
+                      _ => {}
+                  }
+              }
+          }
+          _ => {}
+      }
+  }
+  function
+}
+
+fn build_block(pair: Pair<Rule>) -> Block {
+  // { <vars>? <statement>* }
+  let mut block = Block {
+      vars: Vec::new(),
+      stmts: Vec::new(),
+      span: span_of(&pair),
+  };
+  for inner_pair in pair.into_inner() {
+      match inner_pair.as_rule() {
+          Rule::vars => block.vars.extend(build_vars(inner_pair)),
+          Rule::statement => block.stmts.push(build_statement(inner_pair)),
+          _ => {}
+      }
+  }
+  block
+}
+
+fn build_statement(pair: Pair<Rule>) -> Stmt {
+  let pair = pair.into_inner().next().unwrap();
+  let span = span_of(&pair);
+  let rule = pair.as_rule();
+  let mut inner_pairs = pair.into_inner();
+
+  let kind = match rule {
+      Rule::assign => {
+          // <id> = <expression> ;
+          let target = build_ident(inner_pairs.next().unwrap());
+          let value = build_expression(inner_pairs.nth(1).unwrap());
+          StmtKind::Assign { target, value }
+      }
+      Rule::condition => {
+          // if ( <expression> ) <body> (else <body>)? ;
+          let condition = build_expression(inner_pairs.nth(2).unwrap());
+          let mut blocks = inner_pairs.filter(|inner_pair| inner_pair.as_rule() == Rule::body).map(build_block);
+          let then_block = blocks.next().unwrap();
+          StmtKind::If { condition, then_block, else_block: blocks.next() }
+      }
+      Rule::while_loop => {
+          // while ( <expression> ) do <body> ;
+          let condition = build_expression(inner_pairs.nth(2).unwrap());
+          let body = build_block(inner_pairs.find(|inner_pair| inner_pair.as_rule() == Rule::body).unwrap());
+          StmtKind::While { condition, body }
+      }
+      Rule::match_stmt => {
+          // match ( <expression> ) { <value> => <body> ... _ => <body> } ;
+          let scrutinee = build_expression(inner_pairs.nth(2).unwrap());
+          let mut arms = Vec::new();
+          let mut default = None;
+          for arm in inner_pairs {
+              match arm.as_rule() {
+                  Rule::match_arm => {
+                      let span = span_of(&arm);
+                      let mut arm_pairs = arm.into_inner();
+                      let pattern = build_value(arm_pairs.next().unwrap());
+                      let body = build_block(arm_pairs.next().unwrap());
+                      arms.push(MatchArm { pattern, body, span });
+                  }
+                  Rule::default_arm => default = Some(build_block(arm.into_inner().next().unwrap())),
+                  _ => {}
+              }
+          }
+          StmtKind::Match { scrutinee, arms, default }
+      }
+      Rule::func_call => StmtKind::Call(build_call(inner_pairs, span)),
+      Rule::print => {
+          // print!( <expression | string>, ... ) ;
+          let elements = inner_pairs
+              .filter(|inner_pair| inner_pair.as_rule() == Rule::print_element)
+              .map(|print_element| {
+                  let element = print_element.into_inner().next().unwrap();
+                  match element.as_rule() {
+                      Rule::string => {
+                          let span = span_of(&element);
+                          PrintElement::Str(element.into_inner().as_str().to_string(), span)
+                      }
+                      _ => PrintElement::Expr(build_expression(element)),
+                  }
+              })
+              .collect();
+          StmtKind::Print(elements)
+      }
+      _ => unreachable!(),
+  };
+  Stmt { kind, span }
+}
+
+fn build_call(mut inner_pairs: pest::iterators::Pairs<Rule>, span: Span) -> Call {
+  // <id> ( <expression>, ... )
+  let name = build_ident(inner_pairs.next().unwrap());
+  let args = inner_pairs
+      .filter(|inner_pair| inner_pair.as_rule() == Rule::expression)
+      .map(build_expression)
+      .collect();
+  Call {
+      name,
+      args,
+      function: String::new(),
+      is_builtin: false,
+      signature: String::new(),
+      span,
+  }
+}
+
+fn build_expression(pair: Pair<Rule>) -> Expr {
+  match pair.as_rule() {
+      Rule::expression => {
+          // <exp> (<comparator> <exp>)?
+          let mut inner_pairs = pair.into_inner();
+          let left = build_expression(inner_pairs.next().unwrap());
+          match inner_pairs.next() {
+              Some(comparator) => {
+                  let right = build_expression(inner_pairs.next().unwrap());
+                  binary(comparator.as_str(), left, right)
+              }
+              None => left,
+          }
+      }
+      Rule::exp | Rule::term => {
+          // Flatten the right-recursive rule so operators are applied from left to right
+          let mut operands = Vec::new();
+          let mut operators = Vec::new();
+          flatten(pair.as_rule(), pair, &mut operands, &mut operators);
+
+          let mut operands = operands.into_iter();
+          let mut result = build_expression(operands.next().unwrap());
+          for (operator, operand) in operators.into_iter().zip(operands) {
+              result = binary(&operator, result, build_expression(operand));
+          }
+          result
+      }
+      Rule::factor => {
+          let span = span_of(&pair);
+          let inner_pair = pair.into_inner()
+              .find(|inner_pair| inner_pair.as_rule() != Rule::openP && inner_pair.as_rule() != Rule::closeP)
+              .unwrap();
+          match inner_pair.as_rule() {
+              Rule::expression => Expr::new(ExprKind::Group(Box::new(build_expression(inner_pair))), span),
+              _ => build_expression(inner_pair),
+          }
+      }
+      Rule::conversion => {
+          // <conversion> ( <expression> )
+          let span = span_of(&pair);
+          let mut inner_pairs = pair.into_inner();
+          let conversion = inner_pairs.next().unwrap().as_str().to_string();
+          let operand = build_expression(inner_pairs.nth(1).unwrap());
+          Expr::new(ExprKind::Conversion { conversion, operand: Box::new(operand) }, span)
+      }
+      Rule::call => {
+          let span = span_of(&pair);
+          Expr::new(ExprKind::Call(Box::new(build_call(pair.into_inner(), span))), span)
+      }
+      Rule::value => build_value(pair),
+      _ => unreachable!(),
+  }
+}
+
+fn build_value(pair: Pair<Rule>) -> Expr {
+  // <sign>? (<id> | <cte>)
+  let span = span_of(&pair);
+  let mut sign = None;
+  let mut operand = None;
+  for inner_pair in pair.into_inner() {
+      let inner_span = span_of(&inner_pair);
+      match inner_pair.as_rule() {
+          Rule::sign => sign = Some(inner_pair.as_str().to_string()),
+          Rule::id | Rule::access => operand = Some(Expr::new(ExprKind::Name(build_ident(inner_pair)), inner_span)),
+          _ => {
+              let cte = inner_pair.into_inner().next().unwrap();
+              let kind = match cte.as_rule() {
+                  Rule::cte_float => ExprKind::Float(cte.as_str().to_string()),
+                  _ => ExprKind::Int(cte.as_str().to_string()),
+              };
+              operand = Some(Expr::new(kind, inner_span));
+          }
+      }
+  }
+
+  match sign {
+      Some(sign) => Expr::new(ExprKind::Unary { sign, operand: Box::new(operand.unwrap()) }, span),
+      None => operand.unwrap(),
+  }
+}
+
+fn flatten<'a>(rule: Rule, pair: Pair<'a, Rule>, operands: &mut Vec<Pair<'a, Rule>>, operators: &mut Vec<String>) {
+  for inner_pair in pair.into_inner() {
+      match inner_pair.as_rule() {
+          inner_rule if inner_rule == rule => flatten(rule, inner_pair, operands, operators),
+          Rule::sign | Rule::operator => operators.push(inner_pair.as_str().to_string()),
+          _ => operands.push(inner_pair),
+      }
+  }
+}
+
+fn binary(operator: &str, left: Expr, right: Expr) -> Expr {
+  let span = join(left.span, right.span);
+  Expr::new(
+      ExprKind::Binary {
+          operator: operator.to_string(),
+          left: Box::new(left),
+          right: Box::new(right),
+      },
+      span
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{compile, parse};
+  use crate::structs::dusty_context::DustyContext;
+
+  const SOURCE: &str = "program p;
+vars x: int;
+begin
+{
+  x = 1 + 2 * x;
+}
+end";
+
+  #[test]
+  fn the_tree_keeps_the_structure_and_spans_of_the_source() {
+      let program = parse(SOURCE).unwrap();
+      assert_eq!(program.name.name, "p");
+      assert_eq!(program.module.vars[0].names[0].span, Span { line: 2, col: 6, end_line: 2, end_col: 7 });
+      let StmtKind::Assign { target, value } = &program.body.stmts[0].kind else {
+          panic!("expected an assignment");
+      };
+      assert_eq!(target.name, "x");
+      // * binds tighter than +
+      let ExprKind::Binary { operator, right, .. } = &value.kind else {
+          panic!("expected a binary expression");
+      };
+      assert_eq!(operator, "+");
+      assert!(matches!(&right.kind, ExprKind::Binary { operator, .. } if operator == "*"));
+      assert_eq!(value.span, Span { line: 5, col: 7, end_line: 5, end_col: 16 });
+      // Annotations are set by the later passes
+      assert!(target.symbol.is_none() && value.ty.is_empty());
+  }
+
+  #[test]
+  fn compiling_annotates_the_tree() {
+      let mut dusty_context = DustyContext::new();
+      let program = compile(SOURCE, &mut dusty_context).unwrap();
+      let StmtKind::Assign { target, value } = &program.body.stmts[0].kind else {
+          panic!("expected an assignment");
+      };
+      assert_eq!(dusty_context.symbols[target.symbol.unwrap()].name, "x");
+      assert_eq!(value.ty, "int");
+  }
+}
@@ -0,0 +1,68 @@
+use super::structs::{
+  runtime_error::RuntimeError,
+  value::Value
+};
+
+// Native implementations of the builtin functions
+pub fn call_builtin(name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+  let args: Vec<f64> = args.iter().map(|arg| match arg {
+      Value::Int(value) => *value as f64,
+      Value::Float(value) => *value,
+  }).collect();
+  let result = match name {
+      "abs" => args[0].abs(),
+      "min" => args[0].min(args[1]),
+      "max" => args[0].max(args[1]),
+      "pow" => args[0].powf(args[1]),
+      "sqrt" => {
+          if args[0] < 0.0 {
+              return Err(RuntimeError::MathDomain(format!("sqrt of negative number {}", args[0])));
+          }
+          args[0].sqrt()
+      }
+      "sin" => args[0].sin(),
+      "cos" => args[0].cos(),
+      "exp" => args[0].exp(),
+      "log" => {
+          if args[0] <= 0.0 {
+              return Err(RuntimeError::MathDomain(format!("log of non-positive number {}", args[0])));
+          }
+          args[0].ln()
+      }
+      _ => return Err(RuntimeError::UnknownFunction(name.to_string())),
+  };
+  Ok(Value::Float(result))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile;
+  use crate::structs::{dusty_context::DustyContext, vm_options::VmOptions};
+  use crate::virtual_machine::run_virtual_machine;
+
+  #[test]
+  fn builtins_compute_floats() {
+      assert_eq!(call_builtin("abs", &[Value::Float(-2.5)]), Ok(Value::Float(2.5)));
+      assert_eq!(call_builtin("max", &[Value::Float(1.0), Value::Float(4.0)]), Ok(Value::Float(4.0)));
+      assert_eq!(call_builtin("pow", &[Value::Float(2.0), Value::Float(10.0)]), Ok(Value::Float(1024.0)));
+      assert_eq!(call_builtin("sqrt", &[Value::Float(9.0)]), Ok(Value::Float(3.0)));
+  }
+
+  #[test]
+  fn builtins_outside_their_domain_are_errors() {
+      assert_eq!(
+          call_builtin("sqrt", &[Value::Float(-1.0)]),
+          Err(RuntimeError::MathDomain("sqrt of negative number -1".to_string()))
+      );
+      assert_eq!(
+          call_builtin("log", &[Value::Float(0.0)]),
+          Err(RuntimeError::MathDomain("log of non-positive number 0".to_string()))
+      );
+
+      let mut dusty_context = DustyContext::new();
+      compile("program p; vars x: float; begin { x = log(0.0 - 1.0); } end", &mut dusty_context).unwrap();
+      let failure = run_virtual_machine(&dusty_context, &VmOptions::new()).unwrap_err();
+      assert_eq!(failure.error.exit_code(), 6);
+  }
+}
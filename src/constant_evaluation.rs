@@ -0,0 +1,168 @@
+use super::structs::{
+  ast::{Expr, ExprKind, Span},
+  diagnostic::Diagnostic,
+  dusty_context::DustyContext,
+  symbol::SymbolKind,
+};
+
+// Evaluates a constant expression at compile time, returns (value, type)
+pub fn evaluate_constant(expr: &Expr, dusty_context: &DustyContext) -> Result<(String, String), Diagnostic> {
+  match &expr.kind {
+      ExprKind::Int(value) => Ok((value.clone(), "int".to_string())),
+      ExprKind::Float(value) => Ok((value.clone(), "float".to_string())),
+      ExprKind::Group(operand) => evaluate_constant(operand, dusty_context),
+      ExprKind::Unary { sign, operand } => {
+          let result = evaluate_constant(operand, dusty_context)?;
+          if sign == "-" {
+              let zero = ("0".to_string(), result.1.clone());
+              return apply_operator("-", zero, result, expr.span, dusty_context);
+          }
+          Ok(result)
+      }
+      ExprKind::Binary { operator, left, right } => {
+          let left = evaluate_constant(left, dusty_context)?;
+          let right = evaluate_constant(right, dusty_context)?;
+          apply_operator(operator, left, right, expr.span, dusty_context)
+      }
+      ExprKind::Name(id) => {
+          let constant = id.symbol
+              .map(|symbol| &dusty_context.symbols[symbol])
+              .filter(|symbol| id.field.is_none() && matches!(symbol.kind, SymbolKind::Constant | SymbolKind::Variant))
+              .and_then(|symbol| symbol.value.clone().map(|value| (value, symbol.var_type.clone())));
+          match constant {
+              Some(constant) => Ok(constant),
+              None => Err(Diagnostic::new(format!("ERROR: \"{}\" is not a constant and cannot be used in a constant expression, line: {}, col: {}",
+                  id.name,
+                  id.span.line,
+                  id.span.col
+              ), id.span)),
+          }
+      }
+      ExprKind::Conversion { .. } | ExprKind::Call(_) => Err(Diagnostic::new(format!(
+          "ERROR: Conversions and function calls are not constant expressions, line: {}, col: {}",
+          expr.span.line,
+          expr.span.col
+      ), expr.span)),
+  }
+}
+
+fn apply_operator(
+  operator: &str,
+  left: (String, String),
+  right: (String, String),
+  span: Span,
+  dusty_context: &DustyContext
+) -> Result<(String, String), Diagnostic> {
+  let result_type = dusty_context.quad_data.semantic_cube.get_result_type(&left.1, &right.1, operator);
+  if result_type == "error" {
+      return Err(Diagnostic::new(format!("ERROR: Type mismatch. Cannot use {} with {} and {}, line: {}, col: {}",
+          operator, left.1, right.1, span.line, span.col
+      ), span));
+  }
+
+  if left.1 == "int" && right.1 == "int" && operator != "/" {
+      let left = left.0.parse::<i64>().unwrap();
+      let right = right.0.parse::<i64>().unwrap();
+      let result = match operator {
+          "+" => left.checked_add(right),
+          "-" => left.checked_sub(right),
+          "*" => left.checked_mul(right),
+          "<" => Some((left < right) as i64),
+          ">" => Some((left > right) as i64),
+          "==" => Some((left == right) as i64),
+          "!=" => Some((left != right) as i64),
+          _ => None,
+      };
+      match result {
+          Some(result) => Ok((result.to_string(), result_type)),
+          None => Err(Diagnostic::new(format!("ERROR: Integer overflow in constant expression ({} {} {}), line: {}, col: {}",
+              left, operator, right, span.line, span.col
+          ), span)),
+      }
+  } else {
+      let left = left.0.parse::<f64>().unwrap();
+      let right = right.0.parse::<f64>().unwrap();
+      if operator == "/" && right == 0.0 {
+          return Err(Diagnostic::new(format!("ERROR: Division by zero in constant expression, line: {}, col: {}",
+              span.line, span.col
+          ), span));
+      }
+      let result = match operator {
+          "+" => left + right,
+          "-" => left - right,
+          "*" => left * right,
+          "/" => left / right,
+          "<" => return Ok((((left < right) as i64).to_string(), result_type)),
+          ">" => return Ok((((left > right) as i64).to_string(), result_type)),
+          "==" => return Ok((((left == right) as i64).to_string(), result_type)),
+          _ => return Ok((((left != right) as i64).to_string(), result_type)),
+      };
+      Ok((format!("{:?}", result), result_type))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::compile;
+
+  fn constant_value(dusty_context: &DustyContext, name: &str) -> Option<String> {
+      dusty_context.symbols.iter()
+          .find(|symbol| symbol.name == name && symbol.kind == SymbolKind::Constant)
+          .and_then(|symbol| symbol.value.clone())
+  }
+
+  fn compile_error(source: &str) -> String {
+      let mut dusty_context = DustyContext::new();
+      compile(source, &mut dusty_context).expect_err("the program should not compile").to_string()
+  }
+
+  #[test]
+  fn constants_are_folded_at_compile_time() {
+      let mut dusty_context = DustyContext::new();
+      compile(&fs::read_to_string("src/tests/test4.dusty").unwrap(), &mut dusty_context).unwrap();
+      assert_eq!(constant_value(&dusty_context, "MAX").as_deref(), Some("100"));
+      assert_eq!(constant_value(&dusty_context, "LIMIT").as_deref(), Some("180"));
+      assert_eq!(constant_value(&dusty_context, "RATE").as_deref(), Some("5.0"));
+      assert_eq!(constant_value(&dusty_context, "OFFSET").as_deref(), Some("181"));
+  }
+
+  #[test]
+  fn constant_expressions_report_their_errors() {
+      assert_eq!(
+          compile_error("program p; void f(a: int) { const B: int = a + 1; }; begin { } end"),
+          "ERROR: \"a\" is not a constant and cannot be used in a constant expression, line: 1, col: 44"
+      );
+      assert_eq!(
+          compile_error("program p; const B: float = 1.0 / 0.0; begin { } end"),
+          "ERROR: Division by zero in constant expression, line: 1, col: 29"
+      );
+      assert_eq!(
+          compile_error("program p; const B: int = 9223372036854775807 + 1; begin { } end"),
+          "ERROR: Integer overflow in constant expression (9223372036854775807 + 1), line: 1, col: 27"
+      );
+      assert_eq!(
+          compile_error("program p; const B: int = 2.5; begin { } end"),
+          "ERROR: Type mismatch. Cannot assign float to constant \"B\" of type int, line: 1, col: 18"
+      );
+  }
+
+  #[test]
+  fn constants_cant_be_assigned() {
+      assert_eq!(
+          compile_error("program p; const MAX: int = 10; begin { MAX = 5; } end"),
+          "ERROR: Cannot assign to constant \"MAX\", line: 1, col: 41"
+      );
+  }
+
+  #[test]
+  fn strings_and_numbers_with_the_same_text_have_their_own_address() {
+      let mut dusty_context = DustyContext::new();
+      let number = dusty_context.add_constant("1", "int");
+      let string = dusty_context.add_constant("1", "string");
+      assert_ne!(number.location, string.location);
+      assert_eq!(dusty_context.add_constant("1", "int").location, number.location);
+  }
+}
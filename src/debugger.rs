@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use colored::*;
+
+use super::virtual_machine::VirtualMachine;
+use super::structs::{
+  dusty_context::DustyContext,
+  source_location::SourceLocation,
+  var_info::VarInfo,
+  vm_options::VmOptions,
+};
+
+const HELP: &str = "Commands:
+  break <line>, break <file>:<line>  stop at the statements of a line (b)
+  delete [<line>]                    remove a breakpoint, or all of them
+  run                                start the program again from the beginning
+  continue                           run until a breakpoint, a watch or the end (c)
+  step                               run until the next statement, entering calls (s)
+  next                               run until the next statement of this function (n)
+  finish                             run until the function returns (out)
+  print <var>                        value of a variable, parameter, constant or field (p)
+  backtrace                          functions being run and their lines (bt)
+  watch <var>, unwatch <var>         stop when the value of a variable changes
+  quit                               exit (q)";
+
+// Where the VM must stop when it reaches a statement
+enum Resume {
+  Continue,
+  Step, // Any statement
+  Next, // Statements of the same function or its callers
+  Finish, // Statements of the callers
+}
+
+// Variable watched by its address, locals only in the frame that was running
+// when the watch was added
+struct Watch {
+  name: String,
+  var: VarInfo,
+  depth: Option<usize>,
+  value: String,
+}
+
+// Runs the compiled program under the control of the commands read from stdin
+pub fn run_debugger(dusty_context: &DustyContext, path: &str, source: &str, options: &VmOptions) {
+  let virtual_machine = match VirtualMachine::new(dusty_context) {
+      Ok(virtual_machine) => virtual_machine,
+      Err(error) => {
+          eprintln!("{}", error.to_string().red());
+          return;
+      }
+  };
+  let mut debugger = Debugger::new(dusty_context, options, virtual_machine, source);
+
+  println!("Debugging {}, help for the commands", path);
+  let stdin = io::stdin();
+  let mut lines = stdin.lock().lines();
+  loop {
+      print!("(dusty) ");
+      io::stdout().flush().unwrap();
+      let Some(Ok(line)) = lines.next() else {
+          break;
+      };
+      let line = line.trim();
+      let (command, argument) = line.split_once(' ').map_or((line, ""), |(command, argument)| (command, argument.trim()));
+      match command {
+          "" => {}
+          "break" | "b" => debugger.add_breakpoint(argument),
+          "delete" => debugger.delete_breakpoint(argument),
+          "run" => match VirtualMachine::new(dusty_context) {
+              Ok(virtual_machine) => {
+                  debugger.virtual_machine = virtual_machine;
+                  debugger.finished = false;
+                  debugger.stopped_at = None;
+                  debugger.resume(Resume::Continue);
+              }
+              Err(error) => eprintln!("{}", error.to_string().red()),
+          },
+          "continue" | "c" => debugger.resume(Resume::Continue),
+          "step" | "s" => debugger.resume(Resume::Step),
+          "next" | "n" => debugger.resume(Resume::Next),
+          "finish" | "out" => debugger.resume(Resume::Finish),
+          "print" | "p" => debugger.print(argument),
+          "backtrace" | "bt" => debugger.backtrace(),
+          "watch" => debugger.watch(argument),
+          "unwatch" => debugger.watches.retain(|watch| watch.name != argument),
+          "help" => println!("{}", HELP),
+          "quit" | "q" => break,
+          _ => println!("Unknown command \"{}\", help for the commands", command),
+      }
+  }
+}
+
+struct Debugger<'a> {
+  dusty_context: &'a DustyContext,
+  options: &'a VmOptions,
+  virtual_machine: VirtualMachine,
+  breakpoints: Vec<(String, usize)>, // Namespace of the file, empty for the program, and line
+  watches: Vec<Watch>,
+  sources: HashMap<String, Vec<String>>, // Lines of the program and the imported files
+  finished: bool,
+  stopped_at: Option<usize>, // Instruction pointer of the last stop
+}
+
+impl<'a> Debugger<'a> {
+  fn new(dusty_context: &'a DustyContext, options: &'a VmOptions, virtual_machine: VirtualMachine, source: &str) -> Self {
+      Debugger {
+          dusty_context,
+          options,
+          virtual_machine,
+          breakpoints: Vec::new(),
+          watches: Vec::new(),
+          sources: HashMap::from([(String::new(), source.lines().map(|line| line.to_string()).collect())]),
+          finished: false,
+          stopped_at: None,
+      }
+  }
+
+  // <line> in the program or <file>:<line> in an imported file
+  fn add_breakpoint(&mut self, argument: &str) {
+      let (module, line) = match argument.rsplit_once(':') {
+          Some((file, line)) => (file.trim_end_matches(".dusty").to_string(), line),
+          None => (String::new(), argument),
+      };
+      let Ok(line) = line.parse::<usize>() else {
+          println!("Usage: break <line> or break <file>:<line>");
+          return;
+      };
+      let has_statement = self.dusty_context.statement_starts.values()
+          .any(|location| location.module == module && location.span.line == line);
+      if !has_statement {
+          println!("No statement starts at {}", describe_line(&module, line));
+          return;
+      }
+      println!("Breakpoint at {}", describe_line(&module, line));
+      self.breakpoints.push((module, line));
+  }
+
+  fn delete_breakpoint(&mut self, argument: &str) {
+      if argument.is_empty() {
+          self.breakpoints.clear();
+          return;
+      }
+      let (module, line) = match argument.rsplit_once(':') {
+          Some((file, line)) => (file.trim_end_matches(".dusty").to_string(), line),
+          None => (String::new(), argument),
+      };
+      self.breakpoints.retain(|breakpoint| *breakpoint != (module.clone(), line.parse().unwrap_or_default()));
+  }
+
+  // Runs quadruples until the VM reaches a statement where it must stop
+  fn resume(&mut self, resume: Resume) {
+      if self.finished {
+          println!("The program is not running, use run to start it again");
+          return;
+      }
+      let depth = self.virtual_machine.call_stack.len();
+      // The statement where it stopped runs before checking again
+      let mut resumed_at = self.stopped_at.take();
+      loop {
+          let pointer = self.virtual_machine.instruction_pointer;
+          if let Some(location) = self.dusty_context.statement_starts.get(&pointer).filter(|_| resumed_at != Some(pointer)) {
+              let depth_now = self.virtual_machine.call_stack.len();
+              let is_breakpoint = self.breakpoints.contains(&(location.module.clone(), location.span.line));
+              let stop = is_breakpoint || match resume {
+                  Resume::Continue => false,
+                  Resume::Step => true,
+                  Resume::Next => depth_now <= depth,
+                  Resume::Finish => depth_now < depth,
+              };
+              if stop {
+                  if is_breakpoint {
+                      print!("Breakpoint, ");
+                  }
+                  self.stop();
+                  return;
+              }
+          }
+          resumed_at = None;
+
+          match self.virtual_machine.step(self.dusty_context, self.options) {
+              Ok(true) => {}
+              Ok(false) => {
+                  println!("Program finished");
+                  self.finished = true;
+                  return;
+              }
+              Err(error) => {
+                  // Shown with the functions being run, like backtrace
+                  eprintln!("{}", self.virtual_machine.failure(self.dusty_context, error).to_string().red());
+                  self.finished = true;
+                  return;
+              }
+          }
+
+          if self.check_watches() {
+              self.stop();
+              return;
+          }
+      }
+  }
+
+  // Reports the watched variables whose value changed, true if there is any
+  fn check_watches(&mut self) -> bool {
+      let depth = self.virtual_machine.call_stack.len();
+      let mut changed = false;
+      for watch in self.watches.iter_mut() {
+          if watch.depth.is_some_and(|watch_depth| watch_depth != depth) {
+              continue;
+          }
+          let value = self.virtual_machine.describe(&watch.var, self.dusty_context);
+          if value != watch.value {
+              println!("Watch {}: {} -> {}", watch.name, watch.value, value);
+              watch.value = value;
+              changed = true;
+          }
+      }
+      changed
+  }
+
+  fn print(&mut self, name: &str) {
+      match self.find_variable(name) {
+          Some(var) => {
+              for var in var.flatten() {
+                  println!("{}: {} = {}", var.name, var.var_type, self.virtual_machine.describe(&var, self.dusty_context));
+              }
+          }
+          None => println!("No variable \"{}\" in the current function", name),
+      }
+  }
+
+  fn watch(&mut self, name: &str) {
+      let Some(var) = self.find_variable(name) else {
+          println!("No variable \"{}\" in the current function", name);
+          return;
+      };
+      let is_local = (11000..21000).contains(&var.location);
+      for var in var.flatten() {
+          let value = self.virtual_machine.describe(&var, self.dusty_context);
+          println!("Watching {} = {}", var.name, value);
+          self.watches.push(Watch {
+              name: name.to_string(),
+              var,
+              depth: is_local.then_some(self.virtual_machine.call_stack.len()),
+              value,
+          });
+      }
+  }
+
+  // Variable visible in the function being run, its locals before the
+  // globals. Between names declared twice (in different blocks) the closest
+  // one before the current line is used
+  fn find_variable(&self, name: &str) -> Option<VarInfo> {
+      let function = self.virtual_machine.call_stack.last().map_or("global", |function| function.as_str());
+      let line = self.current_location(self.virtual_machine.instruction_pointer).map_or(0, |location| location.span.line);
+      let (symbol_name, field) = match self.lookup(name, function, line) {
+          Some(var) => return Some(var),
+          None => name.rsplit_once('.')?,
+      };
+      let var = self.lookup(symbol_name, function, line)?;
+      let field_name = format!("{}.{}", var.name, field);
+      var.fields.into_iter().find(|field_var| field_var.name == field_name)
+  }
+
+  fn lookup(&self, name: &str, function: &str, line: usize) -> Option<VarInfo> {
+      // Inside an imported file its globals are also found without the namespace
+      let module = self.current_location(self.virtual_machine.instruction_pointer).map_or("", |location| location.module.as_str());
+      let qualified = format!("{}.{}", module, name);
+      [function, "global"].iter().find_map(|scope| {
+          self.dusty_context.symbols.iter()
+              .filter(|symbol| (symbol.name == name || symbol.name == qualified) && symbol.function == *scope && symbol.var.is_some())
+              .max_by_key(|symbol| (symbol.span.line <= line, symbol.span.line))
+              .and_then(|symbol| symbol.var.clone())
+      })
+  }
+
+  // Function and line of every frame, the innermost first
+  fn backtrace(&mut self) {
+      let call_stack = &self.virtual_machine.call_stack;
+      let mut pointer = self.virtual_machine.instruction_pointer;
+      for (index, function) in call_stack.iter().rev().chain(std::iter::once(&"global".to_string())).enumerate() {
+          let location = self.current_location(pointer).map_or("unknown line".to_string(), |location| {
+              describe_line(&location.module, location.span.line)
+          });
+          println!("#{} {} at {}", index, function, location);
+          // The caller is at its GOSUB quad, the one before the return address
+          let frame = call_stack.len().saturating_sub(index + 1);
+          if let Some(return_pointer) = self.virtual_machine.memory.jump_stack.get(frame).filter(|_| index < call_stack.len()) {
+              pointer = return_pointer - 1;
+          }
+      }
+  }
+
+  // Statement that starts at the quadruple, or the expression it comes from
+  fn current_location(&self, pointer: usize) -> Option<&SourceLocation> {
+      self.dusty_context.statement_starts.get(&pointer).or(self.dusty_context.quad_locations.get(pointer))
+  }
+
+  // Shows where the program continues, the next resume starts there
+  fn stop(&mut self) {
+      let pointer = self.virtual_machine.instruction_pointer;
+      self.stopped_at = Some(pointer);
+      self.show_location(pointer);
+  }
+
+  fn show_location(&mut self, pointer: usize) {
+      let Some(location) = self.current_location(pointer).cloned() else {
+          return;
+      };
+      let function = self.virtual_machine.call_stack.last().cloned().unwrap_or("global".to_string());
+      println!("stopped at {} in {}", describe_line(&location.module, location.span.line), function);
+
+      // Lines of imported files are read the first time they are shown
+      if !self.sources.contains_key(&location.module) {
+          let source = self.dusty_context.modules.get(&location.module)
+              .and_then(|path| fs::read_to_string(path).ok())
+              .unwrap_or_default();
+          self.sources.insert(location.module.clone(), source.lines().map(|line| line.to_string()).collect());
+      }
+      if let Some(text) = self.sources[&location.module].get(location.span.line - 1) {
+          println!("{}", format!("{:>5} | {}", location.span.line, text).cyan());
+      }
+  }
+}
+
+fn describe_line(module: &str, line: usize) -> String {
+  if module.is_empty() {
+      format!("line {}", line)
+  } else {
+      format!("{}.dusty line {}", module, line)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile;
+
+  const SOURCE: &str = "program p;
+vars x, y: int;
+void bump(n: int) {
+  x = x + n;
+  y = y + 1;
+};
+begin
+{
+  x = 1;
+  y = 0;
+  bump(2);
+  bump(3);
+  print!(x);
+}
+end";
+
+  fn compiled() -> DustyContext {
+      let mut dusty_context = DustyContext::new();
+      compile(SOURCE, &mut dusty_context).unwrap();
+      dusty_context
+  }
+
+  // Line and function where the debugger stopped
+  fn stopped(debugger: &Debugger) -> (usize, String) {
+      let location = debugger.current_location(debugger.stopped_at.unwrap()).unwrap();
+      let function = debugger.virtual_machine.call_stack.last().cloned().unwrap_or("global".to_string());
+      (location.span.line, function)
+  }
+
+  fn value(debugger: &Debugger, name: &str) -> String {
+      debugger.virtual_machine.describe(&debugger.find_variable(name).unwrap(), debugger.dusty_context)
+  }
+
+  #[test]
+  fn breakpoints_stop_at_their_line_with_the_values_so_far() {
+      let dusty_context = compiled();
+      let options = VmOptions::new();
+      let mut debugger = Debugger::new(&dusty_context, &options, VirtualMachine::new(&dusty_context).unwrap(), SOURCE);
+      debugger.add_breakpoint("5");
+      debugger.add_breakpoint("2");
+      assert_eq!(debugger.breakpoints, [(String::new(), 5)]);
+
+      debugger.resume(Resume::Continue);
+      assert_eq!(stopped(&debugger), (5, "bump(int)".to_string()));
+      assert_eq!((value(&debugger, "x"), value(&debugger, "n")), ("3".to_string(), "2".to_string()));
+      debugger.resume(Resume::Continue);
+      assert_eq!(value(&debugger, "x"), "6");
+      debugger.delete_breakpoint("");
+      debugger.resume(Resume::Continue);
+      assert!(debugger.finished);
+  }
+
+  #[test]
+  fn step_enters_calls_and_next_and_finish_dont() {
+      let dusty_context = compiled();
+      let options = VmOptions::new();
+      let mut debugger = Debugger::new(&dusty_context, &options, VirtualMachine::new(&dusty_context).unwrap(), SOURCE);
+      debugger.add_breakpoint("11");
+      debugger.resume(Resume::Continue);
+      debugger.resume(Resume::Step);
+      assert_eq!(stopped(&debugger), (4, "bump(int)".to_string()));
+      debugger.resume(Resume::Finish);
+      assert_eq!(stopped(&debugger), (12, "global".to_string()));
+      debugger.resume(Resume::Next);
+      assert_eq!(stopped(&debugger), (13, "global".to_string()));
+  }
+
+  #[test]
+  fn watches_stop_when_the_value_changes() {
+      let dusty_context = compiled();
+      let options = VmOptions::new();
+      let mut debugger = Debugger::new(&dusty_context, &options, VirtualMachine::new(&dusty_context).unwrap(), SOURCE);
+      debugger.watch("y");
+      debugger.resume(Resume::Continue);
+      assert_eq!(value(&debugger, "y"), "0");
+      debugger.resume(Resume::Continue);
+      assert_eq!((value(&debugger, "y"), debugger.virtual_machine.call_stack.len()), ("1".to_string(), 1));
+  }
+}
@@ -0,0 +1,256 @@
+use std::collections::{HashMap, HashSet};
+
+use super::structs::{
+  dusty_context::DustyContext,
+  quadruple_unit::QuadrupleUnit,
+};
+
+// Definite assignment analysis over the quadruples. Each function (and the
+// main program) is analyzed as a control flow graph where the state of every
+// quad is the set of variable addresses that are assigned on all the paths
+// that reach it. Globals flow between functions through the GOSUB quads.
+pub fn check_definite_assignment(dusty_context: &DustyContext) -> Vec<String> {
+  let regions = get_regions(dusty_context);
+  let globals = get_variables(dusty_context, |location| dusty_context.quad_data.is_global_variable(location));
+  let locals = get_variables(dusty_context, |location| dusty_context.quad_data.is_local_variable(location));
+
+  // Optimistic start: every function is assumed to be called with (and to
+  // leave) every global assigned, then the sets shrink until they are stable
+  let mut entry_globals: HashMap<String, HashSet<u32>> = HashMap::new();
+  let mut exit_globals: HashMap<String, HashSet<u32>> = HashMap::new();
+  for (name, _) in &regions {
+      entry_globals.insert(name.clone(), globals.clone());
+      exit_globals.insert(name.clone(), globals.clone());
+  }
+  // The main program starts with nothing assigned
+  entry_globals.insert("global".to_string(), HashSet::new());
+
+  loop {
+      let mut new_entry_globals: HashMap<String, Option<HashSet<u32>>> = HashMap::new();
+      let mut new_exit_globals = exit_globals.clone();
+
+      for (name, start) in &regions {
+          let states = analyze_region(dusty_context, name, *start, &entry_globals, &exit_globals);
+
+          for (index, state) in &states {
+              let quad = &dusty_context.quadruples[*index];
+              match quad[0].name.as_str() {
+                  "gosub" => {
+                      // Globals that are assigned on every call site
+                      let callee_globals: HashSet<u32> = state.intersection(&globals).cloned().collect();
+                      let entry = new_entry_globals.entry(quad[3].name.clone()).or_insert(None);
+                      *entry = Some(match entry.take() {
+                          Some(current) => current.intersection(&callee_globals).cloned().collect(),
+                          None => callee_globals,
+                      });
+                  }
+                  "endfunc" => {
+                      let assigned: HashSet<u32> = state.intersection(&globals).cloned().collect();
+                      let exit = new_exit_globals.get_mut(name).unwrap();
+                      *exit = exit.intersection(&assigned).cloned().collect();
+                  }
+                  _ => {}
+              }
+          }
+      }
+
+      // Functions that are never called keep the optimistic entry set
+      let mut changed = new_exit_globals != exit_globals;
+      for (name, entry) in new_entry_globals {
+          if let (Some(entry), Some(current)) = (entry, entry_globals.get_mut(&name)) {
+              if entry != *current {
+                  *current = entry;
+                  changed = true;
+              }
+          }
+      }
+      exit_globals = new_exit_globals;
+
+      if !changed {
+          break;
+      }
+  }
+
+  // Report the reads of variables that are not assigned on every path
+  let mut warnings = Vec::new();
+  let mut reported = HashSet::new();
+  for (name, start) in &regions {
+      let variables = if name == "global" { &globals } else { &locals };
+      let states = analyze_region(dusty_context, name, *start, &entry_globals, &exit_globals);
+      let mut indexes: Vec<&usize> = states.keys().collect();
+      indexes.sort();
+
+      for index in indexes {
+          for unit in get_reads(&dusty_context.quadruples[*index]) {
+              if (globals.contains(&unit.memory) || variables.contains(&unit.memory))
+                  && !states[index].contains(&unit.memory)
+                  && reported.insert((name.clone(), unit.name.clone())) {
+                  warnings.push(format!(
+                      "WARNING: Variable \"{}\" may be used before being assigned in \"{}\"{}",
+                      unit.name,
+                      if name == "global" { "main" } else { name },
+                      describe_location(dusty_context, *index)
+                  ));
+              }
+          }
+      }
+  }
+  warnings
+}
+
+// ", line: <line>, col: <col>" of the expression that generated the quad, with
+// the path of the file when it was imported
+fn describe_location(dusty_context: &DustyContext, index: usize) -> String {
+  let Some(location) = dusty_context.quad_locations.get(index) else {
+      return String::new();
+  };
+  match dusty_context.modules.get(&location.module) {
+      Some(path) => format!(", line: {}, col: {} (in file \"{}\")", location.span.line, location.span.col, path.display()),
+      None => format!(", line: {}, col: {}", location.span.line, location.span.col),
+  }
+}
+
+// Entry quad (0-based) of the main program and of every function
+fn get_regions(dusty_context: &DustyContext) -> Vec<(String, usize)> {
+  let mut regions = Vec::new();
+  if let Some(first) = dusty_context.quadruples.front() {
+      regions.push(("global".to_string(), first[3].memory as usize - 1));
+  }
+  let mut functions: Vec<(&String, u32)> = dusty_context.func_dir.iter()
+      .filter(|(name, _)| name.as_str() != "global")
+      .map(|(name, function_info)| (name, function_info.location))
+      .collect();
+  functions.sort();
+  for (name, location) in functions {
+      regions.push((name.clone(), location as usize - 1));
+  }
+  regions
+}
+
+// Addresses of the variables (not temporals nor constants) used by the quads
+fn get_variables(dusty_context: &DustyContext, is_variable: impl Fn(u32) -> bool) -> HashSet<u32> {
+  dusty_context.quadruples.iter()
+      .flat_map(|quad| get_reads(quad).into_iter().map(|unit| unit.memory).chain(get_write(quad)))
+      .filter(|location| is_variable(*location))
+      .collect()
+}
+
+fn get_reads(quad: &[QuadrupleUnit; 4]) -> Vec<&QuadrupleUnit> {
+  match quad[0].name.as_str() {
+      "+" | "-" | "*" | "/" | "<" | ">" | "==" | "!=" => vec![&quad[1], &quad[2]],
+      "=" | "gotof" | "param" | "int" | "float" | "round" | "floor" | "ceil" => vec![&quad[1]],
+      "print" => vec![&quad[3]],
+      _ => vec![],
+  }
+}
+
+fn get_write(quad: &[QuadrupleUnit; 4]) -> Option<u32> {
+  match quad[0].name.as_str() {
+      "+" | "-" | "*" | "/" | "<" | ">" | "==" | "!=" | "="
+      | "int" | "float" | "round" | "floor" | "ceil" | "callb" => Some(quad[3].memory),
+      _ => None,
+  }
+}
+
+fn get_successors(quad: &[QuadrupleUnit; 4], index: usize) -> Vec<usize> {
+  match quad[0].name.as_str() {
+      "goto" => vec![quad[3].memory as usize - 1],
+      "gotof" => vec![index + 1, quad[3].memory as usize - 1],
+      "end" | "endfunc" => vec![],
+      _ => vec![index + 1],
+  }
+}
+
+// State at the entry of every reachable quad of a region
+fn analyze_region(
+  dusty_context: &DustyContext,
+  name: &str,
+  start: usize,
+  entry_globals: &HashMap<String, HashSet<u32>>,
+  exit_globals: &HashMap<String, HashSet<u32>>
+) -> HashMap<usize, HashSet<u32>> {
+  let mut entry_state = entry_globals.get(name).cloned().unwrap_or_default();
+  if name != "global" {
+      // Parameters are assigned by the caller
+      entry_state.extend(dusty_context.func_dir.get(name).unwrap().param_locations.iter());
+  }
+
+  let mut states: HashMap<usize, HashSet<u32>> = HashMap::new();
+  let mut worklist = vec![(start, entry_state)];
+  while let Some((index, incoming)) = worklist.pop() {
+      if index >= dusty_context.quadruples.len() {
+          continue;
+      }
+      let state = match states.get(&index) {
+          Some(current) => {
+              let merged: HashSet<u32> = current.intersection(&incoming).cloned().collect();
+              if merged == *current {
+                  continue;
+              }
+              merged
+          }
+          None => incoming,
+      };
+      states.insert(index, state.clone());
+
+      let quad = &dusty_context.quadruples[index];
+      let mut outgoing = state;
+      if let Some(address) = get_write(quad) {
+          outgoing.insert(address);
+      }
+      // Variables of a block are unassigned every time it is entered
+      if quad[0].name == "reset" {
+          outgoing.retain(|address| !(quad[1].memory..quad[1].memory + quad[2].memory).contains(address));
+      }
+      if quad[0].name == "gosub" {
+          if let Some(assigned) = exit_globals.get(&quad[3].name) {
+              outgoing.extend(assigned.iter());
+          }
+      }
+      for successor in get_successors(quad, index) {
+          worklist.push((successor, outgoing.clone()));
+      }
+  }
+  states
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::compile;
+
+  fn warnings(source: &str) -> Vec<String> {
+      let mut dusty_context = DustyContext::new();
+      compile(source, &mut dusty_context).unwrap();
+      check_definite_assignment(&dusty_context)
+  }
+
+  #[test]
+  fn reads_not_assigned_on_every_path_are_reported() {
+      assert_eq!(warnings(&fs::read_to_string("src/tests/test6.dusty").unwrap()), [
+          "WARNING: Variable \"ratio\" may be used before being assigned in \"main\", line: 23, col: 3",
+          "WARNING: Variable \"total\" may be used before being assigned in \"accumulate(int)\", line: 12, col: 11",
+          "WARNING: Variable \"partial\" may be used before being assigned in \"accumulate(int)\", line: 12, col: 11",
+      ]);
+  }
+
+  #[test]
+  fn reads_assigned_on_both_branches_are_not_reported() {
+      let source = "program p;
+vars x, y: int;
+begin
+{
+  if (1 > 0) {
+    x = 1;
+  } else {
+    x = 2;
+  };
+  y = x;
+  print!(y);
+}
+end";
+      assert!(warnings(source).is_empty());
+  }
+}
@@ -0,0 +1,477 @@
+use pest::Parser;
+
+use super::ast_builder::{build_program, build_module};
+use super::is_program;
+use super::imports::syntax_error_span;
+use super::structs::{
+  ast::*,
+  diagnostic::Diagnostic,
+  parser::{Rule, DustyParser},
+};
+
+// Formats a program or an imported file (a file without the program header).
+// Comments are kept before the code that follows them, or at the end of the
+// line when they were after code, and single blank lines are kept
+pub fn format_source(source: &str) -> Result<String, Diagnostic> {
+  let mut formatter = Formatter::new(find_comments(source));
+  if is_program(source) {
+      match DustyParser::parse(Rule::program, source) {
+          Ok(pairs) => formatter.program(&build_program(pairs.into_iter().next().unwrap())),
+          Err(e) => return Err(Diagnostic::new(format!("ERROR: Syntax error\n{}", e), syntax_error_span(&e))),
+      }
+  } else {
+      match DustyParser::parse(Rule::module, source) {
+          Ok(pairs) => formatter.module(&build_module(pairs.into_iter().next().unwrap())),
+          Err(e) => return Err(Diagnostic::new(format!("ERROR: Syntax error\n{}", e), syntax_error_span(&e))),
+      }
+  }
+  Ok(formatter.finish())
+}
+
+pub struct Comment {
+  pub line: usize,
+  pub col: usize,
+  pub text: String, // Starting with //
+  pub trailing: bool, // After code in the same line
+}
+
+// Comments outside strings, in source order
+pub fn find_comments(source: &str) -> Vec<Comment> {
+  let mut comments = Vec::new();
+  for (index, line) in source.lines().enumerate() {
+      let mut in_string = false;
+      let mut escaped = false;
+      let mut previous = ' ';
+      for (col, char) in line.char_indices() {
+          match char {
+              _ if escaped => escaped = false,
+              '\\' if in_string => escaped = true,
+              '"' => in_string = !in_string,
+              '/' if !in_string && previous == '/' => {
+                  let start = col - 1;
+                  comments.push(Comment {
+                      line: index + 1,
+                      col: line[..start].chars().count() + 1,
+                      text: line[start..].trim_end().to_string(),
+                      trailing: !line[..start].trim().is_empty(),
+                  });
+                  break;
+              }
+              _ => {}
+          }
+          previous = char;
+      }
+  }
+  comments
+}
+
+struct Formatter {
+  comments: Vec<Comment>,
+  next_comment: usize,
+  lines: Vec<String>,
+  indent: usize,
+  last_line: usize, // Source line where the last code or comment written ends
+  opened: bool, // The last line opens a block, blank lines are not kept after it
+}
+
+impl Formatter {
+  fn new(comments: Vec<Comment>) -> Self {
+      Formatter {
+          comments,
+          next_comment: 0,
+          lines: Vec::new(),
+          indent: 0,
+          last_line: 0,
+          opened: false,
+      }
+  }
+
+  fn finish(mut self) -> String {
+      self.flush_comments(usize::MAX);
+      let mut formatted = self.lines.join("\n");
+      formatted.push('\n');
+      formatted
+  }
+
+  // Writes a line of code that starts and ends at the given source lines
+  fn line(&mut self, text: &str, line: usize, end_line: usize) {
+      self.flush_comments(line);
+      self.keep_blank_line(line);
+      self.lines.push(format!("{}{}", "  ".repeat(self.indent), text));
+      self.last_line = self.last_line.max(end_line);
+      self.opened = false;
+  }
+
+  fn open(&mut self, text: &str, line: usize) {
+      self.line(text, line, line);
+      self.indent += 1;
+      self.opened = true;
+  }
+
+  // Comments inside the block are written before it's closed
+  fn close(&mut self, text: &str, line: usize) {
+      self.flush_comments(line);
+      self.indent -= 1;
+      self.lines.push(format!("{}{}", "  ".repeat(self.indent), text));
+      self.last_line = self.last_line.max(line);
+      self.opened = false;
+  }
+
+  // Writes the comments that are before the source line
+  fn flush_comments(&mut self, line: usize) {
+      while self.next_comment < self.comments.len() && self.comments[self.next_comment].line < line {
+          let comment = &self.comments[self.next_comment];
+          let (comment_line, text) = (comment.line, comment.text.clone());
+          let last = self.lines.iter_mut().rev().find(|last| !last.is_empty());
+          match last {
+              Some(last) if comment.trailing && comment_line == self.last_line => {
+                  last.push_str("  ");
+                  last.push_str(&text);
+              }
+              _ => {
+                  self.keep_blank_line(comment_line);
+                  self.lines.push(format!("{}{}", "  ".repeat(self.indent), text));
+                  self.opened = false;
+              }
+          }
+          self.last_line = self.last_line.max(comment_line);
+          self.next_comment += 1;
+      }
+  }
+
+  fn keep_blank_line(&mut self, line: usize) {
+      if !self.opened && self.last_line > 0 && line > self.last_line + 1 {
+          self.blank_line();
+      }
+  }
+
+  fn blank_line(&mut self) {
+      if self.lines.last().is_some_and(|last| !last.is_empty()) {
+          self.lines.push(String::new());
+      }
+  }
+
+  fn program(&mut self, program: &Program) {
+      self.line(&format!("program {};", program.name.name), program.name.span.line, program.name.span.end_line);
+      self.module(&program.module);
+
+      // begin { <body> } end
+      self.blank_line();
+      self.line("begin", program.begin.line, program.begin.line);
+      self.open("{", program.body.span.line);
+      self.block_contents(&program.body);
+      self.close("}", program.body.span.end_line);
+      self.line("end", program.end.line, program.end.line);
+  }
+
+  // Sections are separated by a blank line
+  fn module(&mut self, module: &Module) {
+      if !module.imports.is_empty() {
+          self.blank_line();
+      }
+      for import in module.imports.iter() {
+          self.line(&format!("import \"{}\";", import.path), import.span.line, import.span.end_line);
+      }
+
+      if !module.types.is_empty() {
+          self.blank_line();
+      }
+      for type_decl in module.types.iter() {
+          match type_decl {
+              TypeDecl::Record(record) => {
+                  let fields: Vec<String> = record.fields.iter()
+                      .map(|(field, field_type)| format!("{}: {}", field.name, field_type.name))
+                      .collect();
+                  self.line(&format!("type {} = record {{ {} }};", record.name.name, fields.join(", ")), record.span.line, record.span.end_line);
+              }
+              TypeDecl::Enum(enum_decl) => {
+                  let variants: Vec<&str> = enum_decl.variants.iter().map(|variant| variant.name.as_str()).collect();
+                  self.line(&format!("enum {} {{ {} }};", enum_decl.name.name, variants.join(", ")), enum_decl.span.line, enum_decl.span.end_line);
+              }
+          }
+      }
+
+      if !module.consts.is_empty() {
+          self.blank_line();
+      }
+      self.consts(&module.consts);
+
+      if !module.vars.is_empty() {
+          self.blank_line();
+      }
+      self.global_vars(module);
+
+      for function in module.funcs.iter() {
+          self.blank_line();
+          self.function(function);
+      }
+  }
+
+  fn consts(&mut self, consts: &[ConstDecl]) {
+      for const_decl in consts {
+          self.line(&format!("const {}: {} = {};",
+              const_decl.name.name,
+              const_decl.const_type.name,
+              expression(&const_decl.value)
+          ), const_decl.span.line, const_decl.span.end_line);
+      }
+  }
+
+  // Locals go in the line of vars
+  fn vars(&mut self, vars: &[VarDecl]) {
+      if vars.is_empty() {
+          return;
+      }
+      let groups: Vec<String> = vars.iter().map(var_group).collect();
+      let end_line = vars.last().unwrap().span.end_line;
+      self.line(&format!("vars {};", groups.join(", ")), vars[0].span.line, end_line);
+  }
+
+  // Globals go one group per line after vars, comments between them stay in the section
+  fn global_vars(&mut self, module: &Module) {
+      if module.vars.is_empty() {
+          return;
+      }
+      self.open("vars", module.vars_keyword.line);
+      for var_decl in module.vars.iter() {
+          self.line(&format!("{};", var_group(var_decl)), var_decl.span.line, var_decl.span.end_line);
+      }
+      self.indent -= 1;
+  }
+
+  fn function(&mut self, function: &FuncDecl) {
+      let params: Vec<String> = function.params.iter()
+          .map(|(param, param_type)| format!("{}: {}", param.name, param_type.name))
+          .collect();
+      self.open(&format!("void {}({}) {{", function.name.name, params.join(", ")), function.span.line);
+      self.consts(&function.consts);
+      self.vars(&function.vars);
+      for stmt in function.body.iter() {
+          self.statement(stmt);
+      }
+      self.close("};", function.span.end_line);
+  }
+
+  fn block_contents(&mut self, block: &Block) {
+      self.vars(&block.vars);
+      for stmt in block.stmts.iter() {
+          self.statement(stmt);
+      }
+  }
+
+  fn statement(&mut self, stmt: &Stmt) {
+      let span = stmt.span;
+      if let Some(text) = simple_statement(stmt) {
+          self.line(&text, span.line, span.end_line);
+          return;
+      }
+      match &stmt.kind {
+          StmtKind::If { condition, then_block, else_block } => {
+              self.open(&format!("if ({}) {{", expression(condition)), span.line);
+              self.block_contents(then_block);
+              if let Some(else_block) = else_block {
+                  self.close("} else {", then_block.span.end_line);
+                  self.indent += 1;
+                  self.opened = true;
+                  self.block_contents(else_block);
+              }
+              self.close("};", span.end_line);
+          }
+          StmtKind::While { condition, body } => {
+              self.open(&format!("while ({}) do {{", expression(condition)), span.line);
+              self.block_contents(body);
+              self.close("};", span.end_line);
+          }
+          StmtKind::Match { scrutinee, arms, default } => {
+              self.open(&format!("match ({}) {{", expression(scrutinee)), span.line);
+              for (index, arm) in arms.iter().enumerate() {
+                  // Arms are separated by commas
+                  let is_last = index == arms.len() - 1 && default.is_none();
+                  let span = Span { end_line: arm.body.span.end_line, end_col: arm.body.span.end_col, ..arm.span };
+                  self.match_arm(&expression(&arm.pattern), &arm.body, span, if is_last { "" } else { "," });
+              }
+              if let Some(default) = default {
+                  self.match_arm("_", default, default.span, "");
+              }
+              self.close("};", span.end_line);
+          }
+          _ => {}
+      }
+  }
+
+  // An arm with a single assignment, call or print goes in one line, unless
+  // it has comments inside
+  fn match_arm(&mut self, pattern: &str, body: &Block, span: Span, separator: &str) {
+      let has_comments = self.comments[self.next_comment..].iter()
+          .any(|comment| (comment.line, comment.col) > span.start() && (comment.line, comment.col) < (span.end_line, span.end_col));
+      let single = match body.stmts.as_slice() {
+          [stmt] if body.vars.is_empty() && !has_comments => simple_statement(stmt),
+          _ => None,
+      };
+
+      match single {
+          Some(text) => self.line(&format!("{} => {{ {} }}{}", pattern, text, separator), span.line, span.end_line),
+          None => {
+              self.open(&format!("{} => {{", pattern), span.line);
+              self.block_contents(body);
+              self.close(&format!("}}{}", separator), span.end_line);
+          }
+      }
+  }
+}
+
+// Text of the statements that take one line
+fn simple_statement(stmt: &Stmt) -> Option<String> {
+  match &stmt.kind {
+      StmtKind::Assign { target, value } => Some(format!("{} = {};", target.name, expression(value))),
+      StmtKind::Call(call_stmt) => Some(format!("{};", call(call_stmt))),
+      StmtKind::Print(elements) => {
+          let elements: Vec<String> = elements.iter()
+              .map(|element| match element {
+                  PrintElement::Expr(expr) => expression(expr),
+                  PrintElement::Str(string, _) => format!("\"{}\"", string),
+              })
+              .collect();
+          Some(format!("print!({});", elements.join(", ")))
+      }
+      _ => None,
+  }
+}
+
+// <id_list> : <type> (= <expression>)?
+fn var_group(var_decl: &VarDecl) -> String {
+  let names: Vec<&str> = var_decl.names.iter().map(|name| name.name.as_str()).collect();
+  match &var_decl.init {
+      Some(init) => format!("{}: {} = {}", names.join(", "), var_decl.var_type.name, expression(init)),
+      None => format!("{}: {}", names.join(", "), var_decl.var_type.name),
+  }
+}
+
+fn call(call: &Call) -> String {
+  let args: Vec<String> = call.args.iter().map(expression).collect();
+  format!("{}({})", call.name.name, args.join(", "))
+}
+
+// Operators are surrounded by spaces, parentheses are kept as written
+fn expression(expr: &Expr) -> String {
+  match &expr.kind {
+      ExprKind::Int(value) | ExprKind::Float(value) => value.clone(),
+      ExprKind::Name(id) => id.name.clone(),
+      ExprKind::Group(operand) => format!("({})", expression(operand)),
+      ExprKind::Unary { sign, operand } => format!("{}{}", sign, expression(operand)),
+      ExprKind::Binary { operator, left, right } => format!("{} {} {}", expression(left), operator, expression(right)),
+      ExprKind::Conversion { conversion, operand } => format!("{}({})", conversion, expression(operand)),
+      ExprKind::Call(call_expr) => call(call_expr),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+
+  // Tree of a program or an imported file without the spans, which move when
+  // the layout changes
+  fn tree(source: &str) -> String {
+      let tree = if is_program(source) {
+          format!("{:?}", build_program(DustyParser::parse(Rule::program, source).unwrap().next().unwrap()))
+      } else {
+          format!("{:?}", build_module(DustyParser::parse(Rule::module, source).unwrap().next().unwrap()))
+      };
+      let mut without_spans = String::new();
+      let mut rest = tree.as_str();
+      while let Some(start) = rest.find("Span {") {
+          without_spans.push_str(&rest[..start]);
+          rest = &rest[start + rest[start..].find('}').unwrap() + 1..];
+      }
+      without_spans.push_str(rest);
+      without_spans
+  }
+
+  // Layouts the samples don't have: comments inside vars, escapes and blank lines
+  const CASES: [&str; 3] = [
+      "program p;
+vars // globals
+  // counters
+  x, y: int;
+
+  // ratio
+  r: float;
+begin
+{
+  x = 1;
+}
+end",
+      r#"program p;
+begin
+{
+  print!("say \"hi\"", "\\", "tab\tend \u0041");
+}
+end"#,
+      "program p;
+void f(a: int) {
+
+
+  print!(a);
+  // before
+
+
+  print!(a);
+
+};
+
+begin
+{
+
+  f(1);
+}
+end",
+  ];
+
+  // Formatting again gives the same text and the tree doesn't change
+  fn check_stable(name: &str, source: &str) {
+      let formatted = format_source(source).unwrap();
+      assert_eq!(format_source(&formatted).unwrap(), formatted, "{} is not stable", name);
+      assert_eq!(tree(&formatted), tree(source), "{} changed its tree", name);
+  }
+
+  #[test]
+  fn samples_are_stable_and_keep_the_tree() {
+      for entry in fs::read_dir("src/tests").unwrap() {
+          let path = entry.unwrap().path();
+          if path.extension().is_none_or(|extension| extension != "dusty") {
+              continue;
+          }
+          check_stable(&path.display().to_string(), &fs::read_to_string(&path).unwrap());
+      }
+      for (index, source) in CASES.iter().enumerate() {
+          check_stable(&format!("case {}", index), source);
+      }
+  }
+
+  #[test]
+  fn comments_stay_inside_vars() {
+      let formatted = format_source(CASES[0]).unwrap();
+      assert!(formatted.starts_with("program p;\n\nvars  // globals\n  // counters\n  x, y: int;\n\n  // ratio\n  r: float;\n\nbegin"), "{}", formatted);
+  }
+
+  #[test]
+  fn blank_lines_are_single_and_not_after_an_opening() {
+      let formatted = format_source(CASES[2]).unwrap();
+      assert!(formatted.contains("void f(a: int) {\n  print!(a);\n  // before\n\n  print!(a);\n};\n\nbegin\n{\n  f(1);\n}"), "{}", formatted);
+  }
+
+  #[test]
+  fn strings_keep_their_escapes() {
+      let source = r#"program p;
+
+begin
+{
+  print!("say \"hi\"", "a\\");
+}
+end
+"#;
+      assert_eq!(format_source(source).unwrap(), source);
+  }
+}
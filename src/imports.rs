@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::PathBuf;
+
+use pest::Parser;
+
+use super::ast_builder::build_module;
+use super::structs::{
+  ast::{Module, Span},
+  diagnostic::Diagnostic,
+  dusty_context::DustyContext,
+  parser::{Rule, DustyParser},
+};
+
+// Loads the files imported by the module into its syntax tree. Their functions,
+// globals, constants and types are declared under the name of the file
+// (geometry.dusty -> geometry)
+pub fn load_imports(module: &mut Module, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  for import in module.imports.iter_mut() {
+      let (line, col) = import.span.start();
+
+      // Paths are relative to the file that imports them
+      let directory = match dusty_context.import_stack.last() {
+          Some(file) => file.parent().unwrap().to_path_buf(),
+          None => PathBuf::from("."),
+      };
+      let path = match fs::canonicalize(directory.join(&import.path)) {
+          Ok(path) => path,
+          Err(_) => return Err(Diagnostic::new(
+              format!("ERROR: Cannot read imported file \"{}\", line: {}, col: {}", import.path, line, col),
+              import.span
+          )),
+      };
+
+      if dusty_context.import_stack.contains(&path) {
+          let cycle: Vec<String> = dusty_context.import_stack.iter()
+              .skip_while(|file| **file != path)
+              .chain(std::iter::once(&path))
+              .map(|file| file.file_name().unwrap().to_string_lossy().to_string())
+              .collect();
+          return Err(Diagnostic::new(format!("ERROR: Import cycle detected: {}", cycle.join(" -> ")), import.span));
+      }
+
+      let namespace = path.file_stem().unwrap().to_string_lossy().to_string();
+      match dusty_context.modules.get(&namespace) {
+          // Files imported from several places are compiled once
+          Some(imported) if *imported == path => continue,
+          Some(imported) => return Err(Diagnostic::new(
+              format!("ERROR: Module \"{}\" is already imported from \"{}\", line: {}, col: {}",
+                  namespace,
+                  imported.display(),
+                  line,
+                  col
+              ),
+              import.span
+          )),
+          None => {}
+      }
+
+      let Ok(source) = fs::read_to_string(&path) else {
+          return Err(Diagnostic::new(
+              format!("ERROR: Cannot read imported file \"{}\", line: {}, col: {}", import.path, line, col),
+              import.span
+          ));
+      };
+      let pairs = match DustyParser::parse(Rule::module, &source) {
+          Ok(pairs) => pairs,
+          Err(e) => {
+              let span = syntax_error_span(&e);
+              return Err(Diagnostic::new(
+                  format!("ERROR: Syntax error in imported file\n{}", e.with_path(&path.display().to_string())),
+                  span
+              ).in_file(&path));
+          }
+      };
+
+      let mut imported = build_module(pairs.into_iter().next().unwrap());
+      imported.namespace = namespace.clone();
+      imported.path = Some(path.clone());
+      dusty_context.modules.insert(namespace, path.clone());
+
+      // Errors of the imported file are reported with its path
+      dusty_context.import_stack.push(path.clone());
+      let result = load_imports(&mut imported, dusty_context).map_err(|e| e.in_file(&path));
+      dusty_context.import_stack.pop();
+      result?;
+
+      import.module = Some(imported);
+  }
+  Ok(())
+}
+
+// Where pest found the syntax error
+pub fn syntax_error_span(e: &pest::error::Error<Rule>) -> Span {
+  match e.line_col {
+      pest::error::LineColLocation::Pos((line, col)) => Span { line, col, end_line: line, end_col: col },
+      pest::error::LineColLocation::Span((line, col), (end_line, end_col)) => Span { line, col, end_line, end_col },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile_file;
+
+  // Writes the files in a directory of their own and compiles main.dusty
+  fn compile_files(name: &str, files: &[(&str, &str)]) -> (Result<DustyContext, Diagnostic>, PathBuf) {
+      let directory = std::env::temp_dir().join(format!("dusty_imports_{}", name));
+      fs::create_dir_all(&directory).unwrap();
+      for (file, source) in files {
+          fs::write(directory.join(file), source).unwrap();
+      }
+      let directory = fs::canonicalize(directory).unwrap();
+      let mut dusty_context = DustyContext::new();
+      let result = compile_file(&directory.join("main.dusty").display().to_string(), &mut dusty_context);
+      (result.map(|_| dusty_context), directory)
+  }
+
+  #[test]
+  fn imported_declarations_are_used_with_their_namespace() {
+      let mut dusty_context = DustyContext::new();
+      compile_file("src/tests/test12.dusty", &mut dusty_context).unwrap();
+      for name in ["geometry.PI", "geometry.calls", "units.factor", "geometry.Shape.Square"] {
+          assert!(dusty_context.symbols.iter().any(|symbol| symbol.name == name), "{} is not declared", name);
+      }
+      assert!(dusty_context.type_dir.contains_key("geometry.Point"));
+      assert!(dusty_context.func_dir.keys().any(|signature| signature.starts_with("geometry.area(")));
+  }
+
+  #[test]
+  fn files_imported_twice_are_compiled_once() {
+      let (result, _) = compile_files("twice", &[
+          ("main.dusty", "program p;\nimport \"a.dusty\";\nimport \"b.dusty\";\nbegin { b.show(); } end\n"),
+          ("a.dusty", "import \"b.dusty\";\n"),
+          ("b.dusty", "vars x: int;\nvoid show() { print!(x); };\n"),
+      ]);
+      let dusty_context = result.unwrap();
+      assert_eq!(dusty_context.symbols.iter().filter(|symbol| symbol.name == "b.x").count(), 1);
+  }
+
+  #[test]
+  fn import_cycles_and_errors_show_the_file() {
+      let (result, directory) = compile_files("cycle", &[
+          ("main.dusty", "program p;\nimport \"a.dusty\";\nbegin { } end\n"),
+          ("a.dusty", "import \"b.dusty\";\n"),
+          ("b.dusty", "import \"a.dusty\";\n"),
+      ]);
+      assert_eq!(
+          result.err().unwrap().to_string(),
+          format!("ERROR: Import cycle detected: a.dusty -> b.dusty -> a.dusty (in file \"{}\")", directory.join("b.dusty").display())
+      );
+
+      let (result, directory) = compile_files("error", &[
+          ("main.dusty", "program p;\nimport \"a.dusty\";\nbegin { } end\n"),
+          ("a.dusty", "void f() { y = 1; };\n"),
+      ]);
+      assert_eq!(
+          result.err().unwrap().to_string(),
+          format!("ERROR: ID \"y\" not found in current context \"a.f()\", line: 1, col: 12 (in file \"{}\")", directory.join("a.dusty").display())
+      );
+
+      let (result, _) = compile_files("missing", &[("main.dusty", "program p;\nimport \"nowhere.dusty\";\nbegin { } end\n")]);
+      assert_eq!(
+          result.err().unwrap().to_string(),
+          "ERROR: Cannot read imported file \"nowhere.dusty\", line: 2, col: 1"
+      );
+  }
+}
@@ -0,0 +1,98 @@
+pub mod structs;
+pub mod virtual_machine;
+pub mod ast_builder;
+pub mod name_resolution;
+pub mod type_check;
+pub mod quadruples;
+pub mod constant_evaluation;
+pub mod definite_assignment;
+pub mod builtins;
+pub mod imports;
+pub mod formatter;
+pub mod linter;
+pub mod lsp;
+pub mod repl;
+pub mod debugger;
+pub mod profiler;
+
+use std::fs;
+
+use pest::Parser;
+
+use ast_builder::{build_program, build_repl_input};
+use imports::{load_imports, syntax_error_span};
+use name_resolution::{resolve_program, resolve_input};
+use type_check::{check_program, check_input};
+use quadruples::{generate_quadruples, generate_input_quadruples};
+use structs::{
+    ast::{Program, Span},
+    diagnostic::Diagnostic,
+    dusty_context::DustyContext,
+    parser::{Rule, DustyParser},
+};
+
+// Programs start with the program keyword, imported files don't have it
+pub fn is_program(source: &str) -> bool {
+    source.lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("//"))
+        .is_some_and(|line| line.starts_with("program"))
+}
+
+// Builds the syntax tree of a program, without resolving names nor imports
+pub fn parse(source: &str) -> Result<Program, Diagnostic> {
+    match DustyParser::parse(Rule::program, source) {
+        Ok(pairs) => Ok(build_program(pairs.into_iter().next().unwrap())),
+        Err(e) => Err(Diagnostic::new(format!("ERROR: Syntax error\n{}", e), syntax_error_span(&e))),
+    }
+}
+
+// Compiles the program and generates its quadruples in the context. Host
+// functions must be registered in the context before calling this
+pub fn compile(source: &str, dusty_context: &mut DustyContext) -> Result<Program, Diagnostic> {
+    compile_program(parse(source)?, dusty_context)
+}
+
+// Compiles one input of the REPL into the context, its quadruples are appended
+// after the ones of the previous inputs
+pub fn compile_input(source: &str, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+    let (mut module, mut body) = match DustyParser::parse(Rule::repl_input, source) {
+        Ok(pairs) => build_repl_input(pairs.into_iter().next().unwrap()),
+        Err(e) => return Err(Diagnostic::new(format!("ERROR: Syntax error\n{}", e), syntax_error_span(&e))),
+    };
+    load_imports(&mut module, dusty_context)?;
+    resolve_input(&mut module, &mut body, dusty_context)?;
+    check_input(&mut module, &mut body, dusty_context)?;
+    generate_input_quadruples(&module, &body, dusty_context)
+}
+
+fn compile_program(mut program: Program, dusty_context: &mut DustyContext) -> Result<Program, Diagnostic> {
+    load_imports(&mut program.module, dusty_context)?;
+    resolve_program(&mut program, dusty_context)?;
+    check_program(&mut program, dusty_context)?;
+    generate_quadruples(&program, dusty_context)?;
+    Ok(program)
+}
+
+// Compiles a file, imports are relative to its directory
+pub fn compile_file(path: &str, dusty_context: &mut DustyContext) -> Result<Program, Diagnostic> {
+    let Ok(source) = fs::read_to_string(path) else {
+        return Err(Diagnostic::new(format!("ERROR: Cannot read file \"{}\"", path), Span::default()));
+    };
+    compile_source(path, &source, dusty_context)
+}
+
+// Compiles the source of a file that was already read
+pub fn compile_source(path: &str, source: &str, dusty_context: &mut DustyContext) -> Result<Program, Diagnostic> {
+    let Ok(canonical) = fs::canonicalize(path) else {
+        return Err(Diagnostic::new(format!("ERROR: Cannot read file \"{}\"", path), Span::default()));
+    };
+    dusty_context.import_stack.push(canonical);
+    // Syntax errors show the path of the file
+    let result = match DustyParser::parse(Rule::program, source) {
+        Ok(pairs) => compile_program(build_program(pairs.into_iter().next().unwrap()), dusty_context),
+        Err(e) => Err(Diagnostic::new(format!("ERROR: Syntax error\n{}", e.clone().with_path(path)), syntax_error_span(&e))),
+    };
+    dusty_context.import_stack.pop();
+    result
+}
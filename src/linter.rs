@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+
+use super::constant_evaluation::evaluate_constant;
+use super::formatter::find_comments;
+use super::structs::{
+  ast::*,
+  dusty_context::DustyContext,
+  lint::Lint,
+};
+
+// Ids of the warnings, used in the comments that silence them:
+// "// lint: allow(<id>, ...)" at the end of the line or in the line before it,
+// and "// lint: allow-file(<id>, ...)" anywhere in the file
+pub const LINTS: [&str; 6] = [
+  "unused-variable",
+  "unused-parameter",
+  "unused-function",
+  "unreachable-code",
+  "constant-condition",
+  "self-assignment",
+];
+
+// Warnings of a compiled program, imported files are only used to find the
+// uses of the declarations of the program
+pub fn lint_program(program: &Program, source: &str, dusty_context: &DustyContext) -> Vec<Lint> {
+  let mut linter = Linter {
+      dusty_context,
+      references: HashMap::new(),
+      calls: HashSet::new(),
+      current_func: "global".to_string(),
+      declarations: Vec::new(),
+      lints: Vec::new(),
+  };
+  for import in program.module.imports.iter() {
+      if let Some(imported) = &import.module {
+          linter.module(imported, false);
+      }
+  }
+  linter.module(&program.module, true);
+  linter.block(&program.body, true);
+
+  // Declarations that are never used
+  for (id, kind) in std::mem::take(&mut linter.declarations) {
+      if !linter.references.contains_key(&id.symbol.unwrap()) {
+          let lint_id = if kind == "parameter" { "unused-parameter" } else { "unused-variable" };
+          linter.lints.push(Lint::new(lint_id, format!("{} \"{}\" is never used", capitalize(kind), id.name), id.span));
+      }
+  }
+  for function in program.module.funcs.iter() {
+      if !linter.calls.contains(&function.signature) {
+          linter.lints.push(Lint::new("unused-function", format!("Function \"{}\" is never called", function.signature), function.name.span));
+      }
+  }
+
+  let mut lints = silence(linter.lints, source);
+  lints.sort_by_key(|lint| lint.span.start());
+  lints
+}
+
+// Drops the warnings silenced by comments
+fn silence(lints: Vec<Lint>, source: &str) -> Vec<Lint> {
+  let mut file_allowed = HashSet::new();
+  let mut line_allowed = HashSet::new();
+  for comment in find_comments(source) {
+      let text = comment.text.trim_start_matches('/').trim();
+      if let Some(ids) = text.strip_prefix("lint: allow-file(").and_then(|ids| ids.strip_suffix(')')) {
+          file_allowed.extend(ids.split(',').map(|id| id.trim().to_string()));
+      } else if let Some(ids) = text.strip_prefix("lint: allow(").and_then(|ids| ids.strip_suffix(')')) {
+          // A comment in its own line applies to the next line
+          let line = if comment.trailing { comment.line } else { comment.line + 1 };
+          line_allowed.extend(ids.split(',').map(|id| (line, id.trim().to_string())));
+      }
+  }
+
+  lints.into_iter()
+      .filter(|lint| !file_allowed.contains(lint.id) && !line_allowed.contains(&(lint.span.line, lint.id.to_string())))
+      .collect()
+}
+
+fn capitalize(text: &str) -> String {
+  let mut chars = text.chars();
+  match chars.next() {
+      Some(first) => first.to_uppercase().chain(chars).collect(),
+      None => String::new(),
+  }
+}
+
+struct Linter<'a> {
+  dusty_context: &'a DustyContext,
+  references: HashMap<usize, usize>, // Reads of every symbol
+  calls: HashSet<String>, // Called signatures, recursive calls are not counted
+  current_func: String,
+  declarations: Vec<(Ident, &'static str)>, // Variables and parameters of the program
+  lints: Vec<Lint>,
+}
+
+impl Linter<'_> {
+  // Only the declarations and statements of the program are reported
+  fn module(&mut self, module: &Module, report: bool) {
+      for const_decl in module.consts.iter() {
+          self.expression(&const_decl.value);
+      }
+      self.vars(&module.vars, report);
+      for function in module.funcs.iter() {
+          self.current_func = function.signature.clone();
+          if report {
+              self.declarations.extend(function.params.iter().map(|(param, _)| (param.clone(), "parameter")));
+          }
+          for const_decl in function.consts.iter() {
+              self.expression(&const_decl.value);
+          }
+          self.vars(&function.vars, report);
+          self.statements(&function.body, report);
+          self.current_func = "global".to_string();
+      }
+  }
+
+  fn vars(&mut self, vars: &[VarDecl], report: bool) {
+      for var_decl in vars {
+          if report {
+              self.declarations.extend(var_decl.names.iter().map(|name| (name.clone(), "variable")));
+          }
+          if let Some(init) = &var_decl.init {
+              self.expression(init);
+          }
+      }
+  }
+
+  fn block(&mut self, block: &Block, report: bool) {
+      self.vars(&block.vars, report);
+      self.statements(&block.stmts, report);
+  }
+
+  fn statements(&mut self, stmts: &[Stmt], report: bool) {
+      let mut unreachable = false;
+      for stmt in stmts {
+          if unreachable && report {
+              self.lints.push(Lint::new("unreachable-code", "Unreachable code after an infinite loop".to_string(), stmt.span));
+              unreachable = false;
+          }
+          self.statement(stmt, report);
+
+          // Nothing leaves a loop whose condition is always true
+          if let StmtKind::While { condition, .. } = &stmt.kind {
+              unreachable = self.constant_condition(condition) == Some(true);
+          }
+      }
+  }
+
+  fn statement(&mut self, stmt: &Stmt, report: bool) {
+      match &stmt.kind {
+          // Assigning a variable is not a use, only reading it in an expression
+          StmtKind::Assign { target, value } => {
+              self.expression(value);
+              if let ExprKind::Name(id) = &value.kind {
+                  if report && id.symbol == target.symbol && id.field == target.field {
+                      self.lints.push(Lint::new("self-assignment", format!("\"{}\" is assigned to itself", target.name), stmt.span));
+                  }
+              }
+          }
+          StmtKind::If { condition, then_block, else_block } => {
+              self.condition(condition, report);
+              self.block(then_block, report);
+              if let Some(else_block) = else_block {
+                  self.block(else_block, report);
+              }
+          }
+          StmtKind::While { condition, body } => {
+              self.condition(condition, report);
+              self.block(body, report);
+          }
+          StmtKind::Match { scrutinee, arms, default } => {
+              self.expression(scrutinee);
+              for arm in arms.iter() {
+                  self.expression(&arm.pattern);
+                  self.block(&arm.body, report);
+              }
+              if let Some(default) = default {
+                  self.block(default, report);
+              }
+          }
+          StmtKind::Call(call) => self.call(call),
+          StmtKind::Print(elements) => {
+              for element in elements.iter() {
+                  if let PrintElement::Expr(expr) = element {
+                      self.expression(expr);
+                  }
+              }
+          }
+      }
+  }
+
+  fn condition(&mut self, condition: &Expr, report: bool) {
+      self.expression(condition);
+      if let Some(value) = self.constant_condition(condition).filter(|_| report) {
+          self.lints.push(Lint::new("constant-condition", format!("Condition is always {}", value), condition.span));
+      }
+  }
+
+  // Value of a condition made of literals and constants
+  fn constant_condition(&self, condition: &Expr) -> Option<bool> {
+      evaluate_constant(condition, self.dusty_context).ok().map(|(value, _)| value != "0")
+  }
+
+  fn call(&mut self, call: &Call) {
+      if !call.is_builtin && call.signature != self.current_func {
+          self.calls.insert(call.signature.clone());
+      }
+      for arg in call.args.iter() {
+          self.expression(arg);
+      }
+  }
+
+  fn expression(&mut self, expr: &Expr) {
+      match &expr.kind {
+          ExprKind::Int(_) | ExprKind::Float(_) => {}
+          ExprKind::Name(id) => self.reference(id),
+          ExprKind::Group(operand) | ExprKind::Unary { operand, .. } | ExprKind::Conversion { operand, .. } => {
+              self.expression(operand);
+          }
+          ExprKind::Binary { left, right, .. } => {
+              self.expression(left);
+              self.expression(right);
+          }
+          ExprKind::Call(call) => self.call(call),
+      }
+  }
+
+  fn reference(&mut self, id: &Ident) {
+      *self.references.entry(id.symbol.unwrap()).or_insert(0) += 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile;
+
+  fn lint(source: &str) -> Vec<String> {
+      let mut dusty_context = DustyContext::new();
+      let program = compile(source, &mut dusty_context).unwrap();
+      lint_program(&program, source, &dusty_context).iter()
+          .map(|lint| format!("{} {}:{} {}", lint.id, lint.span.line, lint.span.col, lint.message))
+          .collect()
+  }
+
+  #[test]
+  fn variables_that_are_only_assigned_are_unused() {
+      let source = "program p;
+vars written, read: int;
+begin
+{
+  written = 1;
+  read = 2;
+  written = read;
+}
+end";
+      assert_eq!(lint(source), ["unused-variable 2:6 Variable \"written\" is never used"]);
+  }
+
+  const WARNINGS: &str = "program p;
+vars x: int;
+void unused(n: int) { unused(1); };
+begin
+{
+  x = 1;
+  x = x;
+  if (10 < 1) { print!(x); };
+  while (1 > 0) do { print!(x); };
+  print!(x);
+}
+end";
+
+  #[test]
+  fn every_warning_is_reported_with_its_line_and_col() {
+      assert_eq!(lint(WARNINGS), [
+          "unused-function 3:6 Function \"unused(int)\" is never called",
+          "unused-parameter 3:13 Parameter \"n\" is never used",
+          "self-assignment 7:3 \"x\" is assigned to itself",
+          "constant-condition 8:7 Condition is always false",
+          "constant-condition 9:10 Condition is always true",
+          "unreachable-code 10:3 Unreachable code after an infinite loop",
+      ]);
+  }
+
+  #[test]
+  fn comments_silence_warnings() {
+      let source = WARNINGS
+          .replace("void unused(n: int) {", "// lint: allow(unused-function)\nvoid unused(n: int) {")
+          .replace("x = x;", "x = x; // lint: allow(self-assignment)")
+          + "\n// lint: allow-file(constant-condition, unreachable-code)";
+      assert_eq!(lint(&source), ["unused-parameter 4:13 Parameter \"n\" is never used"]);
+  }
+}
@@ -0,0 +1,662 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use pest::Parser;
+use serde_json::{json, Value};
+
+use super::{compile, is_program};
+use super::imports::syntax_error_span;
+use super::linter::lint_program;
+use super::structs::{
+  ast::*,
+  diagnostic::Diagnostic,
+  dusty_context::DustyContext,
+  parser::{Rule, DustyParser},
+  symbol::SymbolKind,
+};
+
+const KEYWORDS: [&str; 21] = [
+  "program", "import", "type", "record", "enum", "const", "vars", "void", "begin", "end",
+  "if", "else", "while", "do", "match", "print!", "int", "float", "round", "floor", "ceil",
+];
+
+// Language server over stdin and stdout. Documents are compiled on every
+// change, the last program that compiled is kept for the navigation requests
+pub fn run_server() {
+  let mut server = Server {
+      documents: HashMap::new(),
+      analyses: HashMap::new(),
+      shutdown: false,
+  };
+  let stdin = io::stdin();
+  let mut input = stdin.lock();
+  while let Some(message) = read_message(&mut input) {
+      let method = message["method"].as_str().unwrap_or_default().to_string();
+      if method == "exit" {
+          std::process::exit(if server.shutdown { 0 } else { 1 });
+      }
+      server.handle(&method, &message);
+  }
+}
+
+// Messages have a Content-Length header and a JSON body
+fn read_message(input: &mut impl BufRead) -> Option<Value> {
+  let mut length = None;
+  loop {
+      let mut header = String::new();
+      if input.read_line(&mut header).ok()? == 0 {
+          return None;
+      }
+      let header = header.trim();
+      if header.is_empty() {
+          break;
+      }
+      if let Some(value) = header.strip_prefix("Content-Length:") {
+          length = value.trim().parse::<usize>().ok();
+      }
+  }
+
+  let mut body = vec![0; length?];
+  input.read_exact(&mut body).ok()?;
+  serde_json::from_slice(&body).ok()
+}
+
+fn send(message: Value) {
+  let body = message.to_string();
+  let mut stdout = io::stdout().lock();
+  write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+  stdout.flush().unwrap();
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+  PathBuf::from(uri.strip_prefix("file://").unwrap_or(uri).replace("%20", " ").replace("%3A", ":"))
+}
+
+fn path_to_uri(path: &Path) -> String {
+  format!("file://{}", path.display().to_string().replace(' ', "%20"))
+}
+
+// LSP positions are 0-based, spans are 1-based
+fn range(span: Span) -> Value {
+  let end_col = if (span.end_line, span.end_col) > (span.line, span.col) { span.end_col } else { span.col + 1 };
+  json!({
+      "start": { "line": span.line.saturating_sub(1), "character": span.col.saturating_sub(1) },
+      "end": { "line": span.end_line.max(span.line).saturating_sub(1), "character": end_col.saturating_sub(1) },
+  })
+}
+
+fn contains(span: Span, line: usize, col: usize) -> bool {
+  (span.line, span.col) <= (line, col) && (line, col) < (span.end_line, span.end_col)
+}
+
+#[derive(Clone, PartialEq)]
+enum Target {
+  Symbol(usize),
+  Function(String), // Signature
+}
+
+// Name written in a file, declaration or use
+struct Occurrence {
+  file: PathBuf,
+  span: Span,
+  target: Target,
+  is_declaration: bool,
+  field: Option<String>,
+}
+
+struct Analysis {
+  program: Program,
+  dusty_context: DustyContext,
+  occurrences: Vec<Occurrence>,
+}
+
+struct Server {
+  documents: HashMap<String, String>, // Text of the open documents by uri
+  analyses: HashMap<String, Analysis>, // Last program that compiled by uri
+  shutdown: bool,
+}
+
+impl Server {
+  fn handle(&mut self, method: &str, message: &Value) {
+      let params = &message["params"];
+      let result = match method {
+          "initialize" => json!({
+              "capabilities": {
+                  "textDocumentSync": 1,
+                  "definitionProvider": true,
+                  "referencesProvider": true,
+                  "hoverProvider": true,
+                  "completionProvider": { "triggerCharacters": ["."] },
+              },
+              "serverInfo": { "name": "dusty" },
+          }),
+          "shutdown" => {
+              self.shutdown = true;
+              Value::Null
+          }
+          // Notifications without a uri or a text are ignored, there is no response to report the error
+          "textDocument/didOpen" => {
+              let document = &params["textDocument"];
+              if let (Some(uri), Some(text)) = (document["uri"].as_str(), document["text"].as_str()) {
+                  self.update(uri, text);
+              }
+              return;
+          }
+          "textDocument/didChange" => {
+              // Full synchronization, the last change has the whole text
+              let uri = params["textDocument"]["uri"].as_str();
+              let text = params["contentChanges"].as_array()
+                  .and_then(|changes| changes.last())
+                  .and_then(|change| change["text"].as_str());
+              if let (Some(uri), Some(text)) = (uri, text) {
+                  self.update(uri, text);
+              }
+              return;
+          }
+          "textDocument/didClose" => {
+              let Some(uri) = params["textDocument"]["uri"].as_str() else {
+                  return;
+              };
+              self.documents.remove(uri);
+              self.analyses.remove(uri);
+              send(json!({
+                  "jsonrpc": "2.0",
+                  "method": "textDocument/publishDiagnostics",
+                  "params": { "uri": uri, "diagnostics": [] },
+              }));
+              return;
+          }
+          "textDocument/definition" => self.definition(params),
+          "textDocument/references" => self.references(params),
+          "textDocument/hover" => self.hover(params),
+          "textDocument/completion" => self.completion(params),
+          _ if message.get("id").is_none() => return, // Notifications that are not used
+          _ => {
+              send(json!({
+                  "jsonrpc": "2.0",
+                  "id": message["id"],
+                  "error": { "code": -32601, "message": format!("Method \"{}\" not found", method) },
+              }));
+              return;
+          }
+      };
+      send(json!({ "jsonrpc": "2.0", "id": message["id"], "result": result }));
+  }
+
+  // Compiles the document and publishes its errors and lint warnings
+  fn update(&mut self, uri: &str, text: &str) {
+      self.documents.insert(uri.to_string(), text.to_string());
+      let path = uri_to_path(uri);
+
+      let mut diagnostics = Vec::new();
+      match analyze(&path, text) {
+          Ok(Some(analysis)) => {
+              for lint in lint_program(&analysis.program, text, &analysis.dusty_context) {
+                  diagnostics.push(json!({
+                      "range": range(lint.span),
+                      "severity": 2,
+                      "code": lint.id,
+                      "source": "dusty",
+                      "message": lint.message,
+                  }));
+              }
+              self.analyses.insert(uri.to_string(), analysis);
+          }
+          Ok(None) => {}
+          Err(diagnostic) => {
+              // Errors of imported files are shown at the start of the document
+              let span = if diagnostic.file.is_some() { Span::default() } else { diagnostic.span };
+              diagnostics.push(json!({
+                  "range": range(span),
+                  "severity": 1,
+                  "source": "dusty",
+                  "message": diagnostic.to_string(),
+              }));
+          }
+      }
+
+      send(json!({
+          "jsonrpc": "2.0",
+          "method": "textDocument/publishDiagnostics",
+          "params": { "uri": uri, "diagnostics": diagnostics },
+      }));
+  }
+
+  // Analysis of the document and the name at the position of the request
+  fn find(&self, params: &Value) -> Option<(&Analysis, &Occurrence)> {
+      let uri = params["textDocument"]["uri"].as_str()?;
+      let line = params["position"]["line"].as_u64()? as usize + 1;
+      let col = params["position"]["character"].as_u64()? as usize + 1;
+      let path = uri_to_path(uri);
+      let analysis = self.analyses.get(uri)?;
+      let occurrence = analysis.occurrences.iter()
+          .find(|occurrence| occurrence.file == path && contains(occurrence.span, line, col))?;
+      Some((analysis, occurrence))
+  }
+
+  fn definition(&self, params: &Value) -> Value {
+      let Some((analysis, occurrence)) = self.find(params) else {
+          return Value::Null;
+      };
+      analysis.occurrences.iter()
+          .find(|declaration| declaration.is_declaration && declaration.target == occurrence.target)
+          .map(|declaration| json!({ "uri": path_to_uri(&declaration.file), "range": range(declaration.span) }))
+          .unwrap_or(Value::Null)
+  }
+
+  fn references(&self, params: &Value) -> Value {
+      let Some((analysis, occurrence)) = self.find(params) else {
+          return Value::Null;
+      };
+      let include_declaration = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+      let locations: Vec<Value> = analysis.occurrences.iter()
+          .filter(|reference| reference.target == occurrence.target && (include_declaration || !reference.is_declaration))
+          .map(|reference| json!({ "uri": path_to_uri(&reference.file), "range": range(reference.span) }))
+          .collect();
+      json!(locations)
+  }
+
+  // Type and virtual address of variables, value of constants
+  fn hover(&self, params: &Value) -> Value {
+      let Some((analysis, occurrence)) = self.find(params) else {
+          return Value::Null;
+      };
+      let dusty_context = &analysis.dusty_context;
+      let text = match &occurrence.target {
+          Target::Symbol(symbol) => {
+              let symbol = &dusty_context.symbols[*symbol];
+              let Some(var) = &symbol.var else {
+                  return Value::Null;
+              };
+              let (name, var_type, location) = match &occurrence.field {
+                  Some(field) => {
+                      let name = format!("{}.{}", var.name, field);
+                      let Some(field_var) = var.fields.iter().find(|field_var| field_var.name == name) else {
+                          return Value::Null;
+                      };
+                      (field_var.name.clone(), field_var.var_type.clone(), field_var.location)
+                  }
+                  None => (symbol.name.clone(), symbol.var_type.clone(), var.location),
+              };
+              let mut text = format!("```dusty\n{} {}: {}\n```\naddress {}", symbol.kind.as_str(), name, var_type, location);
+              if let (Some(value), None) = (&symbol.value, &occurrence.field) {
+                  text.push_str(&format!(", value {}", value));
+              }
+              text
+          }
+          Target::Function(signature) => {
+              let Some(function) = dusty_context.func_dir.get(signature) else {
+                  return Value::Null;
+              };
+              format!("```dusty\nvoid {}\n```\nstarts at quad {}", signature, function.location)
+          }
+      };
+      json!({ "contents": { "kind": "markdown", "value": text }, "range": range(occurrence.span) })
+  }
+
+  // Names in scope at the position, functions, builtins and keywords
+  fn completion(&self, params: &Value) -> Value {
+      let mut items = Vec::new();
+      let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+      if let Some(analysis) = self.analyses.get(uri) {
+          let line = params["position"]["line"].as_u64().unwrap_or_default() as usize + 1;
+          let col = params["position"]["character"].as_u64().unwrap_or_default() as usize + 1;
+          for symbol in symbols_in_scope(&analysis.program, line, col) {
+              let symbol = &analysis.dusty_context.symbols[symbol];
+              let kind = match symbol.kind {
+                  SymbolKind::Constant => 21,
+                  SymbolKind::Variant => 20,
+                  _ => 6,
+              };
+              items.push(json!({ "label": symbol.name, "kind": kind, "detail": symbol.var_type }));
+          }
+
+          let mut functions: Vec<&String> = analysis.dusty_context.func_dir.keys().filter(|name| *name != "global").collect();
+          functions.sort();
+          for signature in functions {
+              let name = signature.split('(').next().unwrap();
+              items.push(json!({ "label": name, "kind": 3, "detail": signature }));
+          }
+      }
+
+      let mut builtins: Vec<String> = DustyContext::new().builtin_dir.into_keys().collect();
+      builtins.sort();
+      for builtin in builtins {
+          items.push(json!({ "label": builtin, "kind": 3, "detail": "builtin" }));
+      }
+      for keyword in KEYWORDS {
+          items.push(json!({ "label": keyword, "kind": 14 }));
+      }
+      json!(items)
+  }
+}
+
+// Compiles the program, None for imported files, which are only parsed
+fn analyze(path: &Path, source: &str) -> Result<Option<Analysis>, Diagnostic> {
+  if !is_program(source) {
+      return match DustyParser::parse(Rule::module, source) {
+          Ok(_) => Ok(None),
+          Err(e) => Err(Diagnostic::new(format!("ERROR: Syntax error\n{}", e), syntax_error_span(&e))),
+      };
+  }
+
+  // Imports are relative to the file of the document
+  let mut dusty_context = DustyContext::new();
+  dusty_context.import_stack.push(path.canonicalize().unwrap_or(path.to_path_buf()));
+  let program = compile(source, &mut dusty_context)?;
+
+  let mut occurrences = Vec::new();
+  let file = dusty_context.import_stack[0].clone();
+  index_module(&program.module, &file, &mut occurrences);
+  index_block(&program.body, &file, &mut occurrences);
+  Ok(Some(Analysis {
+      program,
+      dusty_context,
+      occurrences,
+  }))
+}
+
+fn declaration(id: &Ident, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  if let Some(symbol) = id.symbol {
+      occurrences.push(Occurrence {
+          file: file.to_path_buf(),
+          span: id.span,
+          target: Target::Symbol(symbol),
+          is_declaration: true,
+          field: None,
+      });
+  }
+}
+
+fn reference(id: &Ident, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  occurrences.push(Occurrence {
+      file: file.to_path_buf(),
+      span: id.span,
+      target: Target::Symbol(id.symbol.unwrap()),
+      is_declaration: false,
+      field: id.field.clone(),
+  });
+}
+
+fn index_module(module: &Module, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  for import in module.imports.iter() {
+      if let Some(imported) = &import.module {
+          index_module(imported, imported.path.as_ref().unwrap(), occurrences);
+      }
+  }
+  for type_decl in module.types.iter() {
+      if let TypeDecl::Enum(enum_decl) = type_decl {
+          for variant in enum_decl.variants.iter() {
+              declaration(variant, file, occurrences);
+          }
+      }
+  }
+  for const_decl in module.consts.iter() {
+      declaration(&const_decl.name, file, occurrences);
+      index_expr(&const_decl.value, file, occurrences);
+  }
+  index_vars(&module.vars, file, occurrences);
+  for function in module.funcs.iter() {
+      occurrences.push(Occurrence {
+          file: file.to_path_buf(),
+          span: function.name.span,
+          target: Target::Function(function.signature.clone()),
+          is_declaration: true,
+          field: None,
+      });
+      for (param, _) in function.params.iter() {
+          declaration(param, file, occurrences);
+      }
+      for const_decl in function.consts.iter() {
+          declaration(&const_decl.name, file, occurrences);
+          index_expr(&const_decl.value, file, occurrences);
+      }
+      index_vars(&function.vars, file, occurrences);
+      for stmt in function.body.iter() {
+          index_stmt(stmt, file, occurrences);
+      }
+  }
+}
+
+fn index_vars(vars: &[VarDecl], file: &Path, occurrences: &mut Vec<Occurrence>) {
+  for var_decl in vars {
+      for name in var_decl.names.iter() {
+          declaration(name, file, occurrences);
+      }
+      if let Some(init) = &var_decl.init {
+          index_expr(init, file, occurrences);
+      }
+  }
+}
+
+fn index_block(block: &Block, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  index_vars(&block.vars, file, occurrences);
+  for stmt in block.stmts.iter() {
+      index_stmt(stmt, file, occurrences);
+  }
+}
+
+fn index_stmt(stmt: &Stmt, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  match &stmt.kind {
+      StmtKind::Assign { target, value } => {
+          reference(target, file, occurrences);
+          index_expr(value, file, occurrences);
+      }
+      StmtKind::If { condition, then_block, else_block } => {
+          index_expr(condition, file, occurrences);
+          index_block(then_block, file, occurrences);
+          if let Some(else_block) = else_block {
+              index_block(else_block, file, occurrences);
+          }
+      }
+      StmtKind::While { condition, body } => {
+          index_expr(condition, file, occurrences);
+          index_block(body, file, occurrences);
+      }
+      StmtKind::Match { scrutinee, arms, default } => {
+          index_expr(scrutinee, file, occurrences);
+          for arm in arms.iter() {
+              index_expr(&arm.pattern, file, occurrences);
+              index_block(&arm.body, file, occurrences);
+          }
+          if let Some(default) = default {
+              index_block(default, file, occurrences);
+          }
+      }
+      StmtKind::Call(call) => index_call(call, file, occurrences),
+      StmtKind::Print(elements) => {
+          for element in elements.iter() {
+              if let PrintElement::Expr(expr) = element {
+                  index_expr(expr, file, occurrences);
+              }
+          }
+      }
+  }
+}
+
+fn index_call(call: &Call, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  if !call.is_builtin {
+      occurrences.push(Occurrence {
+          file: file.to_path_buf(),
+          span: call.name.span,
+          target: Target::Function(call.signature.clone()),
+          is_declaration: false,
+          field: None,
+      });
+  }
+  for arg in call.args.iter() {
+      index_expr(arg, file, occurrences);
+  }
+}
+
+fn index_expr(expr: &Expr, file: &Path, occurrences: &mut Vec<Occurrence>) {
+  match &expr.kind {
+      ExprKind::Int(_) | ExprKind::Float(_) => {}
+      ExprKind::Name(id) => reference(id, file, occurrences),
+      ExprKind::Group(operand) | ExprKind::Unary { operand, .. } | ExprKind::Conversion { operand, .. } => {
+          index_expr(operand, file, occurrences);
+      }
+      ExprKind::Binary { left, right, .. } => {
+          index_expr(left, file, occurrences);
+          index_expr(right, file, occurrences);
+      }
+      ExprKind::Call(call) => index_call(call, file, occurrences),
+  }
+}
+
+// Symbols visible at the position: globals of the program and its imports,
+// and the locals of the function and blocks that contain it
+fn symbols_in_scope(program: &Program, line: usize, col: usize) -> Vec<usize> {
+  let mut symbols = Vec::new();
+  global_symbols(&program.module, &mut symbols);
+
+  for function in program.module.funcs.iter().filter(|function| contains(function.span, line, col)) {
+      symbols.extend(function.params.iter().filter_map(|(param, _)| param.symbol));
+      symbols.extend(function.consts.iter().filter_map(|const_decl| const_decl.name.symbol));
+      var_symbols(&function.vars, &mut symbols);
+      for stmt in function.body.iter() {
+          block_symbols(stmt, line, col, &mut symbols);
+      }
+  }
+  if contains(program.body.span, line, col) {
+      var_symbols(&program.body.vars, &mut symbols);
+      for stmt in program.body.stmts.iter() {
+          block_symbols(stmt, line, col, &mut symbols);
+      }
+  }
+  symbols
+}
+
+fn global_symbols(module: &Module, symbols: &mut Vec<usize>) {
+  for import in module.imports.iter() {
+      if let Some(imported) = &import.module {
+          global_symbols(imported, symbols);
+      }
+  }
+  for type_decl in module.types.iter() {
+      if let TypeDecl::Enum(enum_decl) = type_decl {
+          symbols.extend(enum_decl.variants.iter().filter_map(|variant| variant.symbol));
+      }
+  }
+  symbols.extend(module.consts.iter().filter_map(|const_decl| const_decl.name.symbol));
+  var_symbols(&module.vars, symbols);
+}
+
+fn var_symbols(vars: &[VarDecl], symbols: &mut Vec<usize>) {
+  for var_decl in vars {
+      symbols.extend(var_decl.names.iter().filter_map(|name| name.symbol));
+  }
+}
+
+// Variables of the blocks of the statement that contain the position
+fn block_symbols(stmt: &Stmt, line: usize, col: usize, symbols: &mut Vec<usize>) {
+  let blocks: Vec<&Block> = match &stmt.kind {
+      StmtKind::If { then_block, else_block, .. } => std::iter::once(then_block).chain(else_block.iter()).collect(),
+      StmtKind::While { body, .. } => vec![body],
+      StmtKind::Match { arms, default, .. } => arms.iter().map(|arm| &arm.body).chain(default.iter()).collect(),
+      _ => Vec::new(),
+  };
+  for block in blocks.into_iter().filter(|block| contains(block.span, line, col)) {
+      var_symbols(&block.vars, symbols);
+      for stmt in block.stmts.iter() {
+          block_symbols(stmt, line, col, symbols);
+      }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DOCUMENT: &str = "program p;
+type Point = record { x: float, y: float };
+vars origin: Point;
+void reset() { origin.x = 0.0; };
+begin
+{
+  reset();
+}
+end";
+
+  fn server(source: &str) -> Server {
+      let analysis = analyze(Path::new("/doc.dusty"), source).unwrap().unwrap();
+      Server {
+          documents: HashMap::new(),
+          analyses: HashMap::from([("file:///doc.dusty".to_string(), analysis)]),
+          shutdown: false,
+      }
+  }
+
+  // Request params at a 1-based line and col
+  fn position(line: usize, col: usize) -> Value {
+      json!({
+          "textDocument": { "uri": "file:///doc.dusty" },
+          "position": { "line": line - 1, "character": col - 1 },
+      })
+  }
+
+  // Start line and col of the range of a location, 1-based
+  fn start(location: &Value) -> (u64, u64) {
+      let start = &location["range"]["start"];
+      (start["line"].as_u64().unwrap() + 1, start["character"].as_u64().unwrap() + 1)
+  }
+
+  #[test]
+  fn definition_and_references_find_the_declaration_and_the_uses() {
+      let server = server(DOCUMENT);
+      let definition = server.definition(&position(4, 17));
+      assert_eq!(definition["uri"], "file:///doc.dusty");
+      assert_eq!(start(&definition), (3, 6));
+
+      let mut params = position(7, 3);
+      params["context"] = json!({ "includeDeclaration": true });
+      let references: Vec<(u64, u64)> = server.references(&params).as_array().unwrap().iter().map(start).collect();
+      assert_eq!(references, [(4, 6), (7, 3)]);
+      params["context"] = json!({ "includeDeclaration": false });
+      assert_eq!(server.references(&params).as_array().unwrap().len(), 1);
+
+      // Nothing at the position
+      assert_eq!(server.definition(&position(5, 1)), Value::Null);
+  }
+
+  #[test]
+  fn completion_lists_names_in_scope_functions_builtins_and_keywords() {
+      let server = server(DOCUMENT);
+      let items = server.completion(&position(4, 16));
+      let labels: Vec<&str> = items.as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap()).collect();
+      for label in ["origin", "reset", "sqrt", "while"] {
+          assert!(labels.contains(&label), "{} is not completed", label);
+      }
+  }
+
+  #[test]
+  fn only_programs_are_compiled() {
+      assert!(analyze(Path::new("/doc.dusty"), "vars x: int;").unwrap().is_none());
+      let error = analyze(Path::new("/doc.dusty"), "program p; begin { x = 1; } end").err().unwrap();
+      assert_eq!(error.span.start(), (1, 20));
+  }
+
+  #[test]
+  fn hover_shows_fields_and_functions() {
+      let server = server(DOCUMENT);
+      let field = server.hover(&position(4, 17));
+      assert!(field["contents"]["value"].as_str().unwrap().contains("origin.x: float"));
+      let function = server.hover(&position(7, 3));
+      assert!(function["contents"]["value"].as_str().unwrap().contains("void reset()"));
+  }
+
+  #[test]
+  fn hover_without_the_declaration_is_null() {
+      let mut server = server(DOCUMENT);
+      let analysis = server.analyses.get_mut("file:///doc.dusty").unwrap();
+      analysis.dusty_context.func_dir.remove("reset()");
+      for symbol in analysis.dusty_context.symbols.iter_mut() {
+          if let Some(var) = &mut symbol.var {
+              var.fields.clear();
+          }
+      }
+      assert_eq!(server.hover(&position(4, 17)), Value::Null);
+      assert_eq!(server.hover(&position(7, 3)), Value::Null);
+  }
+}
@@ -1,40 +1,187 @@
-mod structs;
-mod virtual_machine;
-mod quadruples;
-
 use std::fs;
-use pest::Parser;
+use std::time::Duration;
+
+use colored::*;
 
-use virtual_machine::run_virtual_machine;
-use quadruples::generate_quadruples;
-use structs::{
-    dusty_context::{DustyContext, Stage},
-    parser::{Rule, DustyParser},
+use dusty::compile_source;
+use dusty::formatter::format_source;
+use dusty::linter::lint_program;
+use dusty::repl::run_repl;
+use dusty::debugger::run_debugger;
+use dusty::profiler::Profile;
+use dusty::virtual_machine::VirtualMachine;
+use dusty::definite_assignment::check_definite_assignment;
+use dusty::structs::{
+    dusty_context::DustyContext,
+    vm_options::VmOptions,
+    compile_options::CompileOptions,
 };
 
 fn main() {
-    // File path to read
-    let path = "C:/Users/wetpe/Documents/Tec8/compiladores/ducky-language-rust/src/tests/test2.dusty";
-    let patito_file = fs::read_to_string(&path).expect("error reading file");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "fmt") {
+        format_files(&args[1..]);
+        return;
+    }
+    if args.first().is_some_and(|arg| arg == "lsp") {
+        dusty::lsp::run_server();
+        return;
+    }
+
+    // Usage: dusty [run | lint | debug] <file> [--check-uninit] [--wrapping] [--lenient] [--warn-shadow] [--profile] [<trace options>] [<limits>]
+    //        dusty repl [--check-uninit] [--wrapping] [--lenient] [--warn-shadow] [<trace options>] [<limits>]
+    // Trace options: --trace[=<file>] [--trace-function=<name>] [--trace-addresses=<from>-<to>]
+    // Limits: --max-instructions=<n> --max-time=<ms> --max-depth=<n> --max-memory=<values>
+    let mode = match args.first().map(|arg| arg.as_str()) {
+        Some(mode @ ("run" | "lint" | "debug" | "repl")) => mode.to_string(),
+        _ => String::new(),
+    };
+    let mut path = None;
+    let mut vm_options = VmOptions::new();
+    let mut compile_options = CompileOptions::new();
+    let mut profile = false;
+    for arg in args.into_iter().skip(!mode.is_empty() as usize) {
+        match arg.as_str() {
+            "--check-uninit" => vm_options.check_uninitialized = true,
+            "--wrapping" => vm_options.wrapping_arithmetic = true,
+            "--lenient" => compile_options.strict_types = false,
+            "--warn-shadow" => compile_options.warn_shadowing = true,
+            "--profile" => profile = true,
+            "--trace" => vm_options.trace = true,
+            _ if arg.starts_with("--trace=") => {
+                // The file is created now so a wrong path is reported before compiling
+                let trace_file = &arg["--trace=".len()..];
+                if let Err(error) = fs::File::create(trace_file) {
+                    usage_error(&format!("ERROR: Cannot create the trace file \"{}\", {}", trace_file, error));
+                }
+                vm_options.trace = true;
+                vm_options.trace_file = Some(trace_file.into());
+            }
+            _ if arg.starts_with("--trace-function=") => {
+                vm_options.trace_function = Some(arg["--trace-function=".len()..].to_string());
+            }
+            _ if arg.starts_with("--trace-addresses=") => {
+                let range = arg["--trace-addresses=".len()..].split_once('-')
+                    .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)));
+                let Some(range) = range else {
+                    usage_error("ERROR: --trace-addresses expects a range, like --trace-addresses=1000-2999");
+                };
+                vm_options.trace_addresses = Some(range);
+            }
+            _ if arg.starts_with("--max-") => set_limit(&mut vm_options, &arg),
+            _ if arg.starts_with("--") => usage_error(&format!("ERROR: Unknown option {}", arg)),
+            _ => path = Some(arg),
+        }
+    }
+    if mode == "repl" {
+        run_repl(compile_options, vm_options);
+        return;
+    }
+    let Some(path) = path else {
+        usage_error("Usage: dusty [run | lint | debug] <file> [--check-uninit] [--wrapping] [--lenient] [--warn-shadow] [--profile] [--trace[=<file>]]");
+    };
 
     let mut dusty_context = DustyContext::new();
+    dusty_context.options = compile_options;
 
-    match DustyParser::parse(Rule::program, &patito_file) {
-        Ok(pairs) => {
-            // Enter the Tree and generate quadruples
-            for pair in pairs.into_iter().next().unwrap().into_inner() {
-                generate_quadruples(
-                    pair,
-                    Stage::Before,
-                    &mut dusty_context
-                );
-            }
+    // The source is read once, lint and the debugger show its lines
+    let Ok(source) = fs::read_to_string(&path) else {
+        usage_error(&format!("ERROR: Cannot read file \"{}\"", path));
+    };
+    let program = match compile_source(&path, &source, &mut dusty_context) {
+        Ok(program) => program,
+        Err(diagnostic) => {
+            eprintln!("{}", diagnostic.to_string().red());
+            std::process::exit(1);
         }
-        Err(e) => {
-            println!("Error: {:#?}", e);
+    };
+
+    // Only report the warnings, exits with code 1 if there is any
+    if mode == "lint" {
+        let lints = lint_program(&program, &source, &dusty_context);
+        for lint in lints.iter() {
+            eprintln!("{}", lint.to_string().yellow());
         }
+        std::process::exit(if lints.is_empty() { 0 } else { 1 });
     }
     // println!("{:#?}", dusty_context.const_dir);
     // dusty_context.print_quadruples_as_name();
-    run_virtual_machine(&dusty_context);
+    for warning in dusty_context.warnings.iter().cloned().chain(check_definite_assignment(&dusty_context)) {
+        eprintln!("{}", warning.yellow());
+    }
+    if mode == "debug" {
+        run_debugger(&dusty_context, &path, &source, &vm_options);
+        return;
+    }
+    let mut virtual_machine = match VirtualMachine::new(&dusty_context) {
+        Ok(virtual_machine) => virtual_machine,
+        Err(error) => {
+            eprintln!("{}", error.to_string().red());
+            std::process::exit(error.exit_code());
+        }
+    };
+    if profile {
+        virtual_machine.profile = Some(Profile::new());
+    }
+    let result = virtual_machine.run(&dusty_context, &vm_options);
+    // The report goes to stderr after the output of the program
+    if let Some(profile) = virtual_machine.profile.as_mut() {
+        eprint!("{}", profile.report(&dusty_context, &path));
+    }
+    // Runtime errors are shown with the Dusty call stack, each kind exits with its own code
+    if let Err(failure) = result {
+        eprintln!("{}", failure.to_string().red());
+        std::process::exit(failure.error.exit_code());
+    }
+}
+
+// --max-<limit>=<value> of the sandboxed runs
+fn set_limit(vm_options: &mut VmOptions, arg: &str) {
+    let (limit, value) = arg.split_once('=').unwrap_or((arg, ""));
+    let Ok(value) = value.parse::<u64>() else {
+        usage_error(&format!("ERROR: {} expects a number, like {}=1000", limit, limit));
+    };
+    match limit {
+        "--max-instructions" => vm_options.max_instructions = Some(value),
+        "--max-time" => vm_options.max_time = Some(Duration::from_millis(value)),
+        "--max-depth" => vm_options.max_call_depth = value as usize,
+        "--max-memory" => vm_options.max_memory = Some(value as usize),
+        _ => usage_error(&format!("ERROR: Unknown option {}", limit)),
+    }
+}
+
+// Wrong command line arguments, reported like compile errors
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message.red());
+    std::process::exit(1);
+}
+
+// Usage: dusty fmt [--check] <files>
+// Rewrites the files formatted, with --check only reports the files that are
+// not formatted and exits with code 1 if there is any
+fn format_files(args: &[String]) {
+    let check = args.iter().any(|arg| arg == "--check");
+    let mut unformatted = false;
+    for path in args.iter().filter(|arg| *arg != "--check") {
+        let source = fs::read_to_string(path).expect("error reading file");
+        let formatted = match format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(diagnostic) => {
+                eprintln!("{}", format!("{}: {}", path, diagnostic).red());
+                std::process::exit(1);
+            }
+        };
+
+        if formatted != source {
+            if check {
+                eprintln!("{} is not formatted", path);
+                unformatted = true;
+            } else {
+                fs::write(path, formatted).expect("error writing file");
+            }
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
 }
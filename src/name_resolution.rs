@@ -0,0 +1,426 @@
+use super::structs::{
+  ast::*,
+  diagnostic::Diagnostic,
+  dusty_context::DustyContext,
+  function_info::FunctionInfo,
+  record_info::RecordInfo,
+  enum_info::EnumInfo,
+  symbol::{Symbol, SymbolKind},
+};
+
+// Binds every name of the program to its declaration. Variables, parameters,
+// constants and enum variants go to the symbol table, types and functions to
+// their directories, and every use of a name gets the symbol it refers to
+pub fn resolve_program(program: &mut Program, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  // The global scope is kept in the function directory as "global"
+  dusty_context.func_dir.insert("global".to_string(), FunctionInfo::new(0));
+  dusty_context.current_func = "global".to_string();
+  dusty_context.push_scope();
+
+  resolve_module(&mut program.module, dusty_context)?;
+  resolve_block(&mut program.body, dusty_context)?;
+  dusty_context.pop_scope();
+  Ok(())
+}
+
+// Resolves one input of the REPL, the global scope stays open between inputs
+pub fn resolve_input(module: &mut Module, body: &mut Block, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  if !dusty_context.func_dir.contains_key("global") {
+      dusty_context.func_dir.insert("global".to_string(), FunctionInfo::new(0));
+      dusty_context.push_scope();
+  }
+  dusty_context.current_func = "global".to_string();
+
+  resolve_module(module, dusty_context)?;
+  resolve_block(body, dusty_context)
+}
+
+fn resolve_module(module: &mut Module, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  for import in module.imports.iter_mut() {
+      if let Some(imported) = &mut import.module {
+          // Errors of the imported file are reported with its path
+          let previous_module = std::mem::replace(&mut dusty_context.current_module, imported.namespace.clone());
+          let result = resolve_module(imported, dusty_context);
+          dusty_context.current_module = previous_module;
+          result.map_err(|e| e.in_file(imported.path.as_ref().unwrap()))?;
+      }
+  }
+
+  for type_decl in module.types.iter_mut() {
+      match type_decl {
+          TypeDecl::Record(record) => resolve_record(record, dusty_context)?,
+          TypeDecl::Enum(enum_decl) => resolve_enum(enum_decl, dusty_context)?,
+      }
+  }
+  for const_decl in module.consts.iter_mut() {
+      resolve_const(const_decl, dusty_context)?;
+  }
+  for var_decl in module.vars.iter_mut() {
+      resolve_vars(var_decl, dusty_context)?;
+  }
+  for function in module.funcs.iter_mut() {
+      resolve_func(function, dusty_context)?;
+  }
+  Ok(())
+}
+
+fn resolve_record(record: &mut RecordDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let name = dusty_context.declaration_name(&record.name.name);
+  if dusty_context.type_exists(&name) {
+      return Err(Diagnostic::new(format!("ERROR: Type {} already exists, line: {}, col: {}",
+          record.name.name,
+          record.name.span.line,
+          record.name.span.col
+      ), record.name.span));
+  }
+
+  let mut record_info = RecordInfo::new();
+  for (field, field_type) in record.fields.iter() {
+      let field_type = dusty_context.resolve_type(&field_type.name);
+      if record_info.contains_field(&field.name) {
+          return Err(Diagnostic::new(format!("ERROR: Field {} already exists in record {}, line: {}, col: {}",
+              field.name,
+              record.name.name,
+              field.span.line,
+              field.span.col
+          ), field.span));
+      }
+      if field_type != "int" && field_type != "float" && !dusty_context.enum_dir.contains_key(&field_type) {
+          return Err(Diagnostic::new(format!("ERROR: Field {} of record {} must be int, float or an enum, line: {}, col: {}",
+              field.name,
+              record.name.name,
+              field.span.line,
+              field.span.col
+          ), field.span));
+      }
+      record_info.fields.push((field.name.clone(), field_type));
+  }
+  dusty_context.type_dir.insert(name, record_info);
+  Ok(())
+}
+
+fn resolve_enum(enum_decl: &mut EnumDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let enum_name = dusty_context.declaration_name(&enum_decl.name.name);
+  if dusty_context.type_exists(&enum_name) {
+      return Err(Diagnostic::new(format!("ERROR: Type {} already exists, line: {}, col: {}",
+          enum_decl.name.name,
+          enum_decl.name.span.line,
+          enum_decl.name.span.col
+      ), enum_decl.name.span));
+  }
+  dusty_context.enum_dir.insert(enum_name.clone(), EnumInfo::new());
+  dusty_context.quad_data.semantic_cube.add_enum(&enum_name);
+
+  // Variants are int constants typed with the enum, bound as <enum>.<variant>
+  for variant in enum_decl.variants.iter_mut() {
+      let name = format!("{}.{}", enum_name, variant.name);
+      if dusty_context.contains_id(&name) {
+          return Err(Diagnostic::new(format!("ERROR: Variant {} already exists in enum {}, line: {}, col: {}",
+              variant.name,
+              enum_decl.name.name,
+              variant.span.line,
+              variant.span.col
+          ), variant.span));
+      }
+      let enum_info = dusty_context.enum_dir.get_mut(&enum_name).unwrap();
+      let value = enum_info.variants.len().to_string();
+      enum_info.variants.push(variant.name.clone());
+
+      let mut symbol = Symbol::new(name, SymbolKind::Variant, enum_name.clone(), dusty_context.current_func.clone(), variant.span);
+      symbol.value = Some(value);
+      variant.symbol = Some(dusty_context.declare(symbol));
+  }
+  Ok(())
+}
+
+fn resolve_const(const_decl: &mut ConstDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let name = dusty_context.declaration_name(&const_decl.name.name);
+  dusty_context.check_declaration(&name, SymbolKind::Constant, const_decl.name.span)?;
+  let const_type = dusty_context.resolve_type(&const_decl.const_type.name);
+
+  // The constant is visible after its value
+  resolve_expr(&mut const_decl.value, dusty_context)?;
+  let symbol = Symbol::new(name, SymbolKind::Constant, const_type, dusty_context.current_func.clone(), const_decl.name.span);
+  const_decl.name.symbol = Some(dusty_context.declare(symbol));
+  Ok(())
+}
+
+fn resolve_vars(var_decl: &mut VarDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let var_type = resolve_declared_type(&var_decl.var_type, dusty_context)?;
+  for id in var_decl.names.iter_mut() {
+      let name = dusty_context.declaration_name(&id.name);
+      dusty_context.check_declaration(&name, SymbolKind::Variable, id.span)?;
+      let symbol = Symbol::new(name, SymbolKind::Variable, var_type.clone(), dusty_context.current_func.clone(), id.span);
+      id.symbol = Some(dusty_context.declare(symbol));
+  }
+
+  if let Some(init) = &mut var_decl.init {
+      resolve_expr(init, dusty_context)?;
+  }
+  Ok(())
+}
+
+fn resolve_declared_type(var_type: &Ident, dusty_context: &DustyContext) -> Result<String, Diagnostic> {
+  let resolved = dusty_context.resolve_type(&var_type.name);
+  if !dusty_context.type_exists(&resolved) {
+      return Err(Diagnostic::new(format!("ERROR: Type \"{}\" was not declared, line: {}, col: {}",
+          var_type.name,
+          var_type.span.line,
+          var_type.span.col
+      ), var_type.span));
+  }
+  Ok(resolved)
+}
+
+fn resolve_func(function: &mut FuncDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let id = &function.name;
+  if dusty_context.builtin_dir.contains_key(&id.name) {
+      return Err(Diagnostic::new(format!("ERROR: Function \"{}\" is already declared as a builtin function, line: {}, col: {}",
+          id.name,
+          id.span.line,
+          id.span.col
+      ), id.span));
+  }
+  if id.name == "global" {
+      return Err(Diagnostic::new(format!("ERROR: \"global\" is reserved and can't be used as a function name, line: {}, col: {}",
+          id.span.line,
+          id.span.col
+      ), id.span));
+  }
+
+  // Functions are kept with their parameter types, show(int) and show(float) can coexist
+  let mut function_info = FunctionInfo::new(0);
+  function_info.span = id.span;
+  for (_, param_type) in function.params.iter() {
+      function_info.params.push(resolve_declared_type(param_type, dusty_context)?);
+  }
+  let signature = format!("{}({})", dusty_context.declaration_name(&id.name), function_info.params.join(","));
+  if let Some(original) = dusty_context.func_dir.get(&signature) {
+      return Err(Diagnostic::new(format!("ERROR: Function \"{}\" is already declared at line {}, col {}, redeclared at line {}, col {}",
+          signature,
+          original.span.line,
+          original.span.col,
+          id.span.line,
+          id.span.col
+      ), id.span));
+  }
+  let param_types = function_info.params.clone();
+  dusty_context.func_dir.insert(signature.clone(), function_info);
+  function.signature = signature.clone();
+  dusty_context.current_func = signature;
+  dusty_context.push_scope();
+
+  for ((param, _), param_type) in function.params.iter_mut().zip(param_types) {
+      dusty_context.check_declaration(&param.name, SymbolKind::Parameter, param.span)?;
+      let symbol = Symbol::new(param.name.clone(), SymbolKind::Parameter, param_type, dusty_context.current_func.clone(), param.span);
+      param.symbol = Some(dusty_context.declare(symbol));
+  }
+  for const_decl in function.consts.iter_mut() {
+      resolve_const(const_decl, dusty_context)?;
+  }
+  for var_decl in function.vars.iter_mut() {
+      resolve_vars(var_decl, dusty_context)?;
+  }
+  for stmt in function.body.iter_mut() {
+      resolve_stmt(stmt, dusty_context)?;
+  }
+
+  dusty_context.pop_scope();
+  dusty_context.current_func = "global".to_string();
+  Ok(())
+}
+
+fn resolve_block(block: &mut Block, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  dusty_context.push_scope();
+  for var_decl in block.vars.iter_mut() {
+      resolve_vars(var_decl, dusty_context)?;
+  }
+  for stmt in block.stmts.iter_mut() {
+      resolve_stmt(stmt, dusty_context)?;
+  }
+  dusty_context.pop_scope();
+  Ok(())
+}
+
+fn resolve_stmt(stmt: &mut Stmt, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  match &mut stmt.kind {
+      StmtKind::Assign { target, value } => {
+          resolve_ident(target, dusty_context)?;
+          resolve_expr(value, dusty_context)
+      }
+      StmtKind::If { condition, then_block, else_block } => {
+          resolve_expr(condition, dusty_context)?;
+          resolve_block(then_block, dusty_context)?;
+          match else_block {
+              Some(else_block) => resolve_block(else_block, dusty_context),
+              None => Ok(()),
+          }
+      }
+      StmtKind::While { condition, body } => {
+          resolve_expr(condition, dusty_context)?;
+          resolve_block(body, dusty_context)
+      }
+      StmtKind::Match { scrutinee, arms, default } => {
+          resolve_expr(scrutinee, dusty_context)?;
+          for arm in arms.iter_mut() {
+              resolve_expr(&mut arm.pattern, dusty_context)?;
+              resolve_block(&mut arm.body, dusty_context)?;
+          }
+          match default {
+              Some(default) => resolve_block(default, dusty_context),
+              None => Ok(()),
+          }
+      }
+      StmtKind::Call(call) => resolve_call(call, dusty_context),
+      StmtKind::Print(elements) => {
+          for element in elements.iter_mut() {
+              if let PrintElement::Expr(expr) = element {
+                  resolve_expr(expr, dusty_context)?;
+              }
+          }
+          Ok(())
+      }
+  }
+}
+
+fn resolve_call(call: &mut Call, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let name = dusty_context.resolve_function(&call.name.name);
+  call.is_builtin = dusty_context.builtin_dir.contains_key(&name);
+  if !call.is_builtin && dusty_context.get_overloads(&name).is_empty() {
+      return Err(Diagnostic::new(format!("ERROR: Function \"{}\" was not declared, line: {}, col: {}",
+          call.name.name,
+          call.name.span.line,
+          call.name.span.col
+      ), call.name.span));
+  }
+  call.function = name;
+
+  for arg in call.args.iter_mut() {
+      resolve_expr(arg, dusty_context)?;
+  }
+  Ok(())
+}
+
+fn resolve_expr(expr: &mut Expr, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  match &mut expr.kind {
+      ExprKind::Int(_) | ExprKind::Float(_) => Ok(()),
+      ExprKind::Name(id) => resolve_ident(id, dusty_context),
+      ExprKind::Group(operand) | ExprKind::Unary { operand, .. } | ExprKind::Conversion { operand, .. } => {
+          resolve_expr(operand, dusty_context)
+      }
+      ExprKind::Binary { left, right, .. } => {
+          resolve_expr(left, dusty_context)?;
+          resolve_expr(right, dusty_context)
+      }
+      ExprKind::Call(call) => resolve_call(call, dusty_context),
+  }
+}
+
+fn resolve_ident(id: &mut Ident, dusty_context: &DustyContext) -> Result<(), Diagnostic> {
+  match dusty_context.lookup_id(&id.name) {
+      Some((symbol, field)) => {
+          id.symbol = Some(symbol);
+          id.field = field;
+          Ok(())
+      }
+      None => Err(Diagnostic::new(format!("ERROR: ID \"{}\" not found in current context \"{}\", line: {}, col: {}",
+          id.name,
+          dusty_context.current_func,
+          id.span.line,
+          id.span.col
+      ), id.span)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile;
+
+  fn compile_error(source: &str) -> String {
+      compile(source, &mut DustyContext::new()).expect_err("the program should not compile").to_string()
+  }
+
+  #[test]
+  fn redeclared_functions_report_both_declarations() {
+      let source = "program p;
+void show(a: int) { print!(a); };
+void show(b: int) { print!(b); };
+begin
+{
+  show(1);
+}
+end";
+      assert_eq!(
+          compile_error(source),
+          "ERROR: Function \"show(int)\" is already declared at line 2, col 6, redeclared at line 3, col 6"
+      );
+  }
+
+  #[test]
+  fn redeclared_variables_report_both_declarations() {
+      let globals = "program p;
+vars
+  x: int;
+  x: float;
+begin
+{
+  x = 1;
+}
+end";
+      assert_eq!(
+          compile_error(globals),
+          "ERROR: \"x\" is already declared as a variable at line 3, col 3, redeclared as a variable at line 4, col 3"
+      );
+
+      let params = "program p;
+void f(a: int, a: float) { print!(a); };
+begin
+{
+  f(1, 2.0);
+}
+end";
+      assert_eq!(
+          compile_error(params),
+          "ERROR: \"a\" is already declared as a parameter at line 2, col 8, redeclared as a parameter at line 2, col 16"
+      );
+
+      let locals = "program p;
+void f(a: int) {
+  vars a: float;
+  print!(a);
+};
+begin
+{
+  f(1);
+}
+end";
+      assert_eq!(
+          compile_error(locals),
+          "ERROR: \"a\" is already declared as a parameter at line 2, col 8, redeclared as a variable at line 3, col 8"
+      );
+  }
+
+  #[test]
+  fn locals_that_shadow_globals_are_warnings() {
+      let source = "program p;
+vars x: int;
+void f() {
+  vars x: float;
+  x = 1.5;
+  print!(x);
+};
+begin
+{
+  f();
+}
+end";
+      let mut dusty_context = DustyContext::new();
+      dusty_context.options.warn_shadowing = true;
+      compile(source, &mut dusty_context).unwrap();
+      assert_eq!(
+          dusty_context.warnings,
+          ["WARNING: Local \"x\" in \"f()\" shadows the global variable declared at line 2, col 6 (line 4, col 8)"]
+      );
+  }
+}
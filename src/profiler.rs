@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+use super::structs::dusty_context::DustyContext;
+
+// Source lines shown in the hot spots of the report
+const HOT_SPOTS: usize = 10;
+
+// Totals of a function, the inclusive time counts the calls it made and the
+// exclusive time doesn't
+#[derive(Default)]
+struct FunctionProfile {
+  calls: u64,
+  quads: u64,
+  inclusive: Duration,
+  exclusive: Duration,
+}
+
+// Call being timed
+struct Frame {
+  function: String,
+  start: Instant,
+  children: Duration, // Time spent in the calls it made
+}
+
+// Executions and times collected by the VM while it runs, global counts as a
+// function called once
+pub struct Profile {
+  pub quad_counts: Vec<u64>, // Times every quadruple was executed
+  functions: HashMap<String, FunctionProfile>,
+  frames: Vec<Frame>,
+}
+
+impl Default for Profile {
+  fn default() -> Self {
+      Self::new()
+  }
+}
+
+impl Profile {
+  pub fn new() -> Self {
+      let mut profile = Profile {
+          quad_counts: Vec::new(),
+          functions: HashMap::new(),
+          frames: Vec::new(),
+      };
+      profile.enter("global");
+      profile
+  }
+
+  // Quadruple about to be executed by the function on top of the call stack
+  pub fn count(&mut self, pointer: usize, function: &str) {
+      if self.quad_counts.len() <= pointer {
+          self.quad_counts.resize(pointer + 1, 0);
+      }
+      self.quad_counts[pointer] += 1;
+      self.functions.entry(function.to_string()).or_default().quads += 1;
+  }
+
+  pub fn enter(&mut self, function: &str) {
+      self.functions.entry(function.to_string()).or_default().calls += 1;
+      self.frames.push(Frame {
+          function: function.to_string(),
+          start: Instant::now(),
+          children: Duration::ZERO,
+      });
+  }
+
+  pub fn exit(&mut self) {
+      let Some(frame) = self.frames.pop() else {
+          return;
+      };
+      let elapsed = frame.start.elapsed();
+      // The time of recursive calls is already inside the outermost one
+      let recursive = self.frames.iter().any(|caller| caller.function == frame.function);
+      let function = self.functions.get_mut(&frame.function).unwrap();
+      if !recursive {
+          function.inclusive += elapsed;
+      }
+      function.exclusive += elapsed.saturating_sub(frame.children);
+      if let Some(caller) = self.frames.last_mut() {
+          caller.children += elapsed;
+      }
+  }
+
+  // Functions by exclusive time, then the source lines that executed the most
+  // quadruples. Calls still running (the program stopped with an error) are
+  // closed first
+  pub fn report(&mut self, dusty_context: &DustyContext, path: &str) -> String {
+      while !self.frames.is_empty() {
+          self.exit();
+      }
+      let total: u64 = self.quad_counts.iter().sum();
+      let mut report = format!("Profile: {} quadruples executed\n\n", total);
+
+      report.push_str(&format!("{:<40} {:>8} {:>10} {:>12} {:>12}\n", "function", "calls", "quads", "inclusive", "exclusive"));
+      let mut functions: Vec<_> = self.functions.iter().collect();
+      functions.sort_by(|(a_name, a), (b_name, b)| b.exclusive.cmp(&a.exclusive).then(a_name.cmp(b_name)));
+      for (name, function) in functions {
+          report.push_str(&format!(
+              "{:<40} {:>8} {:>10} {:>12} {:>12}\n",
+              name,
+              function.calls,
+              function.quads,
+              format!("{:.3?}", function.inclusive),
+              format!("{:.3?}", function.exclusive),
+          ));
+      }
+
+      // Quadruples are grouped by the line of the statement or expression they come from
+      let mut lines: HashMap<(&str, usize), u64> = HashMap::new();
+      for (pointer, count) in self.quad_counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+          if let Some(location) = dusty_context.quad_locations.get(pointer) {
+              *lines.entry((location.module.as_str(), location.span.line)).or_default() += count;
+          }
+      }
+      let mut lines: Vec<_> = lines.into_iter().collect();
+      lines.sort_by(|(a_line, a), (b_line, b)| b.cmp(a).then(a_line.cmp(b_line)));
+
+      report.push_str(&format!("\nHot spots\n{:>10}  line\n", "quads"));
+      let mut sources: HashMap<&str, Vec<String>> = HashMap::new();
+      for ((module, line), count) in lines.into_iter().take(HOT_SPOTS) {
+          let file = match dusty_context.modules.get(module) {
+              Some(file) => file.display().to_string(),
+              None => path.to_string(),
+          };
+          let source = sources.entry(module).or_insert_with(|| {
+              fs::read_to_string(&file).unwrap_or_default().lines().map(|line| line.to_string()).collect()
+          });
+          let text = source.get(line - 1).map_or("", |text| text.trim());
+          report.push_str(&format!("{:>10}  {}:{}  {}\n", count, file, line, text));
+      }
+      report
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compile;
+  use crate::structs::vm_options::VmOptions;
+  use crate::virtual_machine::VirtualMachine;
+
+  const SOURCE: &str = "program p;
+vars total: int;
+void count(n: int) {
+  total = total + 1;
+  if (n > 0) {
+    count(n - 1);
+  };
+};
+begin
+{
+  total = 0;
+  count(4);
+}
+end";
+
+  fn profiled() -> (DustyContext, Profile) {
+      let mut dusty_context = DustyContext::new();
+      compile(SOURCE, &mut dusty_context).unwrap();
+      let mut virtual_machine = VirtualMachine::new(&dusty_context).unwrap();
+      virtual_machine.profile = Some(Profile::new());
+      virtual_machine.run(&dusty_context, &VmOptions::new()).unwrap();
+      (dusty_context, virtual_machine.profile.unwrap())
+  }
+
+  #[test]
+  fn calls_and_quadruples_are_counted_per_function() {
+      let (dusty_context, profile) = profiled();
+      let count = &profile.functions["count(int)"];
+      assert_eq!(count.calls, 5);
+      assert_eq!(profile.functions["global"].calls, 1);
+      let total: u64 = profile.functions.values().map(|function| function.quads).sum();
+      assert_eq!(total, profile.quad_counts.iter().sum::<u64>());
+      // The body of count runs once per call
+      let body = dusty_context.func_dir["count(int)"].location as usize;
+      assert_eq!(profile.quad_counts[body], 5);
+  }
+
+  #[test]
+  fn recursive_calls_are_timed_once_and_lines_are_reported() {
+      let (dusty_context, mut profile) = profiled();
+      let report = profile.report(&dusty_context, "count.dusty");
+      let count = &profile.functions["count(int)"];
+      assert!(count.inclusive <= profile.functions["global"].inclusive);
+      assert!(count.exclusive <= count.inclusive);
+      assert!(report.starts_with(&format!("Profile: {} quadruples executed", profile.quad_counts.iter().sum::<u64>())));
+      assert!(report.contains("count.dusty:4  "));
+  }
+}
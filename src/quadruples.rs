@@ -1,931 +1,603 @@
-use colored::*;
-
 use super::structs::{
+  ast::*,
   var_info::VarInfo,
-  dusty_context::{DustyContext, Stage},
-  parser::Rule,
-  function_info::FunctionInfo,
+  diagnostic::Diagnostic,
+  dusty_context::DustyContext,
+  source_location::SourceLocation,
 };
 
-pub fn generate_quadruples(
-  pair: pest::iterators::Pair<Rule>,
-  stage: Stage,
-  dusty_context: &mut DustyContext
-) {
-  // println!("Processing rule: {:#?} in stage {:#?}, parent rule: {:#?}, currrent func: {:#?}, line: {:#?}, col: {:#?}",
-  //     pair.as_rule(), stage, dusty_context.parent_rules.last().unwrap(), dusty_context.current_func,
-  //     pair.as_span().start_pos().line_col().0,
-  //     pair.as_span().start_pos().line_col().1
-  // );
-
-  match (pair.as_rule(), &stage) {
-      // Process beginKeyword ----------------------------
-      (Rule::beginKeyword, Stage::Before) => {
-          // println!("  token BEGIN found:");
-        //   println!("  Filling initial GOTO quad");
-          dusty_context.quadruples[0][3].memory = dusty_context.quad_data.quad_counter as u32;
-          dusty_context.quadruples[0][3].name = dusty_context.quad_data.quad_counter.to_string();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process beginKeyword ----------------------------
-
-      // Process ID --------------------------------------
-      (Rule::id, Stage::Before) => {
-          // println!("  Token ID found: {:#?}", pair.as_str());
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::id, Stage::During) => {
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::program => {
-                  // println!("  Adding global scope to function directory"); // #1 Add global scope during program name
-                  dusty_context.func_dir.insert("global".to_string(), FunctionInfo::new(0));
-                  dusty_context.current_func = "global".to_string();
-              }
-              Rule::vars => {
-                  // println!("  Adding variable stack to add to directory after knowing its type"); // #2 Add variable to stack at ID in VARS
-                  dusty_context.id_stack.push(pair.as_str().to_string());                  
-              }
-              Rule::funcs => {
-                  // println!("  Adding function scope to function directory"); // #3 Add function scope during function name
-                  dusty_context.func_dir.insert(pair.as_str().to_string(), FunctionInfo::new(0));
-                  dusty_context.current_func = pair.as_str().to_string();
-              }
-              Rule::id_type_list => {
-                  // println!("  Adding ID to stack to add to directory after knowing its type"); // #4 Add ID to stack at ID_LIST
-                  dusty_context.id_stack.push(pair.as_str().to_string());
-              }
-              Rule::assign => {
-                  // Quad generation
-                  if dusty_context.contains_id(pair.as_str()) {
-                      let var = dusty_context.func_dir.get(&dusty_context.current_func).unwrap().get(pair.as_str()).unwrap();
-
-                      dusty_context.quad_data.operand_stack.push(var.clone());
-
-                      // println!("{:#?}", dusty_context.quad_data.operand_stack);
-                  } else if dusty_context.id_in_global_scope(pair.as_str()) {
-                      let var = dusty_context.func_dir.get("global").unwrap().get(pair.as_str()).unwrap();
-
-                      dusty_context.quad_data.operand_stack.push(var.clone());
-
-                      // println!("{:#?}", dusty_context.quad_data.operand_stack);
-                  } else {
-                      panic!("ERROR: ID \"{}\" not found in current context", pair.as_str());
-                  }
-              }
-              Rule::func_call => {
-                  // println!("  Generate GOSUB quad to call function"); // #8 Generate GOSUB quad to call function
-                  if !dusty_context.func_dir.contains_key(pair.as_str()) {
-                      let function_name = pair.as_str();
-                      let error_message = format!("ERROR: Function \"{}\" was not declared", function_name.red());
-                      panic!("{:}", error_message.red());
-                  }
-                  dusty_context.current_call = pair.as_str().to_string();
-                  dusty_context.generate_era_quad(pair.as_str());
-              }
-              _ => {
-                  if !dusty_context.contains_id(pair.as_str()) && !dusty_context.id_in_global_scope(pair.as_str()) {
-                      panic!("ERROR: ID \"{}\" not found in current context \"{}\", line: {}, col: {}",
-                          pair.as_str(),
-                          dusty_context.current_func,
-                          pair.as_span().start_pos().line_col().0,
-                          pair.as_span().start_pos().line_col().1
-                      );
-                  } else {
-                      // println!("  ID \"{}\" was found in current context", pair.as_str());
-                  }
-              }
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::id, Stage::After) => {
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::value => {
-                  if dusty_context.contains_id(pair.as_str()) {
-                      // println!("  (#1) Adding ID and type to operand stack in factor"); // #1.1 Add ID and type to operand stack in FACTOR
-                      let var = dusty_context.func_dir.get(&dusty_context.current_func).unwrap().get(pair.as_str()).unwrap().clone();
-                      dusty_context.quad_data.operand_stack.push(var);
-                  } else {
-                      // println!("  (#1) Adding global ID and type to operand stack in factor"); // #1.1 Add ID and type to operand stack in FACTOR
-                      let var = dusty_context.func_dir.get("global").unwrap().get(pair.as_str()).unwrap().clone();
-                      dusty_context.quad_data.operand_stack.push(var);
-                  }
-                  // println!("  Operand stack: {:?}", dusty_context.quad_data.operand_stack);
-              }
-              _ => {}
-          }
-          // println!("\n");
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process ID --------------------------------------
-
-
-      // Process Vars ------------------------------------
-      (Rule::vars, Stage::Before) => {
-          // println!("  Sintactic rule VARS found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::vars);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::vars, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::vars, Stage::After) => {
-          // println!("func_dir after vars: {:#?}", dusty_context.func_dir);
-          // println!("\n");
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process Vars ------------------------------------
-      
-
-      // Process typeVar ---------------------------------
-      (Rule::typeVar, Stage::Before) => {
-          // println!("  Sintactic rule TYPEVAR found: {:#?}", pair.as_str());
-          dusty_context.current_type = pair.as_str().to_string();
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::typeVar, Stage::During) => {
-          // println!("ID stack: {:#?}", dusty_context.id_stack);
-          // println!("Add all pending ids to the current scope and set them to current type");
-          while let Some(id) = dusty_context.id_stack.pop() {
-              if dusty_context.contains_id(&id) {
-                  panic!("ERROR: id {} already exists in current context (typevar)", id);
-              } else {
-                  // Create variable Info
-                  let var_type = dusty_context.current_type.clone();
-                  let base = dusty_context.quad_data.get_memory_segment(&var_type, &dusty_context.current_func, "regular");
-                  let counter = dusty_context.func_dir.get_mut(&dusty_context.current_func).unwrap().get_counter(&var_type, "regular");
-                  
-                  // println!("Adding id {} to {} as {} in {}", id, dusty_context.current_func, dusty_context.current_type, base+counter);
-                  
-                  // Insert variable to function directory
-                  dusty_context.func_dir
-                      .get_mut(&dusty_context.current_func)
-                      .unwrap()
-                      .insert(id.clone(), dusty_context.current_type.clone(), base+counter);
-
-                  // Increase counter
-                  dusty_context.func_dir
-                      .get_mut(&dusty_context.current_func)
-                      .unwrap()
-                      .add_to_counter(&dusty_context.current_type, "regular");
-              }
-          }
-          // println!("func_dir: {:#?}", dusty_context.func_dir);
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::typeVar, Stage::After) => {
-          // println!("\n");
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::id_type_list => {
-                 dusty_context.func_dir.get_mut(&dusty_context.current_func).unwrap().add_param(dusty_context.current_type.clone());
-              }
-              _ => {}
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process typeVar ---------------------------------
-
-
-      // Process id_list ---------------------------------
-      (Rule::id_list, Stage::Before) => {
-          // println!("  Sintactic rule ID_LIST found: {:#?}", pair.as_str());
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process id_list ---------------------------------
-
-
-      // Process Functions -------------------------------
-      (Rule::funcs, Stage::Before) => {
-          // println!("  Sintactic rule FUNCTION found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::funcs);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::funcs, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::funcs, Stage::After) => {
-          // println!("\n");
-          dusty_context.parent_rules.pop();
-          dusty_context.current_func = "global".to_string();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process Functions -------------------------------
-
-
-      // Process parameters ------------------------------
-      (Rule::parameters, Stage::Before) => {
-          // println!("  Sintactic rule PARAMETERS found: {:#?}", pair.as_str());
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process parameters ------------------------------
-
-
-      // Process func_body -------------------------------
-      (Rule::func_body, Stage::Before) => {
-          // println!("  Sintactic rule FUNC_BODY found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::func_body);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::func_body, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::func_body, Stage::After) => {
-          // println!("\n");
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process func_body -------------------------------
-
-
-      // Process func_call -------------------------------
-      (Rule::func_call, Stage::Before) => {
-          // println!("  Sintactic rule FUNC_CALL found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::func_call);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::func_call, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::func_call, Stage::After) => {
-          // println!("\n");
-          dusty_context.parent_rules.pop();
-          dusty_context.quad_data.param_counter = 0;
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process func_call -------------------------------
-
-
-      // Process id_type_list ----------------------------
-      (Rule::id_type_list, Stage::Before) => {
-          // println!("  Sintactic rule ID_TYPE_LIST found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::id_type_list);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::id_type_list, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::id_type_list, Stage::After) => {
-          // println!("\n");
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process id_type_list ----------------------------
+// Lowers the checked syntax tree to quadruples. Variables get their addresses
+// here, every symbol keeps its VarInfo for the uses that come after it
+pub fn generate_quadruples(program: &Program, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  generate_input_quadruples(&program.module, &program.body, dusty_context)?;
+  dusty_context.generate_end_quad();
+  locate(program.end, dusty_context);
+  Ok(())
+}
 
+// Appends the quadruples of the declarations and the body, without the end
+// quad, so the REPL can run them after the ones of the previous inputs
+pub fn generate_input_quadruples(module: &Module, body: &Block, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  dusty_context.current_func = "global".to_string();
+
+  // Jump over the functions to the start of the program
+  let main_jump = dusty_context.generate_goto_quad();
+  locate(body.span, dusty_context);
+  lower_module(module, dusty_context)?;
+
+  dusty_context.fill_jump(main_jump, dusty_context.quad_data.quad_counter);
+  // Global initializers run before the body of the program
+  while let Some((quad, location)) = dusty_context.init_quadruples.pop_front() {
+      dusty_context.quadruples.push_back(quad);
+      dusty_context.quad_locations.push(location);
+      dusty_context.quad_data.quad_counter += 1;
+  }
+  lower_block(body, dusty_context)?;
+  check_segments(body.span, dusty_context)
+}
 
-      // Process body ------------------------------------
-      (Rule::body, Stage::Before) => {
-          // println!("  Sintactic rule BODY found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::body);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::body, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
+// Addresses past the end of a segment would be read by the VM from the next
+// one, the main program is reported at its body
+fn check_segments(body_span: Span, dusty_context: &DustyContext) -> Result<(), Diagnostic> {
+  let config = &dusty_context.quad_data.memmory_config;
+  let segment_size = |segment: usize| config[segment][1] - config[segment][0] + 1;
+
+  let mut functions: Vec<_> = dusty_context.func_dir.iter().collect();
+  functions.sort_by_key(|(name, _)| name.as_str());
+  for (name, function_info) in functions {
+      let (first_segment, function, span) = match name.as_str() {
+          "global" => (0, "main", body_span),
+          _ => (4, name.as_str(), function_info.span),
+      };
+      let resources = &function_info.resources;
+      let counters = [
+          ("int variables", resources.int_size()),
+          ("float variables", resources.float_size()),
+          ("int temporals", resources.temp_i_count),
+          ("float temporals", resources.temp_f_count),
+      ];
+      for (segment, (kind, count)) in (first_segment..).zip(counters) {
+          if count > segment_size(segment) {
+              let (line, col) = span.start();
+              return Err(Diagnostic::new(
+                  format!("ERROR: \"{}\" needs {} {}, the limit is {}, line: {}, col: {}", function, count, kind, segment_size(segment), line, col),
+                  span,
+              ));
           }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::body, Stage::After) => {
-          // println!("\n");
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
       }
-      // Process body ------------------------------------
-
+  }
 
-      // Process statement -------------------------------
-      (Rule::statement, Stage::Before) => {
-          // println!("\n");
-          // println!("  Sintactic rule STATEMENT found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::statement);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::statement, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
+  let constants = ["int", "float", "string"];
+  for (index, kind) in constants.iter().enumerate() {
+      if dusty_context.constants[index] > segment_size(8 + index) {
+          return Err(Diagnostic::new(
+              format!("ERROR: The program needs {} {} constants, the limit is {}", dusty_context.constants[index], kind, segment_size(8 + index)),
+              body_span,
+          ));
       }
-      (Rule::statement, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process statement -------------------------------
+  }
+  Ok(())
+}
 
+// The quadruples generated since the last call come from the node at the
+// span. Nodes are located after their children, which keep their own spans
+fn locate(span: Span, dusty_context: &mut DustyContext) {
+  let location = SourceLocation::new(span, dusty_context.current_module.clone());
+  let count = dusty_context.quadruples.len();
+  dusty_context.quad_locations.resize(count, location);
+}
 
-      // Process while -----------------------------------
-      (Rule::while_loop, Stage::Before) => {
-          // println!("  Sintactic rule WHILE found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::while_loop);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::while_loop, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
+fn lower_module(module: &Module, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  for import in module.imports.iter() {
+      if let Some(imported) = &import.module {
+          // Statements of the imported file are located with its namespace
+          let previous_module = std::mem::replace(&mut dusty_context.current_module, imported.namespace.clone());
+          let result = lower_module(imported, dusty_context);
+          dusty_context.current_module = previous_module;
+          result.map_err(|e| e.in_file(imported.path.as_ref().unwrap()))?;
       }
-      (Rule::while_loop, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process while -----------------------------------
-
+  }
 
-      // Process doKeyword -------------------------------
-      (Rule::doKeyword, Stage::Before) => {
-          // println!("  token DO found:");
-          // println!("  (#?) Generate GOTO quad to start of while loop");
-          dusty_context.generate_gotof_quad();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process doKeyword -------------------------------
-      
-
-      // Process if --------------------------------------
-      (Rule::condition, Stage::Before) => {
-          // println!("  Sintactic rule CONDITION found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::condition);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::condition, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
+  // Variants are int constants typed with the enum
+  for type_decl in module.types.iter() {
+      if let TypeDecl::Enum(enum_decl) = type_decl {
+          for variant in enum_decl.variants.iter() {
+              let symbol = variant.symbol.unwrap();
+              let value = dusty_context.symbols[symbol].value.clone().unwrap();
+              let const_var = dusty_context.add_constant(&value, "int");
+              let symbol = &mut dusty_context.symbols[symbol];
+              symbol.var = Some(VarInfo::new(symbol.name.clone(), symbol.var_type.clone(), const_var.location));
           }
-          generate_quadruples(pair, Stage::After, dusty_context);
       }
-      (Rule::condition, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process if --------------------------------------
+  }
+  for const_decl in module.consts.iter() {
+      lower_const(const_decl, dusty_context);
+  }
 
+  // Initializers of global variables are moved to the start of the program
+  for var_decl in module.vars.iter() {
+      let init_start = dusty_context.quadruples.len();
+      lower_vars(var_decl, dusty_context)?;
+      let init_quadruples = dusty_context.quadruples.split_off(init_start);
+      let init_locations = dusty_context.quad_locations.split_off(init_start);
+      dusty_context.quad_data.quad_counter -= init_quadruples.len();
+      dusty_context.init_quadruples.extend(init_quadruples.into_iter().zip(init_locations));
+  }
 
-      // Process elseKeyword -----------------------------
-      (Rule::elseKeyword, Stage::Before) => {
-          // println!("  token rule ELSE found: {:#?}", pair.as_str());
-        //   println!("filling jump...");
-          dusty_context.fill_jump();
-          dusty_context.quad_data.jump_stack.push(dusty_context.quad_data.quad_counter);
-          dusty_context.generate_goto_quad();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process elseKeyword -----------------------------
+  for function in module.funcs.iter() {
+      lower_func(function, dusty_context)?;
+  }
+  Ok(())
+}
 
+fn lower_const(const_decl: &ConstDecl, dusty_context: &mut DustyContext) {
+  // Store the value in the constant segment and bind the name to its address
+  let symbol = const_decl.name.symbol.unwrap();
+  let value = dusty_context.symbols[symbol].value.clone().unwrap();
+  let const_type = dusty_context.symbols[symbol].var_type.clone();
+  let const_var = dusty_context.add_constant(&value, dusty_context.storage_type(&const_type));
+  let symbol = &mut dusty_context.symbols[symbol];
+  symbol.var = Some(VarInfo::new(symbol.name.clone(), const_type, const_var.location));
+}
 
-      // Process print -----------------------------------
-      (Rule::print, Stage::Before) => {
-          // println!("  Sintactic rule PRINT found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::print);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::print, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::print, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process print -----------------------------------
+fn lower_vars(var_decl: &VarDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  declare_vars(var_decl, dusty_context)?;
+  init_vars(var_decl, dusty_context);
+  Ok(())
+}
 
+fn declare_vars(var_decl: &VarDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  for id in var_decl.names.iter() {
+      let symbol = id.symbol.unwrap();
+      let (name, var_type) = (dusty_context.symbols[symbol].name.clone(), dusty_context.symbols[symbol].var_type.clone());
+      let var = dusty_context.declare_var(&name, &var_type, id.span)?;
+      dusty_context.symbols[symbol].var = Some(var);
+  }
+  Ok(())
+}
 
-      // Process print_element ---------------------------
-      (Rule::print_element, Stage::Before) => {
-          // println!("  Sintactic rule PRINT_ELEMENT found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::print_element);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::print_element, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::print_element, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          dusty_context.generate_print_quad();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
+fn init_vars(var_decl: &VarDecl, dusty_context: &mut DustyContext) {
+  if let Some(init) = &var_decl.init {
+      // Evaluate the initializer once and assign it to every id of the group
+      let value = lower_expr(init, dusty_context);
+      for id in var_decl.names.iter() {
+          let var = ident_var(id, dusty_context);
+          dusty_context.generate_assign_quad(&var, &value);
       }
-      // Process print_element ---------------------------
+      locate(var_decl.span, dusty_context);
+  }
+}
 
+fn lower_func(function: &FuncDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  dusty_context.current_func = function.signature.clone();
+
+  // Parameters take the first addresses of the function, records one per field
+  for (param, _) in function.params.iter() {
+      let symbol = param.symbol.unwrap();
+      let (name, var_type) = (dusty_context.symbols[symbol].name.clone(), dusty_context.symbols[symbol].var_type.clone());
+      let var = dusty_context.declare_var(&name, &var_type, param.span)?;
+      let locations: Vec<u32> = var.flatten().iter().map(|field| field.location).collect();
+      dusty_context.func_dir.get_mut(&function.signature).unwrap().param_locations.extend(locations);
+      dusty_context.symbols[symbol].var = Some(var);
+  }
+  dusty_context.func_dir.get_mut(&function.signature).unwrap().location = dusty_context.quad_data.quad_counter as u32;
 
-      // Process assignment ------------------------------
-      (Rule::assign, Stage::Before) => {
-          // println!("  Sintactic rule ASSIGNMENT found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::assign);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::assign, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::assign, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          if dusty_context.top_is_equals() {
-              // println!("  (#7) Execute #4 with =");
-              dusty_context.generate_assign_quad();
-          }
-          // dusty_context.debug_quad_gen();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process assignment ------------------------------
+  for const_decl in function.consts.iter() {
+      lower_const(const_decl, dusty_context);
+  }
+  for var_decl in function.vars.iter() {
+      lower_vars(var_decl, dusty_context)?;
+  }
+  for stmt in function.body.iter() {
+      lower_stmt(stmt, dusty_context)?;
+  }
 
+  dusty_context.generate_endfunc_quad();
+  locate(function.span, dusty_context);
+  dusty_context.current_func = "global".to_string();
+  Ok(())
+}
 
-      // Process expression ------------------------------
-      (Rule::expression, Stage::Before) => {
-          // println!("  Sintactic rule EXPRESSION found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::expression);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::expression, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::expression, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          if dusty_context.top_is_logical_operator() {
-              // println!("  (#6) Execute #4 with >, <, == or !=");
-              dusty_context.generate_full_quad();
-          }
+fn lower_block(block: &Block, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let counters = dusty_context.open_block();
+  for var_decl in block.vars.iter() {
+      declare_vars(var_decl, dusty_context)?;
+  }
+  // Sibling blocks and later iterations of a loop reuse the addresses, they
+  // must not look assigned to --check-uninit
+  dusty_context.generate_reset_quad("int", counters.0);
+  dusty_context.generate_reset_quad("float", counters.1);
+  for var_decl in block.vars.iter() {
+      init_vars(var_decl, dusty_context);
+  }
+  for stmt in block.stmts.iter() {
+      lower_stmt(stmt, dusty_context)?;
+  }
+  dusty_context.close_block(counters);
+  Ok(())
+}
 
-          // Parameters for function call
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::func_call => {
-                  // println!("  (#?) Generate PARAM quad for function call");
-                  dusty_context.generate_param_quad();
+fn lower_stmt(stmt: &Stmt, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let location = SourceLocation::new(stmt.span, dusty_context.current_module.clone());
+  dusty_context.statement_starts.insert(dusty_context.quadruples.len(), location);
+
+  match &stmt.kind {
+      StmtKind::Assign { target, value } => {
+          let value = lower_expr(value, dusty_context);
+          let target = ident_var(target, dusty_context);
+          dusty_context.generate_assign_quad(&target, &value);
+          locate(stmt.span, dusty_context);
+      }
+      StmtKind::If { condition, then_block, else_block } => {
+          let condition = lower_expr(condition, dusty_context);
+          let false_jump = dusty_context.generate_gotof_quad(&condition);
+          locate(stmt.span, dusty_context);
+          lower_block(then_block, dusty_context)?;
+          match else_block {
+              Some(else_block) => {
+                  let end_jump = dusty_context.generate_goto_quad();
+                  locate(stmt.span, dusty_context);
+                  dusty_context.fill_jump(false_jump, dusty_context.quad_data.quad_counter);
+                  lower_block(else_block, dusty_context)?;
+                  dusty_context.fill_jump(end_jump, dusty_context.quad_data.quad_counter);
               }
-              _ => {}
-          }
-
-          // dusty_context.debug_quad_gen();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process expression ------------------------------
-
-
-      // Process exp -------------------------------------
-      (Rule::exp, Stage::Before) => {
-          // println!("  Sintactic rule EXP found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::exp);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::exp, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::exp, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          if dusty_context.top_is_addition_or_subtraction() {
-              // println!("  (#4) Execute #4 with + or -");
-              dusty_context.generate_full_quad();
-          }
-          // dusty_context.debug_quad_gen();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process exp -------------------------------------
-
-
-      // Process term ------------------------------------
-      (Rule::term, Stage::Before) => {
-          // println!("  Sintactic rule TERM found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::term);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::term, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
+              None => dusty_context.fill_jump(false_jump, dusty_context.quad_data.quad_counter),
+          }
+      }
+      StmtKind::While { condition, body } => {
+          let start = dusty_context.quad_data.quad_counter;
+          let condition = lower_expr(condition, dusty_context);
+          let false_jump = dusty_context.generate_gotof_quad(&condition);
+          locate(stmt.span, dusty_context);
+          lower_block(body, dusty_context)?;
+          let start_jump = dusty_context.generate_goto_quad();
+          locate(stmt.span, dusty_context);
+          dusty_context.fill_jump(start_jump, start);
+          dusty_context.fill_jump(false_jump, dusty_context.quad_data.quad_counter);
+      }
+      StmtKind::Match { scrutinee, arms, default } => {
+          let scrutinee = lower_expr(scrutinee, dusty_context);
+          locate(stmt.span, dusty_context);
+          let mut exit_jumps = Vec::new();
+          for arm in arms.iter() {
+              // Compare with the pattern and jump to the next arm when it's different
+              let pattern = lower_expr(&arm.pattern, dusty_context);
+              let condition = dusty_context.generate_full_quad("==", scrutinee.clone(), pattern);
+              let false_jump = dusty_context.generate_gotof_quad(&condition);
+              locate(arm.span, dusty_context);
+              lower_block(&arm.body, dusty_context)?;
+
+              // Leave the match after the body
+              exit_jumps.push(dusty_context.generate_goto_quad());
+              locate(arm.span, dusty_context);
+              dusty_context.fill_jump(false_jump, dusty_context.quad_data.quad_counter);
+          }
+          if let Some(default) = default {
+              lower_block(default, dusty_context)?;
+          }
+          for exit_jump in exit_jumps {
+              dusty_context.fill_jump(exit_jump, dusty_context.quad_data.quad_counter);
+          }
+      }
+      StmtKind::Call(call) => {
+          lower_call(call, dusty_context);
+      }
+      StmtKind::Print(elements) => {
+          for element in elements.iter() {
+              let value = match element {
+                  PrintElement::Expr(expr) => lower_expr(expr, dusty_context),
+                  PrintElement::Str(string, _) => dusty_context.add_constant(&unescape(string), "string"),
+              };
+              dusty_context.generate_print_quad(&value);
+              locate(stmt.span, dusty_context);
           }
-          generate_quadruples(pair, Stage::After, dusty_context);
       }
-      (Rule::term, Stage::After) => {
-          if dusty_context.top_is_multiplication_or_division() {
-              // println!("  (#5) Execute #4 with * or /");
-              dusty_context.generate_full_quad();
-          }
-          // dusty_context.debug_quad_gen();
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process term ------------------------------------
-      
-
-      // Process factor ----------------------------------
-      (Rule::factor, Stage::Before) => {
-          // println!("  Sintactic rule FACTOR found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::factor);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::factor, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::factor, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process factor ----------------------------------
-
-
-      // Process value -----------------------------------
-      (Rule::value, Stage::Before) => {
-          // println!("  Sintactic rule VALUE found: {:#?}", pair.as_str());
-          dusty_context.parent_rules.push(Rule::value);
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::value, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::value, Stage::After) => {
-          dusty_context.parent_rules.pop();
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process value -----------------------------------
-
-
-      // Process operator --------------------------------
-      (Rule::operator, Stage::Before) => {
-          // println!("  token OPERATOR found: {:#?}", pair.as_str());
-          if dusty_context.top_is_multiplication_or_division() {
-              // println!("  (#10) (Encountered * or / but there is at least 1 that needs to be executed before... Execute #4 with * or /");
-              dusty_context.generate_full_quad();
-          }
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::operator, Stage::During) => {
-          // println!("  (#2) Push operator to operator stack");
-          dusty_context.quad_data.operator_stack.push(pair.as_str().to_string());
-          generate_quadruples(pair, Stage::Finished, dusty_context);
+  }
+  Ok(())
+}
+// Text of a string literal as it is printed, with the escapes replaced
+// Text of a string literal as it is printed, "\\n" becomes a new line
+fn unescape(string: &str) -> String {
+  let mut text = String::new();
+  let mut chars = string.chars();
+  while let Some(char) = chars.next() {
+      if char != '\\' {
+          text.push(char);
+          continue;
+      }
+      match chars.next() {
+          Some('n') => text.push('\n'),
+          Some('t') => text.push('\t'),
+          Some('r') => text.push('\r'),
+          Some('b') => text.push('\u{8}'),
+          Some('f') => text.push('\u{c}'),
+          Some('u') => {
+              let code: String = chars.by_ref().take(4).collect();
+              text.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+          }
+          Some(escaped) => text.push(escaped), // \", \\ and \/
+          None => {}
       }
-      // Process operator --------------------------------
+  }
+  text
+}
 
+// Returns the value of the call, "_" for void functions
+fn lower_call(call: &Call, dusty_context: &mut DustyContext) -> VarInfo {
+  if call.is_builtin {
+      // Builtin and host functions don't need ERA, their arguments are passed as they are computed
+      for (index, arg) in call.args.iter().enumerate() {
+          let mut arg = lower_expr(arg, dusty_context);
+          if arg.var_type == "int" && dusty_context.builtin_dir[&call.function].params[index] == "float" {
+              arg = dusty_context.generate_conversion_quad("float", arg);
+          }
+          dusty_context.generate_arg_quad(index, arg);
+          locate(call.span, dusty_context);
+      }
+      let result = dusty_context.generate_callb_quad(&call.function, call.args.len());
+      locate(call.span, dusty_context);
+      return result;
+  }
 
-      // Process sign ------------------------------------
-      (Rule::sign, Stage::Before) => {
-          // println!("  token SIGN found: {:#?}", pair.as_str());
-          if dusty_context.top_is_addition_or_subtraction() {
-              // println!("  (#11) (Encountered + or - but there is at least 1 that needs to be executed before... Execute #4 with + or -");
-              dusty_context.generate_full_quad();
-          }
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::sign, Stage::During) => {
-          // println!("  (#3) Push sign to operator stack");
-          dusty_context.quad_data.operator_stack.push(pair.as_str().to_string());
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process sign ------------------------------------
+  let args: Vec<VarInfo> = call.args.iter().map(|arg| lower_expr(arg, dusty_context)).collect();
+  dusty_context.generate_era_quad(&call.signature);
+  for (index, arg) in args.into_iter().enumerate() {
+      dusty_context.generate_param_quad(&call.signature, index, arg);
+  }
+  dusty_context.generate_gosub_quad(&call.signature);
+  locate(call.span, dusty_context);
+  VarInfo::new("_".to_string(), "void".to_string(), 0)
+}
 
+fn lower_expr(expr: &Expr, dusty_context: &mut DustyContext) -> VarInfo {
+  let value = match &expr.kind {
+      ExprKind::Int(value) => dusty_context.add_constant(value, "int"),
+      ExprKind::Float(value) => dusty_context.add_constant(value, "float"),
+      ExprKind::Name(id) => ident_var(id, dusty_context),
+      ExprKind::Group(operand) => lower_expr(operand, dusty_context),
+      ExprKind::Unary { sign, operand } => {
+          let operand = lower_expr(operand, dusty_context);
+          if sign != "-" {
+              return operand;
+          }
+          // -x is computed as 0 - x
+          let zero = dusty_context.add_constant("0", "int");
+          dusty_context.generate_full_quad("-", zero, operand)
+      }
+      ExprKind::Binary { operator, left, right } => {
+          let left = lower_expr(left, dusty_context);
+          let right = lower_expr(right, dusty_context);
+          dusty_context.generate_full_quad(operator, left, right)
+      }
+      ExprKind::Conversion { conversion, operand } => {
+          let operand = lower_expr(operand, dusty_context);
+          dusty_context.generate_conversion_quad(conversion, operand)
+      }
+      ExprKind::Call(call) => lower_call(call, dusty_context),
+  };
+  locate(expr.span, dusty_context);
+  value
+}
 
-      // Process logical_operator -------------------------
-      (Rule::comparator, Stage::Before) => {
-          // println!("  token COMPARATOR found: {:#?}", pair.as_str());
-          // println!("  (#4) Push logical operator to operator stack");
-          dusty_context.quad_data.operator_stack.push(pair.as_str().to_string());
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process logical_operator -------------------------
-
-      
-      // Process equals ----------------------------------
-      (Rule::equals, Stage::Before) => {
-          // println!("  token EQUALS found: {:#?}", pair.as_str());
-          // println!("  (#5) Push equals to operator stack");
-          dusty_context.quad_data.operator_stack.push(pair.as_str().to_string());
-          generate_quadruples(pair, Stage::Finished, dusty_context);
+// Address of a variable, constant or record field
+fn ident_var(id: &Ident, dusty_context: &DustyContext) -> VarInfo {
+  let var = dusty_context.symbols[id.symbol.unwrap()].var.clone().unwrap();
+  match &id.field {
+      Some(field) => {
+          let name = format!("{}.{}", var.name, field);
+          var.fields.into_iter().find(|field_var| field_var.name == name).unwrap()
       }
-      // Process equals ----------------------------------
+      None => var,
+  }
+}
 
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::compile;
+  use crate::structs::vm_options::VmOptions;
+  use crate::virtual_machine::VirtualMachine;
+
+  // Runs the program and reads the values of its globals
+  fn run_globals(source: &str, names: &[&str]) -> Vec<Option<String>> {
+      let mut dusty_context = DustyContext::new();
+      compile(source, &mut dusty_context).unwrap();
+      let mut virtual_machine = VirtualMachine::new(&dusty_context).unwrap();
+      virtual_machine.run(&dusty_context, &VmOptions::new()).unwrap();
+      names.iter().map(|name| {
+          // Fields are read as p.x
+          let variable = name.split('.').next().unwrap();
+          let symbol = dusty_context.symbols.iter()
+              .find(|symbol| symbol.name == variable && symbol.function == "global")
+              .unwrap();
+          let var = symbol.var.as_ref().unwrap();
+          let var = var.fields.iter().find(|field| field.name == *name).unwrap_or(var);
+          virtual_machine.read(var.location)
+      }).collect()
+  }
 
-      // Process open_parenthesis -------------------------
-      (Rule::openP, Stage::Before) => {
-          // println!("  token OPEN_PARENTHESIS found: {:#?}", pair.as_str());
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::openP, Stage::During) => {
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::factor => {
-                  // println!("  (#6) Push open parenthesis to operator stack");
-                  dusty_context.quad_data.operator_stack.push(pair.as_str().to_string());
-              }
-              Rule::while_loop => {
-                  // println!("  (#?) Push to jump stack");
-                  dusty_context.quad_data.jump_stack.push(dusty_context.quad_data.quad_counter);
-              }
-              _ => {}
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process open_parenthesis -------------------------
+  fn values(values: &[&str]) -> Vec<Option<String>> {
+      values.iter().map(|value| Some(value.to_string())).collect()
+  }
 
+  #[test]
+  fn string_constants_replace_the_escapes() {
+      let mut dusty_context = DustyContext::new();
+      let source = r#"program p;
+begin
+{
+  print!("say \"hi\"", "a\nb \u0041");
+}
+end"#;
+      compile(source, &mut dusty_context).unwrap();
+      assert!(dusty_context.const_dir.contains_key(&("string".to_string(), "say \"hi\"".to_string())));
+      assert!(dusty_context.const_dir.contains_key(&("string".to_string(), "a\nb A".to_string())));
+  }
 
-      // Process close_parenthesis ------------------------
-      (Rule::closeP, Stage::Before) => {
-          // println!("  token CLOSE_PARENTHESIS found: {:#?}", pair.as_str());
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::closeP, Stage::During) => {
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::factor => {
-                  // println!("  (#9) pop stack");
-                  dusty_context.quad_data.operator_stack.pop();
-                  println!("  {:#?}", dusty_context.quad_data.operator_stack);
-              }
-              Rule::condition => {
-                  // println!("  (#12) Generate incomplete GOTOF quad and push to jump stack");
-                  dusty_context.generate_gotof_quad();
-              }
-              Rule::func_call => {
-                  // println!("  (#?) Generate GOSUB quad to call function");
-                  // Check for correct number of parameters
-                  if dusty_context.quad_data.param_counter != dusty_context.func_dir.get(&dusty_context.current_call).unwrap().params.len() {
-                      let error_message = format!("ERROR: Function \"{}\" was called with {} parameters, expected {}. Line: {}, Col: {}",
-                          dusty_context.current_call.red(),
-                          dusty_context.quad_data.param_counter,
-                          dusty_context.func_dir.get(&dusty_context.current_call).unwrap().params.len(),
-                          pair.as_span().start_pos().line_col().0,
-                          pair.as_span().start_pos().line_col().1
-                      );
-                      panic!("{:}", error_message.red());
-                  } 
-                  dusty_context.generate_gosub_quad();
-              }
-              Rule::funcs => {
-                  // println!("  (#?) Assign quadruple location to the start of the function");
-                  dusty_context.func_dir.get_mut(&dusty_context.current_func).unwrap().location = dusty_context.quad_data.quad_counter as u32;
-              }
-              _ => {}
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process close_parenthesis ------------------------
+  #[test]
+  fn initializers_assign_globals_and_locals() {
+      let source = fs::read_to_string("src/tests/test5.dusty").unwrap();
+      // total is 5 + 20 + 20, plus the step of add(5)
+      assert_eq!(run_globals(&source, &["x", "y", "a", "b", "total"]), values(&["5", "2.5", "20", "20", "51"]));
+  }
 
+  #[test]
+  fn initializers_are_checked_like_assignments() {
+      let mut dusty_context = DustyContext::new();
+      let error = compile("program p; vars x: int = 2.5; begin { } end", &mut dusty_context).unwrap_err();
+      assert_eq!(error.to_string(), "ERROR: Type mismatch. Cannot assign float to int, line: 1, col: 26");
+  }
 
-      // Process cte -------------------------------------
-      (Rule::cte, Stage::Before) => {
-          // println!("  Sintactic rule CTE found: {:#?}", pair.as_str());
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::cte, Stage::During) => {
-          let inner_pairs = pair.clone().into_inner();
-          for inner_pair in inner_pairs {
-              generate_quadruples(
-                  inner_pair,
-                  Stage::Before,
-                  dusty_context
-              );
-          }
-          generate_quadruples(pair, Stage::After, dusty_context);
-      }
-      (Rule::cte, Stage::After) => {
-          // dusty_context.parent_rule = Rule::value;
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process cte -------------------------------------
-
-      
-      // Process cte_int ---------------------------------
-      (Rule::cte_int, Stage::Before) => {
-          // println!("  token CTE found: {:#?}", pair.as_str());
-          // println!("  (#1) Adding CTE to operand stack in factor");
-          if dusty_context.const_dir.contains_key(pair.as_str()) {
-              let const_var = dusty_context.const_dir.get(pair.as_str()).unwrap().clone();
-              dusty_context.quad_data.operand_stack.push(const_var);
-          } else {
-              let const_var = VarInfo::new(
-                  pair.as_str().to_string(),
-                  "int".to_string(),
-                  dusty_context.quad_data.get_memory_segment("int", "global", "constant") + dusty_context.constants[0],
-              );
-              dusty_context.const_dir.insert(pair.as_str().to_string(), const_var.clone());
-              dusty_context.quad_data.operand_stack.push(const_var);
-              dusty_context.constants[0] += 1;
-          }
-          // println!("  Operand stack: {:?}", dusty_context.quad_data.operand_stack);
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process cte_int ---------------------------------
-
-
-      // Process cte_float -------------------------------
-      (Rule::cte_float, Stage::Before) => {
-          // println!("  token CTE_FLOAT found: {:#?}", pair.as_str());
-          // println!("  (#1) Adding CTE_FLOAT to operand stack in factor");
-          if dusty_context.const_dir.contains_key(pair.as_str()) {
-              let const_var = dusty_context.const_dir.get(pair.as_str()).unwrap().clone();
-              dusty_context.quad_data.operand_stack.push(const_var);
-          } else {
-              let const_var = VarInfo::new(
-                  pair.as_str().to_string(),
-                  "float".to_string(),
-                  dusty_context.quad_data.get_memory_segment("float", "global", "constant") + dusty_context.constants[1],
-              );
-              dusty_context.const_dir.insert(pair.as_str().to_string(), const_var.clone());
-              dusty_context.quad_data.operand_stack.push(const_var);
-              dusty_context.constants[1] += 1;
-          }
-          // println!("  Operand stack: {:?}", dusty_context.quad_data.operand_stack);
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process cte_float -------------------------------
-
-
-      // Process string ----------------------------------
-      (Rule::string, Stage::Before) => {
-        //   println!("  token STRING found: {:#?}", pair.as_str());
-          if dusty_context.const_dir.contains_key(pair.as_str()) {
-              let const_var = dusty_context.const_dir.get(pair.as_str()).unwrap().clone();
-              dusty_context.quad_data.operand_stack.push(const_var);
-          } else {
-              let const_var = VarInfo::new(
-                  pair.as_str().to_string().trim_matches('\"').to_string(),
-                  "string".to_string(),
-                  dusty_context.quad_data.get_memory_segment("string", "global", "constant") + dusty_context.constants[2],
-              );
-              dusty_context.const_dir.insert(pair.as_str().to_string().trim_matches('\"').to_string(), const_var.clone());
-              dusty_context.quad_data.operand_stack.push(const_var);
-              dusty_context.constants[2] += 1;
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process string ----------------------------------
+  #[test]
+  fn block_variables_shadow_and_end_with_their_block() {
+      let source = fs::read_to_string("src/tests/test7.dusty").unwrap();
+      // The x of the if block doesn't change the global one
+      assert_eq!(run_globals(&source, &["x", "limit"]), values(&["1", "3"]));
+
+      let mut dusty_context = DustyContext::new();
+      let source = "program p;
+vars x: int;
+begin
+{
+  if (1 > 0) {
+    vars a: int = 5;
+    print!(a);
+  };
+  x = a;
+}
+end";
+      assert_eq!(
+          compile(source, &mut dusty_context).unwrap_err().to_string(),
+          "ERROR: ID \"a\" not found in current context \"global\", line: 9, col: 7"
+      );
+  }
 
+  #[test]
+  fn block_variables_are_unassigned_every_time_the_block_is_entered() {
+      // b reuses the address of a
+      let source = "program p;
+begin
+{
+  if (1 > 0) {
+    vars a: int = 5;
+    print!(a);
+  };
+  if (1 > 0) {
+    vars b: int;
+    print!(b);
+  };
+}
+end";
+      let mut dusty_context = DustyContext::new();
+      compile(source, &mut dusty_context).unwrap();
+      let mut options = VmOptions::new();
+      options.check_uninitialized = true;
+      let mut virtual_machine = VirtualMachine::new(&dusty_context).unwrap();
+      let failure = virtual_machine.run(&dusty_context, &options).unwrap_err();
+      assert_eq!(failure.error.to_string(), "RUNTIME ERROR: Variable \"b\" was read before being assigned");
+      let location = |name: &str| dusty_context.symbols.iter()
+          .find(|symbol| symbol.name == name)
+          .and_then(|symbol| symbol.var.as_ref())
+          .map(|var| var.location);
+      assert_eq!(location("a"), location("b"));
+  }
 
-      // Process delimiter -------------------------------
-      (Rule::delimiter, Stage::Before) => {
-          // println!("  token DELIMITER found: {:#?}", pair.as_str());
-          generate_quadruples(pair, Stage::During, dusty_context);
-      }
-      (Rule::delimiter, Stage::During) => {
-          match dusty_context.parent_rules.last().unwrap() {
-              Rule::condition => {
-                  // println!("  (#13) Complete GOTOF quad");
-                //   println!("filling jump...");
-                  dusty_context.fill_jump();
-              }
-              Rule::while_loop => {
-                  // println!("  (#?) Generate GOTO quad to start of while loop");
-                //   println!("filling jump while...");
-                  dusty_context.fill_while_start();
-                  dusty_context.generate_gotow_quad();
-                //   println!("filling jump while end...");
-                  dusty_context.fill_while_end();
-              }
-              Rule::funcs => {
-                  // println!("  (#?) Generate ENDFUNC to indicate functions end");
-                  dusty_context.generate_endfunc_quad();
-              }
-              Rule::program => {
-                  // println!("  (#?) Generate first GOTO quad to start of program");
-                  dusty_context.generate_goto_quad();
-              }
-              _ => {}
-          }
-          generate_quadruples(pair, Stage::Finished, dusty_context);
-      }
-      // Process delimiter -------------------------------
+  #[test]
+  fn records_are_copied_field_by_field() {
+      let source = fs::read_to_string("src/tests/test10.dusty").unwrap();
+      // q = p copies the fields and move(p) changes only its copy
+      assert_eq!(
+          run_globals(&source, &["p.x", "p.y", "q.x", "q.y", "c.row", "c.col", "c.weight"]),
+          values(&["1.5", "2", "1.5", "6", "2", "3", "3"])
+      );
+
+      let mut dusty_context = DustyContext::new();
+      compile(&source, &mut dusty_context).unwrap();
+      // Every field takes an address in the segment of its type
+      let cell = dusty_context.symbols.iter().find(|symbol| symbol.name == "c").unwrap().var.clone().unwrap();
+      let segments: Vec<u32> = cell.fields.iter().map(|field| field.location / 1000 * 1000).collect();
+      assert_eq!(segments, [1000, 1000, 3000]);
+  }
 
+  #[test]
+  fn match_runs_the_arm_of_the_value_or_the_default() {
+      let source = "program p;
+enum Light { Red, Yellow, Green };
+vars light: Light; first, second: int;
+begin
+{
+  light = Light.Yellow;
+  match (light) {
+    Light.Red => { first = 1; },
+    Light.Yellow => { first = 2; },
+    _ => { first = 3; }
+  };
+  match (first + 5) {
+    1 => { second = 1; },
+    _ => { second = 9; }
+  };
+}
+end";
+      assert_eq!(run_globals(source, &["light", "first", "second"]), values(&["1", "2", "9"]));
+  }
 
-      // Process endKeyword ------------------------------
-      (Rule::endKeyword, Stage::Before) => {
-          // println!("  token END found");
-          // println!("  Generating END quad");
-          dusty_context.generate_end_quad();
-      }
-      // Process endKeyword ------------------------------
+  #[test]
+  fn every_quadruple_keeps_where_it_comes_from() {
+      let source = "program p;
+vars x: float;
+void f(n: float) {
+  x = 10.0 / n;
+};
+begin
+{
+  x = 1.0;
+  f(0.0);
+}
+end";
+      let mut dusty_context = DustyContext::new();
+      compile(source, &mut dusty_context).unwrap();
+      assert_eq!(dusty_context.quad_locations.len(), dusty_context.quadruples.len());
+      let division = dusty_context.quadruples.iter().position(|quadruple| quadruple[0].name == "/").unwrap();
+      assert_eq!(dusty_context.quad_locations[division].span.start(), (4, 7));
+
+      // Runtime errors report the expression and the call of every frame
+      let mut virtual_machine = VirtualMachine::new(&dusty_context).unwrap();
+      let failure = virtual_machine.run(&dusty_context, &VmOptions::new()).unwrap_err();
+      assert_eq!(failure.to_string(), "RUNTIME ERROR: Division by zero, line: 4, col: 7
+    at f(float), line: 4, col: 7
+    at global, line: 9, col: 3");
+  }
 
+  #[test]
+  fn quadruples_of_imported_files_keep_their_module() {
+      let mut dusty_context = DustyContext::new();
+      crate::compile_file("src/tests/test12.dusty", &mut dusty_context).unwrap();
+      let area = dusty_context.func_dir.iter()
+          .find(|(signature, _)| signature.starts_with("geometry.area("))
+          .map(|(_, function_info)| function_info.location as usize)
+          .unwrap();
+      assert_eq!(dusty_context.quad_locations[area].module, "geometry");
+      assert_eq!(dusty_context.quad_locations[0].module, "");
+  }
 
-      // Anything else (move on to the next pair)
-      _ => {
-          // println!("...");
-      }
+  #[test]
+  fn functions_that_need_more_addresses_than_their_segment_are_errors() {
+      let mut dusty_context = DustyContext::new();
+      let program = compile("program p;\nvoid f() { print!(1); };\nbegin\n{\n  f();\n}\nend", &mut dusty_context).unwrap();
+      assert!(check_segments(program.body.span, &dusty_context).is_ok());
+
+      dusty_context.func_dir.get_mut("global").unwrap().resources.temp_i_count = 2100;
+      assert_eq!(
+          check_segments(program.body.span, &dusty_context).unwrap_err().to_string(),
+          "ERROR: \"main\" needs 2100 int temporals, the limit is 2000, line: 4, col: 1"
+      );
+      dusty_context.func_dir.get_mut("global").unwrap().resources.temp_i_count = 0;
+      dusty_context.func_dir.get_mut("f()").unwrap().resources.temp_f_count = 2001;
+      assert_eq!(
+          check_segments(program.body.span, &dusty_context).unwrap_err().to_string(),
+          "ERROR: \"f()\" needs 2001 float temporals, the limit is 2000, line: 2, col: 6"
+      );
   }
 }
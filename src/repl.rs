@@ -0,0 +1,188 @@
+use std::io::{self, BufRead, Write};
+
+use colored::*;
+
+use super::compile_input;
+use super::virtual_machine::VirtualMachine;
+use super::structs::{
+  dusty_context::DustyContext,
+  compile_options::CompileOptions,
+  symbol::SymbolKind,
+  vm_options::VmOptions,
+};
+
+const HELP: &str = "Declarations, statements and expressions are run as they are entered, expressions are printed
+  :vars   global variables and constants with their addresses and values
+  :funcs  declared functions and their first quadruple
+  :quads  quadruples generated so far
+  :help   this message
+  :quit   exit (or end of input)";
+
+// Compiles every input into the same context and resumes the VM from the
+// quadruples it added. Inputs that don't compile leave the context as it was
+pub fn run_repl(compile_options: CompileOptions, vm_options: VmOptions) {
+  let mut dusty_context = DustyContext::new();
+  dusty_context.options = compile_options;
+  compile_input("", &mut dusty_context).unwrap();
+  let mut virtual_machine = match VirtualMachine::new(&dusty_context) {
+      Ok(virtual_machine) => virtual_machine,
+      Err(error) => {
+          eprintln!("{}", error.to_string().red());
+          return;
+      }
+  };
+
+  println!("Dusty REPL, :help for the commands");
+  let stdin = io::stdin();
+  let mut lines = stdin.lock().lines();
+  while let Some(input) = read_input(&mut lines) {
+      let input = input.trim();
+      match input {
+          "" => continue,
+          ":quit" => break,
+          ":help" => println!("{}", HELP),
+          ":vars" => print_vars(&dusty_context, &virtual_machine),
+          ":funcs" => print_funcs(&dusty_context),
+          ":quads" => print_quads(&dusty_context),
+          _ if input.starts_with(':') => eprintln!("{}", format!("Unknown command {}, :help for the commands", input).red()),
+          _ => run_input(input, &mut dusty_context, &mut virtual_machine, &vm_options),
+      }
+  }
+}
+
+// Compiles and runs one input. Runtime errors stop only this input, the REPL keeps going
+fn run_input(input: &str, dusty_context: &mut DustyContext, virtual_machine: &mut VirtualMachine, vm_options: &VmOptions) {
+  let snapshot = dusty_context.clone();
+  if let Err(diagnostic) = compile_input(input, dusty_context) {
+      eprintln!("{}", diagnostic.to_string().red());
+      *dusty_context = snapshot;
+      return;
+  }
+  for warning in dusty_context.warnings.drain(..) {
+      eprintln!("{}", warning.yellow());
+  }
+
+  if let Err(error) = virtual_machine.load(dusty_context) {
+      eprintln!("{}", error.to_string().red());
+      return;
+  }
+  if let Err(failure) = virtual_machine.run(dusty_context, vm_options) {
+      eprintln!("{}", failure.to_string().red());
+      virtual_machine.reset(dusty_context);
+  }
+}
+
+// Reads lines until the braces and parentheses are closed, so functions and
+// blocks can span several lines
+fn read_input(lines: &mut impl Iterator<Item = io::Result<String>>) -> Option<String> {
+  let mut input = String::new();
+  loop {
+      print!("{}", if input.is_empty() { "dusty> " } else { "...> " });
+      io::stdout().flush().unwrap();
+      match lines.next() {
+          Some(Ok(line)) => input.push_str(&line),
+          _ if input.is_empty() => return None,
+          _ => return Some(input),
+      }
+      input.push('\n');
+
+      let depth = input.chars().fold(0, |depth, c| match c {
+          '{' | '(' => depth + 1,
+          '}' | ')' => depth - 1,
+          _ => depth,
+      });
+      if depth <= 0 {
+          return Some(input);
+      }
+  }
+}
+
+fn print_vars(dusty_context: &DustyContext, virtual_machine: &VirtualMachine) {
+  let Some(global_scope) = dusty_context.scopes.first() else {
+      return;
+  };
+  let mut symbols: Vec<usize> = global_scope.vars.values().copied().collect();
+  symbols.sort();
+  for symbol in symbols {
+      let symbol = &dusty_context.symbols[symbol];
+      if symbol.kind == SymbolKind::Variant {
+          continue;
+      }
+      // Records are shown field by field
+      for var in symbol.var.as_ref().unwrap().flatten() {
+          let value = virtual_machine.describe(&var, dusty_context);
+          println!("{} {}: {} = {} ({})", symbol.kind.as_str(), var.name, var.var_type, value, var.location);
+      }
+  }
+}
+
+fn print_funcs(dusty_context: &DustyContext) {
+  let mut functions: Vec<_> = dusty_context.func_dir.iter().filter(|(name, _)| *name != "global").collect();
+  functions.sort_by_key(|(_, function_info)| function_info.location);
+  for (signature, function_info) in functions {
+      println!("{} (quad {})", signature, function_info.location);
+  }
+}
+
+fn print_quads(dusty_context: &DustyContext) {
+  for (index, quad) in dusty_context.quadruples.iter().enumerate() {
+      let units: Vec<&str> = quad.iter().map(|unit| unit.name.as_str()).collect();
+      println!("{}) {}", index + 1, units.join(" "));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Runs the inputs in one session, like the REPL does
+  fn session(inputs: &[&str]) -> (DustyContext, VirtualMachine) {
+      let mut dusty_context = DustyContext::new();
+      compile_input("", &mut dusty_context).unwrap();
+      let mut virtual_machine = VirtualMachine::new(&dusty_context).unwrap();
+      for input in inputs {
+          run_input(input, &mut dusty_context, &mut virtual_machine, &VmOptions::new());
+      }
+      (dusty_context, virtual_machine)
+  }
+
+  fn global(dusty_context: &DustyContext, virtual_machine: &VirtualMachine, name: &str) -> Option<String> {
+      let symbol = dusty_context.symbols.iter().find(|symbol| symbol.name == name)?;
+      virtual_machine.read(symbol.var.as_ref()?.location)
+  }
+
+  #[test]
+  fn globals_and_functions_persist_across_inputs() {
+      let (dusty_context, virtual_machine) = session(&[
+          "vars x: int;",
+          "x = 20;",
+          "void twice() {\n  x = x * 2;\n};",
+          "twice();",
+          "x + 1",
+      ]);
+      assert_eq!(global(&dusty_context, &virtual_machine, "x").as_deref(), Some("40"));
+      assert!(dusty_context.func_dir.contains_key("twice()"));
+  }
+
+  #[test]
+  fn inputs_with_errors_leave_the_session_as_it_was() {
+      let (dusty_context, virtual_machine) = session(&[
+          "vars x: int;",
+          "x = 1;",
+          "vars y: int; x = z;",
+          "x = x / 0;",
+          "x = x + 1;",
+      ]);
+      // The declaration of y was discarded with the error, the division stopped only its input
+      assert!(dusty_context.symbols.iter().all(|symbol| symbol.name != "y"));
+      assert_eq!(global(&dusty_context, &virtual_machine, "x").as_deref(), Some("2"));
+  }
+
+  #[test]
+  fn inputs_continue_while_braces_are_open() {
+      let mut lines = ["void f() {", "  print!(1);", "};", "x = 1;"].into_iter().map(|line| Ok(line.to_string()));
+      assert_eq!(read_input(&mut lines).as_deref(), Some("void f() {\n  print!(1);\n};\n"));
+      assert_eq!(read_input(&mut lines).as_deref(), Some("x = 1;\n"));
+      assert_eq!(read_input(&mut lines), None);
+  }
+}
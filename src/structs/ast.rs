@@ -0,0 +1,193 @@
+use std::path::PathBuf;
+
+// Typed syntax tree of a program, built from the pest tree by ast_builder.
+// Name resolution and type checking fill the annotation fields (symbol, ty,
+// function, signature) that lowering reads to generate the quadruples.
+
+// Line and col where a node starts and ends
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+impl Span {
+    pub fn start(&self) -> (usize, usize) {
+        (self.line, self.col)
+    }
+}
+
+#[derive(Debug)]
+pub struct Program {
+    pub name: Ident,
+    pub module: Module,
+    pub body: Block,
+    pub begin: Span, // begin and end keywords
+    pub end: Span,
+    pub span: Span,
+}
+
+// Declarations of the program or of an imported file
+#[derive(Debug, Default)]
+pub struct Module {
+    pub namespace: String, // Empty for the program
+    pub path: Option<PathBuf>, // File of an imported module
+    pub imports: Vec<Import>,
+    pub types: Vec<TypeDecl>,
+    pub consts: Vec<ConstDecl>,
+    pub vars: Vec<VarDecl>,
+    pub vars_keyword: Span, // vars keyword of the globals
+    pub funcs: Vec<FuncDecl>,
+}
+
+#[derive(Debug)]
+pub struct Import {
+    pub path: String,
+    pub module: Option<Module>, // Loaded before name resolution, None when the file was already imported
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum TypeDecl {
+    Record(RecordDecl),
+    Enum(EnumDecl),
+}
+
+#[derive(Debug)]
+pub struct RecordDecl {
+    pub name: Ident,
+    pub fields: Vec<(Ident, Ident)>, // Field name and type
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct EnumDecl {
+    pub name: Ident,
+    pub variants: Vec<Ident>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct ConstDecl {
+    pub name: Ident,
+    pub const_type: Ident,
+    pub value: Expr,
+    pub span: Span,
+}
+
+// <id_list> : <type> (= <expression>)?
+#[derive(Debug)]
+pub struct VarDecl {
+    pub names: Vec<Ident>,
+    pub var_type: Ident,
+    pub init: Option<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct FuncDecl {
+    pub name: Ident,
+    pub params: Vec<(Ident, Ident)>, // Parameter name and type
+    pub consts: Vec<ConstDecl>,
+    pub vars: Vec<VarDecl>,
+    pub body: Vec<Stmt>,
+    pub signature: String, // e.g. show(int), set by name resolution
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub vars: Vec<VarDecl>,
+    pub stmts: Vec<Stmt>,
+    pub span: Span,
+}
+
+// A name as written in the source, plain (a) or dotted (p.x, geometry.PI)
+#[derive(Debug, Clone)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+    pub symbol: Option<usize>, // Declaration in the symbol table
+    pub field: Option<String>, // Accessed field when the symbol is a record
+}
+
+impl Ident {
+    pub fn new(name: String, span: Span) -> Self {
+        Ident {
+            name,
+            span,
+            symbol: None,
+            field: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum StmtKind {
+    Assign { target: Ident, value: Expr },
+    If { condition: Expr, then_block: Block, else_block: Option<Block> },
+    While { condition: Expr, body: Block },
+    Match { scrutinee: Expr, arms: Vec<MatchArm>, default: Option<Block> },
+    Call(Call),
+    Print(Vec<PrintElement>),
+}
+
+#[derive(Debug)]
+pub struct MatchArm {
+    pub pattern: Expr,
+    pub body: Block,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum PrintElement {
+    Expr(Expr),
+    Str(String, Span), // Text between the quotes, escapes as written
+}
+
+#[derive(Debug)]
+pub struct Call {
+    pub name: Ident,
+    pub args: Vec<Expr>,
+    pub function: String, // Builtin or (qualified) function name, set by name resolution
+    pub is_builtin: bool,
+    pub signature: String, // Chosen overload, set by type checking
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: String, // Set by type checking
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Int(String),
+    Float(String),
+    Name(Ident),
+    Group(Box<Expr>), // ( <expression> )
+    Unary { sign: String, operand: Box<Expr> },
+    Binary { operator: String, left: Box<Expr>, right: Box<Expr> },
+    Conversion { conversion: String, operand: Box<Expr> },
+    Call(Box<Call>),
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr {
+            kind,
+            ty: String::new(),
+            span,
+        }
+    }
+}
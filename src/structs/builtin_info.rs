@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use super::value::Value;
+use super::runtime_error::RuntimeError;
+
+// Function registered by the host application
+pub type NativeFunction = Rc<dyn Fn(&[Value]) -> Result<Value, RuntimeError>>;
+
+#[derive(Clone)]
+pub struct BuiltinInfo {
+    pub id: u32,
+    pub params: Vec<String>,
+    pub return_type: String,
+    pub native: Option<NativeFunction>, // None for the builtins implemented by the VM
+}
+
+impl BuiltinInfo {
+    pub fn new(id: u32, params: &[&str], return_type: &str) -> Self {
+        BuiltinInfo {
+            id,
+            params: params.iter().map(|param| param.to_string()).collect(),
+            return_type: return_type.to_string(),
+            native: None,
+        }
+    }
+}
+
+impl fmt::Debug for BuiltinInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BuiltinInfo")
+            .field("id", &self.id)
+            .field("params", &self.params)
+            .field("return_type", &self.return_type)
+            .field("native", &self.native.is_some())
+            .finish()
+    }
+}
+
+// Builtin function directory, the VM dispatches CALLB quads by name
+pub fn builtin_dir() -> HashMap<String, BuiltinInfo> {
+    let mut map = HashMap::new();
+    map.insert(String::from("abs"), BuiltinInfo::new(1, &["float"], "float"));
+    map.insert(String::from("min"), BuiltinInfo::new(2, &["float", "float"], "float"));
+    map.insert(String::from("max"), BuiltinInfo::new(3, &["float", "float"], "float"));
+    map.insert(String::from("pow"), BuiltinInfo::new(4, &["float", "float"], "float"));
+    map.insert(String::from("sqrt"), BuiltinInfo::new(5, &["float"], "float"));
+    map.insert(String::from("sin"), BuiltinInfo::new(6, &["float"], "float"));
+    map.insert(String::from("cos"), BuiltinInfo::new(7, &["float"], "float"));
+    map.insert(String::from("exp"), BuiltinInfo::new(8, &["float"], "float"));
+    map.insert(String::from("log"), BuiltinInfo::new(9, &["float"], "float"));
+    map
+}
@@ -0,0 +1,20 @@
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    pub strict_types: bool, // When false, int values can be assigned to float variables
+    pub warn_shadowing: bool, // Warn when a local declaration hides a global one
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompileOptions {
+    pub fn new() -> Self {
+        CompileOptions {
+            strict_types: true,
+            warn_shadowing: false,
+        }
+    }
+}
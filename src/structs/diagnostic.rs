@@ -0,0 +1,39 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::ast::Span;
+
+// Compile error, the message already mentions where it happened
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub file: Option<PathBuf>, // Imported file where it happened, None for the compiled file
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Self {
+        Diagnostic {
+            message,
+            span,
+            file: None,
+        }
+    }
+
+    // Errors of an imported file keep the innermost file
+    pub fn in_file(mut self, path: &Path) -> Self {
+        if self.file.is_none() {
+            self.file = Some(path.to_path_buf());
+        }
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{} (in file \"{}\")", self.message, file.display()),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
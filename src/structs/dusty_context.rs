@@ -1,104 +1,334 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use super::function_info::FunctionInfo;
+use super::builtin_info::{BuiltinInfo, builtin_dir};
 use super::quadruple_unit::QuadrupleUnit;
 use super::var_info::VarInfo;
-use super::parser::Rule;
+use super::record_info::RecordInfo;
+use super::enum_info::EnumInfo;
 use super::quad_data::QuadData;
-
-#[derive(Debug)]
+use super::scope::Scope;
+use super::compile_options::CompileOptions;
+use super::value::Value;
+use super::runtime_error::RuntimeError;
+use super::symbol::{Symbol, SymbolKind};
+use super::diagnostic::Diagnostic;
+use super::source_location::SourceLocation;
+use super::ast::Span;
+
+#[derive(Debug, Clone)]
 pub struct DustyContext {
     pub func_dir: HashMap<String, FunctionInfo>, // Function-variable scope directory
-    pub const_dir: HashMap<String, VarInfo>, // Constant directory
-    pub parent_rules: Vec<Rule>,
-    pub current_type: String,
+    pub builtin_dir: HashMap<String, BuiltinInfo>, // Builtin and host function directory
+    pub const_dir: HashMap<(String, String), VarInfo>, // Constant directory, by type and text
+    pub type_dir: HashMap<String, RecordInfo>, // Record type directory
+    pub enum_dir: HashMap<String, EnumInfo>, // Enum type directory
+    pub symbols: Vec<Symbol>, // Declared variables, parameters, constants and enum variants
     pub current_func: String,
-    pub current_call: String,
-    pub id_stack: Vec<String>,
+    pub current_module: String, // Namespace of the imported file being compiled, empty for the program
+    pub import_stack: Vec<PathBuf>, // Files being compiled, the current one last
+    pub modules: HashMap<String, PathBuf>, // Imported namespaces and their files
+    pub scopes: Vec<Scope>, // Lexical scopes, innermost last
     pub quad_data: QuadData,
     pub quadruples: VecDeque<[QuadrupleUnit; 4]>,
-    pub constants: [u32; 3]
+    pub init_quadruples: VecDeque<([QuadrupleUnit; 4], SourceLocation)>, // Global initializers, emitted at begin
+    pub quad_locations: Vec<SourceLocation>, // Statement or expression that generated every quadruple
+    pub statement_starts: BTreeMap<usize, SourceLocation>, // First quadruple (0-based) of every statement
+    pub constants: [u32; 3],
+    pub options: CompileOptions,
+    pub warnings: Vec<String>
 }
 
-#[derive(Debug)]
-pub enum Stage {
-    Before,
-    During,
-    After,
-    Finished,
+impl Default for DustyContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DustyContext {
     pub fn new() -> Self {
         DustyContext {
             func_dir: HashMap::new(),
+            builtin_dir: builtin_dir(),
             const_dir: HashMap::new(),
-            id_stack: Vec::new(),
-            parent_rules: vec![Rule::program],
+            type_dir: HashMap::new(),
+            enum_dir: HashMap::new(),
+            symbols: Vec::new(),
+            scopes: Vec::new(),
             current_func: String::new(),
-            current_call: String::new(),
-            current_type: String::new(),
+            current_module: String::new(),
+            import_stack: Vec::new(),
+            modules: HashMap::new(),
             quad_data: QuadData::new(),
             quadruples: VecDeque::new(),
-            constants: [0,0,0]
+            init_quadruples: VecDeque::new(),
+            statement_starts: BTreeMap::new(),
+            quad_locations: Vec::new(),
+            constants: [0,0,0],
+            options: CompileOptions::new(),
+            warnings: Vec::new()
+        }
+    }
+
+    // Registers a host function, must be called before compiling the program.
+    // Parameter and return types are "int" or "float" ("void" to return nothing)
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        params: &[&str],
+        return_type: &str,
+        function: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static
+    ) -> Result<(), String> {
+        if self.builtin_dir.contains_key(name) {
+            return Err(format!("ERROR: Function \"{}\" is already declared", name));
+        }
+        for param in params {
+            if *param != "int" && *param != "float" {
+                return Err(format!("ERROR: Invalid parameter type {} for function \"{}\"", param, name));
+            }
         }
+        if return_type != "int" && return_type != "float" && return_type != "void" {
+            return Err(format!("ERROR: Invalid return type {} for function \"{}\"", return_type, name));
+        }
+
+        let mut native = BuiltinInfo::new(self.builtin_dir.len() as u32 + 1, params, return_type);
+        native.native = Some(Rc::new(function));
+        self.builtin_dir.insert(name.to_string(), native);
+        Ok(())
     }
 
+    // Checks if the id was declared in the innermost scope
     pub fn contains_id(&self, id: &str) -> bool {
-        self.func_dir.get(&self.current_func).unwrap().contains_key(id)
+        self.scopes.last().unwrap().vars.contains_key(id)
     }
 
-    pub fn id_in_global_scope(&self, id: &str) -> bool {
-        self.func_dir.get("global").unwrap().contains_key(id)
+    // Finds the closest declaration of the id, inner scopes shadow outer ones.
+    // Record fields are found as "record.field", with the symbol of the record
+    pub fn lookup_id(&self, id: &str) -> Option<(usize, Option<String>)> {
+        match id.rsplit_once('.') {
+            Some((record, field)) => self.lookup_id(record)
+                .filter(|(symbol, accessed)| {
+                    accessed.is_none() && self.type_dir.get(&self.symbols[*symbol].var_type)
+                        .is_some_and(|record| record.contains_field(field))
+                })
+                .map(|(symbol, _)| (symbol, Some(field.to_string())))
+                // Enum variants and names of other files are bound with their qualified name
+                .or_else(|| self.find_in_scopes(id).map(|symbol| (symbol, None))),
+            None => self.find_in_scopes(id).map(|symbol| (symbol, None)),
+        }
+    }
+
+    // Inside an imported file its own globals are found without the namespace
+    fn find_in_scopes(&self, id: &str) -> Option<usize> {
+        let qualified = self.qualify(id);
+        self.scopes.iter().rev().find_map(|scope| scope.vars.get(id).or_else(|| scope.vars.get(&qualified)).copied())
     }
-    
-    pub fn top_is_multiplication_or_division(&self) -> bool {
-        self.quad_data.operator_stack.last() == Some(&String::from("*")) || self.quad_data.operator_stack.last() == Some(&String::from("/"))
+
+    // Adds the namespace of the file being compiled to the name
+    pub fn qualify(&self, name: &str) -> String {
+        if self.current_module.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.current_module, name)
+        }
     }
 
-    pub fn top_is_addition_or_subtraction(&self) -> bool {
-        self.quad_data.operator_stack.last() == Some(&String::from("+")) || self.quad_data.operator_stack.last() == Some(&String::from("-"))
+    // Name of a global declaration, functions, globals, constants and types of imported files are qualified
+    pub fn declaration_name(&self, name: &str) -> String {
+        if self.current_func == "global" && self.scopes.len() == 1 {
+            self.qualify(name)
+        } else {
+            name.to_string()
+        }
     }
 
-    pub fn top_is_logical_operator(&self) -> bool {
-        self.quad_data.operator_stack.last() == Some(&String::from("==")) || self.quad_data.operator_stack.last() == Some(&String::from("!="))
-        || self.quad_data.operator_stack.last() == Some(&String::from(">")) || self.quad_data.operator_stack.last() == Some(&String::from("<"))
+    pub fn resolve_function(&self, name: &str) -> String {
+        let qualified = self.qualify(name);
+        if self.get_overloads(&qualified).is_empty() { name.to_string() } else { qualified }
     }
 
-    pub fn top_is_equals(&self) -> bool {
-        self.quad_data.operator_stack.last() == Some(&String::from("="))
+    // Declared signatures of the function, e.g. show(int) and show(float) for show
+    pub fn get_overloads(&self, name: &str) -> Vec<&String> {
+        let mut overloads: Vec<&String> = self.func_dir.keys()
+            .filter(|signature| signature.split_once('(').is_some_and(|(function, _)| function == name))
+            .collect();
+        overloads.sort();
+        overloads
     }
 
-    pub fn generate_full_quad(&mut self) {
-        // Get Operands and Operator
-        let right_operand = self.quad_data.operand_stack.pop()
-            .expect("ERROR: Missing right operand");
-        let left_operand = self.quad_data.operand_stack.pop()
-            .expect("ERROR: Missing left operand");
-        let operator = self.quad_data.operator_stack.pop()
-            .expect("ERROR: Missing operator");
+    // Picks the overload for the argument types, an exact match is preferred over int to float widening
+    pub fn resolve_overload(&self, name: &str, arg_types: &[String]) -> Result<String, String> {
+        let candidates: Vec<&String> = self.get_overloads(name).into_iter()
+            .filter(|signature| self.func_dir.get(*signature).unwrap().params.len() == arg_types.len())
+            .collect();
 
-        // Check if types are compatible
-        if self.quad_data.semantic_cube.get_result_type(&left_operand.var_type, &right_operand.var_type, &operator) == "error" {
-            panic!("ERROR: Type mismatch. Cannot use {} with {} and {}.", operator, left_operand.var_type, right_operand.var_type);
+        if let Some(signature) = candidates.iter().find(|signature| self.func_dir.get(**signature).unwrap().params == arg_types) {
+            return Ok(signature.to_string());
         }
+        let compatible: Vec<&String> = candidates.into_iter()
+            .filter(|signature| {
+                self.func_dir.get(*signature).unwrap().params.iter()
+                    .zip(arg_types)
+                    .all(|(param, arg)| self.can_assign(param, arg))
+            })
+            .collect();
+
+        match compatible.len() {
+            1 => Ok(compatible[0].clone()),
+            0 => Err(format!("ERROR: No overload of function \"{}\" matches ({})", name, arg_types.join(", "))),
+            _ => Err(format!("ERROR: Call to function \"{}\" with ({}) is ambiguous between {}",
+                name,
+                arg_types.join(", "),
+                compatible.iter().map(|signature| signature.as_str()).collect::<Vec<&str>>().join(" and ")
+            )),
+        }
+    }
 
-        // get result type
-        let result_type = self.quad_data.semantic_cube.get_result_type(&left_operand.var_type, &right_operand.var_type, &operator);
+    pub fn resolve_type(&self, name: &str) -> String {
+        let qualified = self.qualify(name);
+        if self.type_exists(&qualified) { qualified } else { name.to_string() }
+    }
 
-        // Get temp variable information
-        let name = format!("t{}", self.quad_data.temp_counter);
-        let var_type = result_type.clone();
-        let base = self.quad_data.get_memory_segment(&var_type, &self.current_func, "temporal");
-        let counter = self.func_dir.get_mut(&self.current_func).unwrap().get_counter(&var_type, "temporal");
-        // let kind: String = "temporal".to_string();
+    // Checks that the name is new in the innermost scope
+    pub fn check_declaration(&mut self, name: &str, kind: SymbolKind, span: Span) -> Result<(), Diagnostic> {
+        if let Some(original) = self.scopes.last().unwrap().vars.get(name).map(|symbol| &self.symbols[*symbol]) {
+            return Err(Diagnostic::new(format!("ERROR: \"{}\" is already declared as a {} at line {}, col {}, redeclared as a {} at line {}, col {}",
+                name, original.kind.as_str(), original.span.line, original.span.col, kind.as_str(), span.line, span.col
+            ), span));
+        }
+
+        if self.options.warn_shadowing && self.scopes.len() > 1 {
+            let global_scope = &self.scopes[0];
+            if let Some(global) = global_scope.vars.get(name).or_else(|| global_scope.vars.get(&self.qualify(name))).map(|symbol| &self.symbols[*symbol]) {
+                self.warnings.push(format!("WARNING: Local \"{}\" in \"{}\" shadows the global {} declared at line {}, col {} (line {}, col {})",
+                    name,
+                    if self.current_func == "global" { "main" } else { &self.current_func },
+                    global.kind.as_str(),
+                    global.span.line,
+                    global.span.col,
+                    span.line,
+                    span.col
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Adds the symbol to the table and binds its name in the innermost scope
+    pub fn declare(&mut self, symbol: Symbol) -> usize {
+        let id = self.symbols.len();
+        self.scopes.last_mut().unwrap().vars.insert(symbol.name.clone(), id);
+        self.symbols.push(symbol);
+        id
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // Counters of the current function when a block is opened
+    pub fn open_block(&self) -> (u32, u32) {
+        let resources = &self.func_dir.get(&self.current_func).unwrap().resources;
+        (resources.int_count, resources.float_count)
+    }
+
+    // Sibling blocks reuse the addresses of the variables of the closed block
+    pub fn close_block(&mut self, counters: (u32, u32)) {
+        let resources = &mut self.func_dir.get_mut(&self.current_func).unwrap().resources;
+        resources.int_peak = resources.int_size();
+        resources.float_peak = resources.float_size();
+        resources.int_count = counters.0;
+        resources.float_count = counters.1;
+    }
+
+    pub fn declare_var(&mut self, id: &str, var_type: &str, span: Span) -> Result<VarInfo, Diagnostic> {
+        match var_type {
+            "int" | "float" => Ok(self.allocate_var(id, var_type)),
+            _ if self.enum_dir.contains_key(var_type) => Ok(self.allocate_var(id, var_type)),
+            _ => {
+                // Fields take consecutive addresses in the segment of their type
+                let Some(record) = self.type_dir.get(var_type) else {
+                    let (line, col) = span.start();
+                    return Err(Diagnostic::new(
+                        format!("ERROR: Type \"{}\" was not declared, line: {}, col: {}", var_type, line, col),
+                        span
+                    ));
+                };
+                let fields = record.fields.clone();
+                let mut var = VarInfo::new(id.to_string(), var_type.to_string(), 0);
+                for (field, field_type) in fields {
+                    var.fields.push(self.allocate_var(&format!("{}.{}", id, field), &field_type));
+                }
+                var.location = var.fields[0].location;
+                Ok(var)
+            }
+        }
+    }
+
+    fn allocate_var(&mut self, name: &str, var_type: &str) -> VarInfo {
+        let storage_type = self.storage_type(var_type);
+        let base = self.quad_data.get_memory_segment(storage_type, &self.current_func, "regular");
+        let counter = self.func_dir.get(&self.current_func).unwrap().get_counter(storage_type, "regular");
+        self.func_dir.get_mut(&self.current_func).unwrap().add_to_counter(storage_type, "regular");
+        VarInfo::new(name.to_string(), var_type.to_string(), base + counter)
+    }
+
+    pub fn type_exists(&self, var_type: &str) -> bool {
+        var_type == "int" || var_type == "float" || self.type_dir.contains_key(var_type) || self.enum_dir.contains_key(var_type)
+    }
 
-        let result = VarInfo::new(name.clone(), var_type.clone(), base+counter);
+    // Type of the segment where values of the type are stored, enums are ints
+    pub fn storage_type<'a>(&self, var_type: &'a str) -> &'a str {
+        if self.enum_dir.contains_key(var_type) {
+            "int"
+        } else {
+            var_type
+        }
+    }
+
+    // Number of addresses taken by a value of the type
+    pub fn type_size(&self, var_type: &str) -> usize {
+        match self.type_dir.get(var_type) {
+            Some(record) => record.fields.len(),
+            None => 1,
+        }
+    }
+
+    pub fn add_constant(&mut self, value: &str, var_type: &str) -> VarInfo {
+        // The same text can be a string and a number, each one has its own address
+        let key = (var_type.to_string(), value.to_string());
+        if let Some(const_var) = self.const_dir.get(&key) {
+            return const_var.clone();
+        }
+        let index = match var_type {
+            "int" => 0,
+            "float" => 1,
+            _ => 2,
+        };
+        let const_var = VarInfo::new(
+            value.to_string(),
+            var_type.to_string(),
+            self.quad_data.get_memory_segment(var_type, "global", "constant") + self.constants[index],
+        );
+        self.const_dir.insert(key, const_var.clone());
+        self.constants[index] += 1;
+        const_var
+    }
+
+    pub fn generate_full_quad(&mut self, operator: &str, left_operand: VarInfo, right_operand: VarInfo) -> VarInfo {
+        let result_type = self.quad_data.semantic_cube.get_result_type(&left_operand.var_type, &right_operand.var_type, operator);
+        let result = self.new_temp(&result_type);
 
         self.quadruples.push_back([
             QuadrupleUnit::new(
-                operator.clone(),
-                self.quad_data.operator_config.get(&operator).unwrap().clone() as u32
+                operator.to_string(),
+                *self.quad_data.operator_config.get(operator).unwrap() as u32
             ),
             QuadrupleUnit::new(
                 left_operand.name,
@@ -110,61 +340,93 @@ impl DustyContext {
             ),
             QuadrupleUnit::new(
                 result.name.clone(),
-                result.location.clone()
+                result.location
             )
         ]);
         self.quad_data.quad_counter += 1;
+        result
+    }
+
+    pub fn new_temp(&mut self, var_type: &str) -> VarInfo {
+        let name = format!("t{}", self.quad_data.temp_counter);
+        let base = self.quad_data.get_memory_segment(var_type, &self.current_func, "temporal");
+        let counter = self.func_dir.get(&self.current_func).unwrap().get_counter(var_type, "temporal");
         self.quad_data.temp_counter += 1;
-        
-        self.quad_data.operand_stack.push(result.clone());
-        self.func_dir.get_mut(&self.current_func).unwrap().add_to_counter(&self.current_type, "temporal");
+        self.func_dir.get_mut(&self.current_func).unwrap().add_to_counter(var_type, "temporal");
+        VarInfo::new(name, var_type.to_string(), base + counter)
     }
 
-    pub fn generate_assign_quad(&mut self) {
-        let right_operand = self.quad_data.operand_stack.pop()
-            .expect("ERROR: Missing right operand");
-        let left_operand = self.quad_data.operand_stack.pop()
-            .expect("ERROR: Missing left operand");
-        let operator = self.quad_data.operator_stack.pop()
-            .expect("ERROR: Missing operator");
+    // Checks the "=" entry of the semantic cube, allowing int to float widening when types are not strict
+    pub fn can_assign(&self, target_type: &str, value_type: &str) -> bool {
+        self.quad_data.semantic_cube.get_result_type(target_type, value_type, "=") != "error"
+            || (target_type == value_type && self.type_dir.contains_key(target_type))
+            || (!self.options.strict_types && target_type == "float" && value_type == "int")
+    }
 
-        if self.quad_data.semantic_cube.get_result_type(&left_operand.var_type, &right_operand.var_type, &operator) == "error" {
-            panic!("ERROR: Type mismatch. Cannot assign {} to {}", right_operand.var_type, left_operand.var_type);
-        }
+    pub fn generate_conversion_quad(&mut self, conversion: &str, operand: VarInfo) -> VarInfo {
+        let result_type = if conversion == "float" { "float" } else { "int" };
+        let result = self.new_temp(result_type);
 
         self.quadruples.push_back([
             QuadrupleUnit::new(
-                operator.clone(),
-                self.quad_data.operator_config.get(&operator).unwrap().clone() as u32
+                conversion.to_string(),
+                *self.quad_data.operator_config.get(conversion).unwrap() as u32
             ),
             QuadrupleUnit::new(
-                right_operand.name,
-                right_operand.location
+                operand.name,
+                operand.location
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
                 0
             ),
             QuadrupleUnit::new(
-                left_operand.name,
-                left_operand.location
+                result.name.clone(),
+                result.location
             )
         ]);
         self.quad_data.quad_counter += 1;
+        result
     }
 
-    pub fn generate_print_quad(&mut self) {
-        let element = self.quad_data.operand_stack.last();
-        if element.is_none() {
-            panic!("ERROR: Missing element to print");
+    pub fn generate_assign_quad(&mut self, target: &VarInfo, value: &VarInfo) {
+        // Records are copied field by field
+        for (left_operand, right_operand) in target.flatten().into_iter().zip(value.flatten()) {
+            self.quadruples.push_back([
+                QuadrupleUnit::new(
+                    "=".to_string(),
+                    *self.quad_data.operator_config.get("=").unwrap() as u32
+                ),
+                QuadrupleUnit::new(
+                    right_operand.name,
+                    right_operand.location
+                ),
+                QuadrupleUnit::new(
+                    "_".to_string(),
+                    0
+                ),
+                QuadrupleUnit::new(
+                    left_operand.name,
+                    left_operand.location
+                )
+            ]);
+            self.quad_data.quad_counter += 1;
         }
+    }
+
+    pub fn generate_print_quad(&mut self, element: &VarInfo) {
+        // Enums are printed with the variant name
+        let enum_name = match self.enum_dir.contains_key(&element.var_type) {
+            true => element.var_type.clone(),
+            false => "_".to_string(),
+        };
         self.quadruples.push_back([
             QuadrupleUnit::new(
                 "print".to_string(),
-                self.quad_data.operator_config.get("print").unwrap().clone() as u32
+                *self.quad_data.operator_config.get("print").unwrap() as u32
             ),
             QuadrupleUnit::new(
-                "_".to_string(),
+                enum_name,
                 0
             ),
             QuadrupleUnit::new(
@@ -172,27 +434,23 @@ impl DustyContext {
                 0
             ),
             QuadrupleUnit::new(
-                element.unwrap().name.clone(),
-                element.unwrap().location
+                element.name.clone(),
+                element.location
             )
         ]);
         self.quad_data.quad_counter += 1;
     }
 
-    pub fn generate_gotof_quad(&mut self) {
-        // Check if top of operand stack is a int
-        if self.quad_data.operand_stack.last().unwrap().var_type != "int" {
-            panic!("ERROR: Expected int but got {}", self.quad_data.operand_stack.last().unwrap().var_type);
-        }
-
+    // Returns the number of the quad, its destination is filled later with fill_jump
+    pub fn generate_gotof_quad(&mut self, condition: &VarInfo) -> usize {
         self.quadruples.push_back([
             QuadrupleUnit::new(
                 "gotof".to_string(),
-                self.quad_data.operator_config.get("gotof").unwrap().clone() as u32
+                *self.quad_data.operator_config.get("gotof").unwrap() as u32
             ),
             QuadrupleUnit::new(
-                format!("t{}", self.quad_data.temp_counter - 1),
-                self.quad_data.operand_stack.last().unwrap().location
+                condition.name.clone(),
+                condition.location
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
@@ -203,15 +461,16 @@ impl DustyContext {
                 0
             )
         ]);
-        self.quad_data.jump_stack.push(self.quad_data.quad_counter);
         self.quad_data.quad_counter += 1;
+        self.quad_data.quad_counter - 1
     }
 
-    pub fn generate_goto_quad(&mut self) {
+    // Returns the number of the quad, its destination is filled later with fill_jump
+    pub fn generate_goto_quad(&mut self) -> usize {
         self.quadruples.push_back([
             QuadrupleUnit::new(
                 "goto".to_string(),
-                self.quad_data.operator_config.get("goto").unwrap().clone() as u32
+                *self.quad_data.operator_config.get("goto").unwrap() as u32
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
@@ -226,15 +485,15 @@ impl DustyContext {
                 0
             )
         ]);
-        self.quad_data.jump_stack.push(self.quad_data.quad_counter);
         self.quad_data.quad_counter += 1;
+        self.quad_data.quad_counter - 1
     }
 
-    pub fn generate_gotow_quad(&mut self) {
+    pub fn generate_endfunc_quad(&mut self) {
         self.quadruples.push_back([
             QuadrupleUnit::new(
-                "goto".to_string(),
-                self.quad_data.operator_config.get("goto").unwrap().clone() as u32
+                "endfunc".to_string(),
+                *self.quad_data.operator_config.get("endfunc").unwrap() as u32
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
@@ -252,11 +511,11 @@ impl DustyContext {
         self.quad_data.quad_counter += 1;
     }
 
-    pub fn generate_endfunc_quad(&mut self) {
+    pub fn generate_era_quad(&mut self, function: &str) {
         self.quadruples.push_back([
             QuadrupleUnit::new(
-                "endfunc".to_string(),
-                self.quad_data.operator_config.get("endfunc").unwrap().clone() as u32
+                "era".to_string(),
+                *self.quad_data.operator_config.get("era").unwrap() as u32
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
@@ -267,76 +526,104 @@ impl DustyContext {
                 0
             ),
             QuadrupleUnit::new(
-                "_".to_string(),
-                0
+                function.to_string(),
+                self.func_dir.get(function).unwrap().location
             )
         ]);
         self.quad_data.quad_counter += 1;
     }
 
-    pub fn generate_era_quad(&mut self, func_name: &str) {
+    pub fn generate_param_quad(&mut self, function: &str, index: usize, param: VarInfo) {
+        let function_info = self.func_dir.get(function).unwrap();
+
+        // Records are passed by value, one PARAM per field
+        let slot: usize = function_info.params[..index].iter()
+            .map(|param_type| self.type_size(param_type))
+            .sum();
+        let locations = function_info.param_locations[slot..].to_vec();
+        for (field, location) in param.flatten().into_iter().zip(locations) {
+            self.quadruples.push_back([
+                QuadrupleUnit::new(
+                    "param".to_string(),
+                    *self.quad_data.operator_config.get("param").unwrap() as u32
+                ),
+                QuadrupleUnit::new(
+                    field.name,
+                    field.location
+                ),
+                QuadrupleUnit::new(
+                    "_".to_string(),
+                    0
+                ),
+                QuadrupleUnit::new(
+                    format!("param{}", index),
+                    location
+                )
+            ]);
+            self.quad_data.quad_counter += 1;
+        }
+    }
+
+    pub fn generate_arg_quad(&mut self, index: usize, arg: VarInfo) {
         self.quadruples.push_back([
             QuadrupleUnit::new(
-                "era".to_string(),
-                self.quad_data.operator_config.get("era").unwrap().clone() as u32
+                "param".to_string(),
+                *self.quad_data.operator_config.get("param").unwrap() as u32
             ),
             QuadrupleUnit::new(
-                "_".to_string(),
-                0
+                arg.name,
+                arg.location
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
                 0
             ),
             QuadrupleUnit::new(
-                func_name.to_string(),
-                self.func_dir.get(&self.current_call).unwrap().location
+                format!("arg{}", index),
+                index as u32
             )
         ]);
         self.quad_data.quad_counter += 1;
     }
 
-    pub fn generate_param_quad(&mut self) { 
-        let param = self.quad_data.operand_stack.last().unwrap();
-
-        // Check for parameter overflow
-        if self.quad_data.param_counter > self.func_dir.get(&self.current_call).unwrap().params.len() {
-            panic!("ERROR: Too many parameters for function \"{}\"", self.current_call);
-        }
-
-        // Check current parameter type
-        let var_type = &self.func_dir.get(&self.current_call).unwrap().params[self.quad_data.param_counter];
-        if param.var_type != *var_type {
-            panic!("ERROR: Type mismatch. Expected {} but got {}", var_type, param.var_type);
-        }
+    // Returns the temporal with the result, void functions return "_"
+    pub fn generate_callb_quad(&mut self, name: &str, arg_count: usize) -> VarInfo {
+        let builtin = self.builtin_dir.get(name).unwrap();
+        let id = builtin.id;
+        let return_type = builtin.return_type.clone();
+        let result = if return_type == "void" {
+            VarInfo::new("_".to_string(), return_type, 0)
+        } else {
+            self.new_temp(&return_type)
+        };
 
         self.quadruples.push_back([
             QuadrupleUnit::new(
-                "param".to_string(),
-                self.quad_data.operator_config.get("param").unwrap().clone() as u32
+                "callb".to_string(),
+                *self.quad_data.operator_config.get("callb").unwrap() as u32
             ),
             QuadrupleUnit::new(
-                param.name.clone(),
-                param.location.clone()
+                name.to_string(),
+                id
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
-                0
+                arg_count as u32
             ),
             QuadrupleUnit::new(
-                format!("param{}", self.quad_data.param_counter),
-                self.quad_data.param_counter as u32
+                result.name.clone(),
+                result.location
             )
         ]);
         self.quad_data.quad_counter += 1;
-        self.quad_data.param_counter += 1;
+        result
     }
 
-    pub fn generate_gosub_quad(&mut self) {
+    pub fn generate_gosub_quad(&mut self, function: &str) {
         self.quadruples.push_back([
             QuadrupleUnit::new(
                 "gosub".to_string(),
-                self.quad_data.operator_config.get("gosub").unwrap().clone() as u32
+                *self.quad_data.operator_config.get("gosub").unwrap() as u32
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
@@ -347,18 +634,47 @@ impl DustyContext {
                 0
             ),
             QuadrupleUnit::new(
-                self.current_call.to_string(),
-                self.func_dir.get(&self.current_call).unwrap().location
+                function.to_string(),
+                self.func_dir.get(function).unwrap().location
             )
         ]);
         self.quad_data.quad_counter += 1;
     }
 
+    // Marks the variables of a block as unassigned every time it is entered,
+    // addresses from the counter of the type to its current value
+    pub fn generate_reset_quad(&mut self, var_type: &str, from_counter: u32) {
+        let base = self.quad_data.get_memory_segment(var_type, &self.current_func, "regular");
+        let count = self.func_dir.get(&self.current_func).unwrap().get_counter(var_type, "regular") - from_counter;
+        if count == 0 {
+            return;
+        }
+        self.quadruples.push_back([
+            QuadrupleUnit::new(
+                "reset".to_string(),
+                *self.quad_data.operator_config.get("reset").unwrap() as u32
+            ),
+            QuadrupleUnit::new(
+                "_".to_string(),
+                base + from_counter
+            ),
+            QuadrupleUnit::new(
+                count.to_string(),
+                count
+            ),
+            QuadrupleUnit::new(
+                "_".to_string(),
+                0
+            ),
+        ]);
+        self.quad_data.quad_counter += 1;
+    }
+
     pub fn generate_end_quad(&mut self) {
         self.quadruples.push_back([
             QuadrupleUnit::new(
                 "end".to_string(),
-                self.quad_data.operator_config.get("end").unwrap().clone() as u32
+                *self.quad_data.operator_config.get("end").unwrap() as u32
             ),
             QuadrupleUnit::new(
                 "_".to_string(),
@@ -376,31 +692,12 @@ impl DustyContext {
         self.quad_data.quad_counter += 1;
     }
 
-    pub fn fill_jump(&mut self) {
-        let jump = self.quad_data.jump_stack.pop().unwrap();
-        self.quadruples[jump - 1][3] = QuadrupleUnit::new(
-            format!("{}", self.quad_data.quad_counter), 
-            self.quad_data.quad_counter as u32
-        );
-        // self.quadruples[jump - 1][3] = format!("{}", self.quad_data.quad_counter);
-    }
-
-    pub fn fill_while_start(&mut self) {
-        let jump = self.quad_data.jump_stack.pop().unwrap();
+    // Sets the destination of a GOTO or GOTOF quad
+    pub fn fill_jump(&mut self, jump: usize, destination: usize) {
         self.quadruples[jump - 1][3] = QuadrupleUnit::new(
-            format!("{}", self.quad_data.quad_counter), 
-            (self.quad_data.quad_counter) as u32
+            format!("{}", destination),
+            destination as u32
         );
-        // self.quadruples[jump - 1][3] = format!("{}", self.quad_data.quad_counter+1);
-    }
-
-    pub fn fill_while_end(&mut self) {
-        let jump = self.quad_data.jump_stack.pop().unwrap();
-        self.quadruples[self.quad_data.quad_counter - 2][3] = QuadrupleUnit::new(
-            format!("{}", jump), 
-            jump as u32
-        );
-        // self.quadruples[self.quad_data.quad_counter - 2][3] = format!("{}", jump);
     }
 
     // pub fn print_quadruples_as_name(&self) {
@@ -426,4 +723,70 @@ impl DustyContext {
     //         counter += 1;
     //     }
     // }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::compile;
+    use crate::structs::vm_options::VmOptions;
+    use crate::virtual_machine::run_virtual_machine;
+
+    fn twice(args: &[Value]) -> Result<Value, RuntimeError> {
+        match args[0] {
+            Value::Int(value) => Ok(Value::Int(value * 2)),
+            _ => Err(RuntimeError::Native("expected an int".to_string())),
+        }
+    }
+
+    #[test]
+    fn register_native_rejects_repeated_names_and_other_types() {
+        let mut dusty_context = DustyContext::new();
+        assert_eq!(dusty_context.register_native("twice", &["int"], "int", twice), Ok(()));
+        assert_eq!(
+            dusty_context.register_native("twice", &["int"], "int", twice),
+            Err("ERROR: Function \"twice\" is already declared".to_string())
+        );
+        assert_eq!(
+            dusty_context.register_native("abs", &["float"], "float", twice),
+            Err("ERROR: Function \"abs\" is already declared".to_string())
+        );
+        assert_eq!(
+            dusty_context.register_native("half", &["string"], "int", twice),
+            Err("ERROR: Invalid parameter type string for function \"half\"".to_string())
+        );
+        assert_eq!(
+            dusty_context.register_native("half", &["int"], "Point", twice),
+            Err("ERROR: Invalid return type Point for function \"half\"".to_string())
+        );
+    }
+
+    #[test]
+    fn natives_are_called_like_builtins() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        let mut dusty_context = DustyContext::new();
+        dusty_context.register_native("twice", &["int"], "int", twice).unwrap();
+        dusty_context.register_native("record", &["int"], "void", move |args| {
+            recorded.borrow_mut().push(args[0]);
+            Ok(Value::Int(0))
+        }).unwrap();
+        compile("program p; begin { record(twice(21)); } end", &mut dusty_context).unwrap();
+        run_virtual_machine(&dusty_context, &VmOptions::new()).unwrap();
+        assert_eq!(*calls.borrow(), [Value::Int(42)]);
+
+        let error = compile("program p; vars x: int; begin { x = record(1); } end", &mut dusty_context).unwrap_err();
+        assert!(error.to_string().starts_with("ERROR: Function \"record\" is void and can't be used as a value"));
+    }
+
+    #[test]
+    fn native_errors_name_their_function() {
+        let mut dusty_context = DustyContext::new();
+        dusty_context.register_native("twice", &["float"], "float", twice).unwrap();
+        compile("program p; vars x: float; begin { x = twice(1.5); } end", &mut dusty_context).unwrap();
+        let failure = run_virtual_machine(&dusty_context, &VmOptions::new()).unwrap_err();
+        assert_eq!(failure.error, RuntimeError::Native("expected an int (in \"twice\")".to_string()));
+        assert_eq!(failure.error.exit_code(), 7);
+    }
+}
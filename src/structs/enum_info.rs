@@ -0,0 +1,18 @@
+#[derive(Debug, Clone)]
+pub struct EnumInfo {
+    pub variants: Vec<String>, // Variant names, the index is the value of the variant
+}
+
+impl Default for EnumInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnumInfo {
+    pub fn new() -> Self {
+        EnumInfo {
+            variants: Vec::new(),
+        }
+    }
+}
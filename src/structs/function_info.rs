@@ -1,15 +1,14 @@
-use std::collections::HashMap;
-
-use super::var_info::VarInfo;
 use super::resources::Resources;
+use super::ast::Span;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionInfo {
     // return_type: String,
     pub location: u32,
+    pub span: Span, // Where the function is declared
     pub resources: Resources,
-    pub vars: HashMap<String, VarInfo>,
-    pub params: Vec<String>
+    pub params: Vec<String>,
+    pub param_locations: Vec<u32> // One per address, records take one per field
 }
 
 impl FunctionInfo {
@@ -17,20 +16,13 @@ impl FunctionInfo {
         FunctionInfo {
             // return_type: String::from("void"),
             location,
+            span: Span::default(),
             resources: Resources::new(),
-            vars: HashMap::new(),
-            params: Vec::new()
+            params: Vec::new(),
+            param_locations: Vec::new()
         }
     }
 
-    pub fn contains_key(&self, key: &str) -> bool {
-        self.vars.contains_key(key)
-    }
-
-    pub fn get(&self, key: &str) -> Option<&VarInfo> {
-        self.vars.get(key)
-    }
-
     pub fn get_counter(&self, var_type: &str, kind: &str) -> u32 {
         // println!("Getting counter for {} {}", var_type, kind);
         match (var_type, kind) {
@@ -51,12 +43,4 @@ impl FunctionInfo {
             _ => {}
         }
     }
-
-    pub fn insert(&mut self, key: String, var_type: String, memory: u32) {
-       self.vars.insert(key.clone(), VarInfo::new(key, var_type, memory));
-    }
-
-    pub fn add_param(&mut self, param: String) {
-        self.params.push(param);
-    }
 }
\ No newline at end of file
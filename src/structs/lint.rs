@@ -0,0 +1,27 @@
+use std::fmt;
+
+use super::ast::Span;
+
+// Warning of the linter, its id is used to silence it
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub id: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Lint {
+    pub fn new(id: &'static str, message: String, span: Span) -> Self {
+        Lint {
+            id,
+            message,
+            span,
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "WARNING [{}]: {}, line: {}, col: {}", self.id, self.message, self.span.line, self.span.col)
+    }
+}
@@ -3,7 +3,20 @@ pub mod virtual_memory;
 pub mod var_info;
 pub mod dusty_context;
 pub mod function_info;
+pub mod builtin_info;
+pub mod record_info;
+pub mod enum_info;
 pub mod resources;
+pub mod scope;
 pub mod parser;
 pub mod quad_data;
-pub mod semantic_cube;
\ No newline at end of file
+pub mod semantic_cube;
+pub mod vm_options;
+pub mod compile_options;
+pub mod value;
+pub mod runtime_error;
+pub mod ast;
+pub mod symbol;
+pub mod diagnostic;
+pub mod lint;
+pub mod source_location;
@@ -1,28 +1,25 @@
 use std::collections::HashMap;
-use super::var_info::VarInfo;
 use super::semantic_cube::SemanticCube;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct QuadData {
-    pub operator_stack: Vec<String>,
-    pub operand_stack: Vec<VarInfo>,
-    pub jump_stack: Vec<usize>,
     pub quad_counter: usize,
-    pub param_counter: usize,
     pub temp_counter: usize,
     pub semantic_cube: SemanticCube,
     pub memmory_config: [[u32; 2]; 11],
     pub operator_config: HashMap<String, usize>
 }
 
+impl Default for QuadData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl QuadData {
     pub fn new() -> Self {
         QuadData {
-            operator_stack: Vec::new(),
-            operand_stack: Vec::new(),
-            jump_stack: Vec::new(),
             quad_counter: 1,
-            param_counter: 0,
             temp_counter: 1,
             semantic_cube: SemanticCube::new(),
             memmory_config: [
@@ -60,6 +57,13 @@ impl QuadData {
                 map.insert(String::from("print"), 15);
                 map.insert(String::from("end"), 16);
                 map.insert(String::from("endfunc"), 17);
+                map.insert(String::from("int"), 18);
+                map.insert(String::from("float"), 19);
+                map.insert(String::from("round"), 20);
+                map.insert(String::from("floor"), 21);
+                map.insert(String::from("ceil"), 22);
+                map.insert(String::from("callb"), 23);
+                map.insert(String::from("reset"), 24);
                 map
             }
         }
@@ -81,4 +85,16 @@ impl QuadData {
             _ => 999999
         }
     }
+
+    pub fn is_global_variable(&self, location: u32) -> bool {
+        location >= self.memmory_config[0][0] && location <= self.memmory_config[1][1]
+    }
+
+    pub fn is_local_variable(&self, location: u32) -> bool {
+        location >= self.memmory_config[4][0] && location <= self.memmory_config[5][1]
+    }
+
+    pub fn is_constant(&self, location: u32) -> bool {
+        location >= self.memmory_config[8][0] && location <= self.memmory_config[10][1]
+    }
 }
\ No newline at end of file
@@ -0,0 +1,22 @@
+#[derive(Debug, Clone)]
+pub struct RecordInfo {
+    pub fields: Vec<(String, String)>, // Field name and type, in declaration order
+}
+
+impl Default for RecordInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordInfo {
+    pub fn new() -> Self {
+        RecordInfo {
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn contains_field(&self, name: &str) -> bool {
+        self.fields.iter().any(|(field, _)| field == name)
+    }
+}
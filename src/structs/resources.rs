@@ -1,9 +1,17 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Resources {
   pub int_count: u32,
   pub float_count: u32,
   pub temp_i_count: u32,
   pub temp_f_count: u32,
+  pub int_peak: u32, // Highest counters reached inside blocks that were closed
+  pub float_peak: u32,
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Resources {
@@ -12,7 +20,17 @@ impl Resources {
             int_count: 0,
             float_count: 0,
             temp_i_count: 0,
-            temp_f_count: 0
+            temp_f_count: 0,
+            int_peak: 0,
+            float_peak: 0
         }
     }
+
+    pub fn int_size(&self) -> u32 {
+        self.int_count.max(self.int_peak)
+    }
+
+    pub fn float_size(&self) -> u32 {
+        self.float_count.max(self.float_peak)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,179 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::ast::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    DivisionByZero,
+    Overflow(String), // Int operation whose result doesn't fit, like "9223372036854775807 + 1"
+    Uninitialized(String), // Variable read before being assigned
+    StackOverflow(usize), // Maximum depth of nested calls
+    MathDomain(String), // Builtin called outside of its domain
+    InvalidReturn(String), // Host function that returned a float where an int was expected
+    UnknownFunction(String),
+    InvalidAddress(u32),
+    ConstantWrite(u32),
+    InvalidValue(String), // Value that can't be stored in the segment of its address
+    Native(String), // Raised by a host function
+    InstructionLimit(u64), // Limits of VmOptions that were exceeded
+    TimeLimit(Duration),
+    OutOfMemory(usize),
+    Trace(String), // The trace file can't be opened or written
+    Internal(String), // Quadruples that don't match the state of the VM, a bug of the compiler
+}
+
+impl RuntimeError {
+    // Exit code of the CLI, 1 is used by compile errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RuntimeError::DivisionByZero => 2,
+            RuntimeError::Overflow(_) => 3,
+            RuntimeError::Uninitialized(_) => 4,
+            RuntimeError::StackOverflow(_) => 5,
+            RuntimeError::MathDomain(_) => 6,
+            RuntimeError::Native(_) => 7,
+            RuntimeError::InvalidReturn(_) => 8,
+            RuntimeError::UnknownFunction(_) => 9,
+            RuntimeError::InvalidAddress(_) => 10,
+            RuntimeError::ConstantWrite(_) => 11,
+            RuntimeError::InvalidValue(_) => 12,
+            RuntimeError::InstructionLimit(_) => 13,
+            RuntimeError::TimeLimit(_) => 14,
+            RuntimeError::OutOfMemory(_) => 15,
+            RuntimeError::Trace(_) => 16,
+            RuntimeError::Internal(_) => 17,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::DivisionByZero => write!(f, "RUNTIME ERROR: Division by zero"),
+            RuntimeError::Overflow(operation) => write!(f, "RUNTIME ERROR: Int overflow in {}", operation),
+            RuntimeError::Uninitialized(name) => write!(f, "RUNTIME ERROR: Variable \"{}\" was read before being assigned", name),
+            RuntimeError::StackOverflow(depth) => write!(f, "RUNTIME ERROR: Stack overflow, more than {} nested calls", depth),
+            RuntimeError::MathDomain(message) => write!(f, "RUNTIME ERROR: {}", message),
+            RuntimeError::InvalidReturn(name) => write!(f, "RUNTIME ERROR: Function \"{}\" returned a float, expected int", name),
+            RuntimeError::UnknownFunction(name) => write!(f, "RUNTIME ERROR: Unknown builtin function \"{}\"", name),
+            RuntimeError::InvalidAddress(address) => write!(f, "RUNTIME ERROR: Invalid address {}", address),
+            RuntimeError::ConstantWrite(address) => write!(f, "RUNTIME ERROR: Cannot modify the constant at address {}", address),
+            RuntimeError::InvalidValue(value) => write!(f, "RUNTIME ERROR: Invalid value \"{}\"", value),
+            RuntimeError::Native(message) => write!(f, "RUNTIME ERROR: {}", message),
+            RuntimeError::InstructionLimit(limit) => write!(f, "RUNTIME ERROR: Instruction limit of {} quadruples exceeded", limit),
+            RuntimeError::TimeLimit(limit) => write!(f, "RUNTIME ERROR: Time limit of {:?} exceeded", limit),
+            RuntimeError::OutOfMemory(limit) => write!(f, "RUNTIME ERROR: Memory limit of {} values exceeded", limit),
+            RuntimeError::Trace(error) => write!(f, "RUNTIME ERROR: Cannot write the trace, {}", error),
+            RuntimeError::Internal(message) => write!(f, "RUNTIME ERROR: Internal error, {}", message),
+        }
+    }
+}
+
+// Function being run when the error happened, and where
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub function: String, // Signature, "global" for the program body
+    pub span: Option<Span>, // Statement or expression of the quadruple, the call for the callers
+    pub file: Option<PathBuf>, // Imported file of the function, None for the compiled file
+}
+
+impl StackFrame {
+    // ", line: <line>, col: <col>", with the path of the file when it was imported
+    pub fn location(&self) -> String {
+        let Some(span) = &self.span else {
+            return String::new();
+        };
+        match &self.file {
+            Some(file) => format!(", line: {}, col: {} (in file \"{}\")", span.line, span.col, file.display()),
+            None => format!(", line: {}, col: {}", span.line, span.col),
+        }
+    }
+}
+
+// Error that stopped the VM with the Dusty call stack, the innermost frame first
+#[derive(Debug, Clone)]
+pub struct RuntimeFailure {
+    pub error: RuntimeError,
+    pub stack: Vec<StackFrame>,
+}
+
+impl fmt::Display for RuntimeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(frame) = self.stack.first() {
+            write!(f, "{}", frame.location())?;
+        }
+        // Recursive calls from the same place are shown once
+        let mut index = 0;
+        while index < self.stack.len() {
+            let frame = &self.stack[index];
+            let repeated = self.stack[index..].iter()
+                .take_while(|other| other.function == frame.function && other.span == frame.span)
+                .count();
+            write!(f, "\n    at {}{}", frame.function, frame.location())?;
+            if repeated > 1 {
+                write!(f, " ({} times)", repeated)?;
+            }
+            index += repeated;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(function: &str, line: usize, file: Option<&str>) -> StackFrame {
+        StackFrame {
+            function: function.to_string(),
+            span: Some(Span { line, col: 3, end_line: line, end_col: 9 }),
+            file: file.map(PathBuf::from),
+        }
+    }
+
+    #[test]
+    fn failures_show_the_stack_with_recursive_calls_once() {
+        let failure = RuntimeFailure {
+            error: RuntimeError::DivisionByZero,
+            stack: vec![
+                frame("geometry.ratio(float)", 4, Some("/src/geometry.dusty")),
+                frame("count(int)", 7, None),
+                frame("count(int)", 7, None),
+                frame("count(int)", 9, None),
+                frame("global", 12, None),
+            ],
+        };
+        assert_eq!(failure.to_string(), "RUNTIME ERROR: Division by zero, line: 4, col: 3 (in file \"/src/geometry.dusty\")
+    at geometry.ratio(float), line: 4, col: 3 (in file \"/src/geometry.dusty\")
+    at count(int), line: 7, col: 3 (2 times)
+    at count(int), line: 9, col: 3
+    at global, line: 12, col: 3");
+    }
+
+    #[test]
+    fn every_error_has_its_own_exit_code() {
+        let errors = [
+            RuntimeError::DivisionByZero,
+            RuntimeError::Overflow(String::new()),
+            RuntimeError::Uninitialized(String::new()),
+            RuntimeError::StackOverflow(0),
+            RuntimeError::MathDomain(String::new()),
+            RuntimeError::Native(String::new()),
+            RuntimeError::InvalidReturn(String::new()),
+            RuntimeError::UnknownFunction(String::new()),
+            RuntimeError::InvalidAddress(0),
+            RuntimeError::ConstantWrite(0),
+            RuntimeError::InvalidValue(String::new()),
+            RuntimeError::InstructionLimit(0),
+            RuntimeError::TimeLimit(Duration::ZERO),
+            RuntimeError::OutOfMemory(0),
+            RuntimeError::Trace(String::new()),
+            RuntimeError::Internal(String::new()),
+        ];
+        let codes: Vec<i32> = errors.iter().map(RuntimeError::exit_code).collect();
+        assert_eq!(codes, (2..=17).collect::<Vec<i32>>());
+    }
+}
@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Scope {
+    pub vars: HashMap<String, usize>, // Names declared in the scope and their symbols
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Scope {
+            vars: HashMap::new(),
+        }
+    }
+}
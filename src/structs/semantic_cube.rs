@@ -1,9 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SemanticCube {
     pub cube: [[[String; 9]; 2]; 2],
     pub string_to_usize: HashMap<String, usize>,
+    pub enums: HashSet<String>, // Enum types, only compared with themselves
+}
+
+impl Default for SemanticCube {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SemanticCube {
@@ -75,14 +82,27 @@ impl SemanticCube {
                 map.insert(String::from("!="), 7);
                 map.insert(String::from("="), 8);
                 map
-            }
+            },
+            enums: HashSet::new(),
         }
     }
 
+    pub fn add_enum(&mut self, name: &str) {
+        self.enums.insert(name.to_string());
+    }
+
     pub fn get_result_type(&self, left: &str, right: &str, operator: &str) -> String {
-        let left_usize = self.string_to_usize.get(left).unwrap();
-        let right_usize = self.string_to_usize.get(right).unwrap();
-        let operator_usize = self.string_to_usize.get(operator).unwrap();
-        self.cube[*left_usize][*right_usize][*operator_usize].clone()
+        if left == right && self.enums.contains(left) {
+            return match operator {
+                "==" | "!=" => String::from("int"),
+                "=" => left.to_string(),
+                _ => String::from("error"),
+            };
+        }
+        // Types outside of the cube (records, mixed enums) can't be operated
+        match (self.string_to_usize.get(left), self.string_to_usize.get(right), self.string_to_usize.get(operator)) {
+            (Some(left_usize), Some(right_usize), Some(operator_usize)) => self.cube[*left_usize][*right_usize][*operator_usize].clone(),
+            _ => String::from("error"),
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,18 @@
+use super::ast::Span;
+
+// Place in the source of a statement or expression, the quadruples keep
+// the one they were generated from
+#[derive(Debug, Clone)]
+pub struct SourceLocation {
+    pub span: Span,
+    pub module: String, // Namespace of the imported file, empty for the program
+}
+
+impl SourceLocation {
+    pub fn new(span: Span, module: String) -> Self {
+        SourceLocation {
+            span,
+            module,
+        }
+    }
+}
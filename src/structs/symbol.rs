@@ -0,0 +1,47 @@
+use super::ast::Span;
+use super::var_info::VarInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Constant,
+    Variant,
+}
+
+impl SymbolKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Variant => "variant",
+        }
+    }
+}
+
+// Declared name, created by name resolution and shared by the later passes
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String, // Qualified name, e.g. geometry.PI or Color.Red
+    pub kind: SymbolKind,
+    pub var_type: String,
+    pub function: String, // Function where it is declared, "global" for the program and imported files
+    pub span: Span,
+    pub value: Option<String>, // Compile time value of constants and variants
+    pub var: Option<VarInfo>, // Address, set when lowering
+}
+
+impl Symbol {
+    pub fn new(name: String, kind: SymbolKind, var_type: String, function: String, span: Span) -> Self {
+        Symbol {
+            name,
+            kind,
+            var_type,
+            function,
+            span,
+            value: None,
+            var: None,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+
+use super::runtime_error::RuntimeError;
+
+// Value passed between the VM and host functions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+}
+
+impl Value {
+    // Builds a value from the VM representation (value, type)
+    pub fn parse(value: &str, var_type: &str) -> Result<Self, RuntimeError> {
+        let invalid_value = || RuntimeError::InvalidValue(value.to_string());
+        match var_type {
+            "int" => value.parse().map(Value::Int).map_err(|_| invalid_value()),
+            _ => value.parse().map(Value::Float).map_err(|_| invalid_value()),
+        }
+    }
+
+    pub fn var_type(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_64_bit() {
+        assert_eq!(Value::parse("9223372036854775807", "int"), Ok(Value::Int(i64::MAX)));
+        assert_eq!(Value::parse("0.1", "float"), Ok(Value::Float(0.1)));
+        assert_eq!(Value::Float(1.0 / 3.0).to_string(), "0.3333333333333333");
+        assert_eq!(Value::Int(i64::MIN).var_type(), "int");
+    }
+}
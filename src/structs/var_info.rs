@@ -4,6 +4,7 @@ pub struct VarInfo {
     pub name: String,
     pub var_type: String,
     pub location: u32,
+    pub fields: Vec<VarInfo>, // Fields of a record variable, empty for int and float
 }
 
 impl VarInfo {
@@ -12,6 +13,16 @@ impl VarInfo {
             name,
             var_type,
             location,
+            fields: Vec::new(),
         }
     }
-}
\ No newline at end of file
+
+    // Variables with an address, the fields of a record or the variable itself
+    pub fn flatten(&self) -> Vec<VarInfo> {
+        if self.fields.is_empty() {
+            vec![self.clone()]
+        } else {
+            self.fields.clone()
+        }
+    }
+}
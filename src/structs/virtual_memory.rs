@@ -1,49 +1,89 @@
+use super::value::Value;
+
 #[derive(Debug)]
 pub struct GlobalMemory {
-    pub ints: Vec<i32>,
-    pub int_temps: Vec<i32>,
-    pub floats: Vec<f32>,
-    pub float_temps: Vec<f32>,
-    pub int_consts: Vec<i32>,
-    pub float_consts: Vec<f32>,
+    pub ints: Vec<i64>,
+    pub int_temps: Vec<i64>,
+    pub floats: Vec<f64>,
+    pub float_temps: Vec<f64>,
+    pub int_consts: Vec<i64>,
+    pub float_consts: Vec<f64>,
     pub string_const: Vec<String>,
+    pub ints_init: Vec<bool>,
+    pub floats_init: Vec<bool>,
     pub memory_stack: Vec<LocalMemory>,
+    pub pending_frames: Vec<LocalMemory>,
+    pub call_args: Vec<Value>,
     pub jump_stack: Vec<usize>,
+    pub frames_size: usize, // Values in memory_stack and pending_frames
 }
 
 impl GlobalMemory {
     pub fn new(i_size: usize, it_size: usize, f_size: usize, ft_size: usize, ic_size: usize, fc_size: usize, sc_size: usize) -> GlobalMemory {
         GlobalMemory {
-            ints: vec![std::i32::MIN; i_size],
-            int_temps: vec![std::i32::MIN; it_size],
-            floats: vec![std::f32::MIN; f_size],
-            float_temps: vec![std::f32::MIN; ft_size],
-            int_consts: vec![std::i32::MIN; ic_size],
-            float_consts: vec![std::f32::MIN; fc_size],
+            ints: vec![i64::MIN; i_size],
+            int_temps: vec![i64::MIN; it_size],
+            floats: vec![f64::MIN; f_size],
+            float_temps: vec![f64::MIN; ft_size],
+            int_consts: vec![i64::MIN; ic_size],
+            float_consts: vec![f64::MIN; fc_size],
             string_const: vec!["".to_string(); sc_size],
+            ints_init: vec![false; i_size],
+            floats_init: vec![false; f_size],
             memory_stack: Vec::new(),
+            pending_frames: Vec::new(),
+            call_args: Vec::new(),
             jump_stack: Vec::new(),
+            frames_size: 0,
         }
     }
+
+    // Values in the global segments, without the frames
+    pub fn global_size(&self) -> usize {
+        self.ints.len() + self.int_temps.len() + self.floats.len() + self.float_temps.len()
+            + self.int_consts.len() + self.float_consts.len() + self.string_const.len()
+    }
+
+    // Grows the segments to the sizes of get_memory_size_main, new addresses
+    // start uninitialized
+    pub fn grow(&mut self, sizes: [usize; 7]) {
+        self.ints.resize(sizes[0].max(self.ints.len()), i64::MIN);
+        self.int_temps.resize(sizes[1].max(self.int_temps.len()), i64::MIN);
+        self.floats.resize(sizes[2].max(self.floats.len()), f64::MIN);
+        self.float_temps.resize(sizes[3].max(self.float_temps.len()), f64::MIN);
+        self.int_consts.resize(sizes[4].max(self.int_consts.len()), i64::MIN);
+        self.float_consts.resize(sizes[5].max(self.float_consts.len()), f64::MIN);
+        self.string_const.resize(sizes[6].max(self.string_const.len()), "".to_string());
+        self.ints_init.resize(self.ints.len(), false);
+        self.floats_init.resize(self.floats.len(), false);
+    }
 }
 
 #[derive(Debug)]
 pub struct LocalMemory {
-    pub ints: Vec<i32>,
-    pub int_temps: Vec<i32>,
-    pub floats: Vec<f32>,
-    pub float_temps: Vec<f32>,
+    pub ints: Vec<i64>,
+    pub int_temps: Vec<i64>,
+    pub floats: Vec<f64>,
+    pub float_temps: Vec<f64>,
+    pub ints_init: Vec<bool>,
+    pub floats_init: Vec<bool>,
 }
 
 impl LocalMemory {
     pub fn new(i_size: usize, it_size: usize, f_size: usize, ft_size: usize) -> LocalMemory {
         LocalMemory {
-            ints: vec![std::i32::MIN; i_size],
-            int_temps: vec![std::i32::MIN; it_size],
-            floats: vec![std::f32::MIN; f_size],
-            float_temps: vec![std::f32::MIN; ft_size],
+            ints: vec![i64::MIN; i_size],
+            int_temps: vec![i64::MIN; it_size],
+            floats: vec![f64::MIN; f_size],
+            float_temps: vec![f64::MIN; ft_size],
+            ints_init: vec![false; i_size],
+            floats_init: vec![false; f_size],
         }
     }
+
+    pub fn size(&self) -> usize {
+        self.ints.len() + self.int_temps.len() + self.floats.len() + self.float_temps.len()
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct VmOptions {
+    pub check_uninitialized: bool, // Raise an error when reading a variable that was never assigned
+    pub wrapping_arithmetic: bool, // Int results that don't fit wrap around instead of raising an Overflow error
+    pub trace: bool, // Log every executed quadruple with the values it read and wrote
+    pub trace_file: Option<PathBuf>, // Where the trace is written, stderr when None
+    pub trace_function: Option<String>, // Only trace the quadruples of this function (name or signature)
+    pub trace_addresses: Option<(u32, u32)>, // Only trace the quadruples that use an address in the range
+    pub max_instructions: Option<u64>, // Quadruples a run can execute
+    pub max_time: Option<Duration>, // Wall-clock time a run can take
+    pub max_call_depth: usize, // Nested calls
+    pub max_memory: Option<usize>, // Values in the global segments and the frames of the calls
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VmOptions {
+    pub fn new() -> Self {
+        VmOptions {
+            check_uninitialized: false,
+            wrapping_arithmetic: false,
+            trace: false,
+            trace_file: None,
+            trace_function: None,
+            trace_addresses: None,
+            max_instructions: None,
+            max_time: None,
+            max_call_depth: 10000,
+            max_memory: None,
+        }
+    }
+}
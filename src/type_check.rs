@@ -0,0 +1,444 @@
+use super::constant_evaluation::evaluate_constant;
+use super::structs::{
+  ast::*,
+  diagnostic::Diagnostic,
+  dusty_context::DustyContext,
+  symbol::SymbolKind,
+};
+
+// Computes the type of every expression and checks that operations,
+// assignments, conditions and calls are valid for their types. Constants get
+// their value and calls to overloaded functions the chosen signature
+pub fn check_program(program: &mut Program, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  check_module(&mut program.module, dusty_context)?;
+  check_block(&mut program.body, dusty_context)
+}
+
+// Checks one input of the REPL
+pub fn check_input(module: &mut Module, body: &mut Block, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  check_module(module, dusty_context)?;
+  check_block(body, dusty_context)
+}
+
+fn check_module(module: &mut Module, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  for import in module.imports.iter_mut() {
+      if let Some(imported) = &mut import.module {
+          check_module(imported, dusty_context).map_err(|e| e.in_file(imported.path.as_ref().unwrap()))?;
+      }
+  }
+
+  for const_decl in module.consts.iter_mut() {
+      check_const(const_decl, dusty_context)?;
+  }
+  for var_decl in module.vars.iter_mut() {
+      check_vars(var_decl, dusty_context)?;
+  }
+  for function in module.funcs.iter_mut() {
+      for const_decl in function.consts.iter_mut() {
+          check_const(const_decl, dusty_context)?;
+      }
+      for var_decl in function.vars.iter_mut() {
+          check_vars(var_decl, dusty_context)?;
+      }
+      for stmt in function.body.iter_mut() {
+          check_stmt(stmt, dusty_context)?;
+      }
+  }
+  Ok(())
+}
+
+fn check_const(const_decl: &mut ConstDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  // Evaluate the value at compile time
+  check_expr(&mut const_decl.value, dusty_context)?;
+  let (mut value, value_type) = evaluate_constant(&const_decl.value, dusty_context)?;
+
+  let symbol = const_decl.name.symbol.unwrap();
+  let const_type = dusty_context.symbols[symbol].var_type.clone();
+  if !dusty_context.can_assign(&const_type, &value_type) {
+      return Err(Diagnostic::new(format!("ERROR: Type mismatch. Cannot assign {} to constant \"{}\" of type {}, line: {}, col: {}",
+          value_type,
+          const_decl.name.name,
+          const_type,
+          const_decl.name.span.line,
+          const_decl.name.span.col
+      ), const_decl.span));
+  }
+  if const_type == "float" && value_type == "int" {
+      value = format!("{:?}", value.parse::<f64>().unwrap());
+  }
+  dusty_context.symbols[symbol].value = Some(value);
+  Ok(())
+}
+
+fn check_vars(var_decl: &mut VarDecl, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  if let Some(init) = &mut var_decl.init {
+      check_expr(init, dusty_context)?;
+      for id in var_decl.names.iter() {
+          check_assign(&dusty_context.symbols[id.symbol.unwrap()].var_type, init, dusty_context)?;
+      }
+  }
+  Ok(())
+}
+
+fn check_assign(target_type: &str, value: &Expr, dusty_context: &DustyContext) -> Result<(), Diagnostic> {
+  if !dusty_context.can_assign(target_type, &value.ty) {
+      return Err(Diagnostic::new(format!("ERROR: Type mismatch. Cannot assign {} to {}, line: {}, col: {}",
+          value.ty,
+          target_type,
+          value.span.line,
+          value.span.col
+      ), value.span));
+  }
+  Ok(())
+}
+
+fn check_block(block: &mut Block, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  for var_decl in block.vars.iter_mut() {
+      check_vars(var_decl, dusty_context)?;
+  }
+  for stmt in block.stmts.iter_mut() {
+      check_stmt(stmt, dusty_context)?;
+  }
+  Ok(())
+}
+
+fn check_stmt(stmt: &mut Stmt, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  match &mut stmt.kind {
+      StmtKind::Assign { target, value } => {
+          // Constants can't be assignment targets
+          let symbol = &dusty_context.symbols[target.symbol.unwrap()];
+          if matches!(symbol.kind, SymbolKind::Constant | SymbolKind::Variant) {
+              return Err(Diagnostic::new(format!("ERROR: Cannot assign to constant \"{}\", line: {}, col: {}",
+                  target.name,
+                  target.span.line,
+                  target.span.col
+              ), target.span));
+          }
+          check_expr(value, dusty_context)?;
+          check_assign(&ident_type(target, dusty_context), value, dusty_context)
+      }
+      StmtKind::If { condition, then_block, else_block } => {
+          check_condition(condition, dusty_context)?;
+          check_block(then_block, dusty_context)?;
+          match else_block {
+              Some(else_block) => check_block(else_block, dusty_context),
+              None => Ok(()),
+          }
+      }
+      StmtKind::While { condition, body } => {
+          check_condition(condition, dusty_context)?;
+          check_block(body, dusty_context)
+      }
+      StmtKind::Match { scrutinee, arms, default } => {
+          check_expr(scrutinee, dusty_context)?;
+          if dusty_context.type_dir.contains_key(&scrutinee.ty) {
+              return Err(Diagnostic::new(format!("ERROR: Cannot match record \"{}\", line: {}, col: {}",
+                  describe(scrutinee),
+                  scrutinee.span.line,
+                  scrutinee.span.col
+              ), scrutinee.span));
+          }
+
+          let mut patterns = Vec::new();
+          for arm in arms.iter_mut() {
+              check_expr(&mut arm.pattern, dusty_context)?;
+              // Patterns are compared by value, so 1 and 1.0 or a constant and
+              // its literal are the same pattern
+              let (pattern, _) = evaluate_constant(&arm.pattern, dusty_context)?;
+              match pattern.parse::<f64>() {
+                  Ok(pattern) if !patterns.contains(&pattern) => patterns.push(pattern),
+                  _ => return Err(Diagnostic::new(format!("ERROR: Match patterns must be different constants, line: {}, col: {}",
+                      arm.span.line,
+                      arm.span.col
+                  ), arm.span)),
+              }
+              check_operation("==", &scrutinee.ty, &arm.pattern.ty, arm.pattern.span, dusty_context)?;
+              check_block(&mut arm.body, dusty_context)?;
+          }
+          match default {
+              Some(default) => check_block(default, dusty_context),
+              None => Ok(()),
+          }
+      }
+      StmtKind::Call(call) => check_call(call, true, dusty_context).map(|_| ()),
+      StmtKind::Print(elements) => {
+          for element in elements.iter_mut() {
+              if let PrintElement::Expr(expr) = element {
+                  check_expr(expr, dusty_context)?;
+                  if dusty_context.type_dir.contains_key(&expr.ty) {
+                      return Err(Diagnostic::new(format!("ERROR: Cannot print record \"{}\", print its fields instead, line: {}, col: {}",
+                          describe(expr),
+                          expr.span.line,
+                          expr.span.col
+                      ), expr.span));
+                  }
+              }
+          }
+          Ok(())
+      }
+  }
+}
+
+fn check_condition(condition: &mut Expr, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  check_expr(condition, dusty_context)?;
+  if condition.ty != "int" {
+      return Err(Diagnostic::new(format!("ERROR: Expected int but got {}, line: {}, col: {}",
+          condition.ty,
+          condition.span.line,
+          condition.span.col
+      ), condition.span));
+  }
+  Ok(())
+}
+
+// Returns the type of the value returned by the call
+fn check_call(call: &mut Call, is_statement: bool, dusty_context: &mut DustyContext) -> Result<String, Diagnostic> {
+  let (line, col) = call.span.start();
+  let builtin = dusty_context.builtin_dir.get(&call.function).filter(|_| call.is_builtin);
+  let is_void = builtin.is_none_or(|builtin| builtin.return_type == "void");
+  if !is_statement && is_void {
+      return Err(Diagnostic::new(format!("ERROR: Function \"{}\" is void and can't be used as a value, line: {}, col: {}",
+          call.name.name, line, col
+      ), call.span));
+  }
+
+  for arg in call.args.iter_mut() {
+      check_expr(arg, dusty_context)?;
+  }
+
+  if call.is_builtin {
+      // Builtin and host functions take their parameter types, int arguments are widened to float with --lenient
+      let builtin = dusty_context.builtin_dir.get(&call.function).unwrap();
+      for (index, arg) in call.args.iter().enumerate() {
+          if index >= builtin.params.len() {
+              return Err(Diagnostic::new(format!("ERROR: Too many parameters for function \"{}\", line: {}, col: {}",
+                  call.name.name, arg.span.line, arg.span.col
+              ), arg.span));
+          }
+          if !dusty_context.can_assign(&builtin.params[index], &arg.ty) {
+              return Err(Diagnostic::new(format!("ERROR: Type mismatch. Expected {} but got {}, line: {}, col: {}",
+                  builtin.params[index], arg.ty, arg.span.line, arg.span.col
+              ), arg.span));
+          }
+      }
+      if call.args.len() != builtin.params.len() {
+          return Err(Diagnostic::new(format!("ERROR: Function \"{}\" was called with {} parameters, expected {}. Line: {}, Col: {}",
+              call.name.name, call.args.len(), builtin.params.len(), line, col
+          ), call.span));
+      }
+      return Ok(builtin.return_type.clone());
+  }
+
+  let arg_types: Vec<String> = call.args.iter().map(|arg| arg.ty.clone()).collect();
+  let overloads = dusty_context.get_overloads(&call.function);
+
+  // Check for correct number of parameters
+  if overloads.len() == 1 && dusty_context.func_dir.get(overloads[0]).unwrap().params.len() != arg_types.len() {
+      return Err(Diagnostic::new(format!("ERROR: Function \"{}\" was called with {} parameters, expected {}. Line: {}, Col: {}",
+          call.function,
+          arg_types.len(),
+          dusty_context.func_dir.get(overloads[0]).unwrap().params.len(),
+          line,
+          col
+      ), call.span));
+  }
+
+  match dusty_context.resolve_overload(&call.function, &arg_types) {
+      Ok(signature) => call.signature = signature,
+      Err(error) => return Err(Diagnostic::new(format!("{}. Line: {}, Col: {}", error, line, col), call.span)),
+  }
+  Ok("void".to_string())
+}
+
+fn check_expr(expr: &mut Expr, dusty_context: &mut DustyContext) -> Result<(), Diagnostic> {
+  let span = expr.span;
+  expr.ty = match &mut expr.kind {
+      ExprKind::Int(value) => {
+          if value.parse::<i64>().is_err() {
+              return Err(Diagnostic::new(format!("ERROR: Int literal {} does not fit in an int, line: {}, col: {}",
+                  value, span.line, span.col
+              ), span));
+          }
+          "int".to_string()
+      }
+      ExprKind::Float(_) => "float".to_string(),
+      ExprKind::Name(id) => ident_type(id, dusty_context),
+      ExprKind::Group(operand) => {
+          check_expr(operand, dusty_context)?;
+          operand.ty.clone()
+      }
+      ExprKind::Unary { sign, operand } => {
+          check_expr(operand, dusty_context)?;
+          // -x is computed as 0 - x
+          match sign.as_str() {
+              "-" => check_operation("-", "int", &operand.ty, span, dusty_context)?,
+              _ => operand.ty.clone(),
+          }
+      }
+      ExprKind::Binary { operator, left, right } => {
+          check_expr(left, dusty_context)?;
+          check_expr(right, dusty_context)?;
+          check_operation(operator, &left.ty, &right.ty, span, dusty_context)?
+      }
+      ExprKind::Conversion { conversion, operand } => {
+          check_expr(operand, dusty_context)?;
+          if operand.ty != "int" && operand.ty != "float" {
+              return Err(Diagnostic::new(format!("ERROR: Type mismatch. Cannot use {} with {}, line: {}, col: {}",
+                  conversion, operand.ty, span.line, span.col
+              ), span));
+          }
+          if conversion == "float" { "float".to_string() } else { "int".to_string() }
+      }
+      ExprKind::Call(call) => check_call(call, false, dusty_context)?,
+  };
+  Ok(())
+}
+
+// Result type of the operation in the semantic cube
+fn check_operation(operator: &str, left: &str, right: &str, span: Span, dusty_context: &DustyContext) -> Result<String, Diagnostic> {
+  let result_type = dusty_context.quad_data.semantic_cube.get_result_type(left, right, operator);
+  if result_type == "error" {
+      return Err(Diagnostic::new(format!("ERROR: Type mismatch. Cannot use {} with {} and {}, line: {}, col: {}",
+          operator, left, right, span.line, span.col
+      ), span));
+  }
+  Ok(result_type)
+}
+
+// Type of a variable, constant or record field
+fn ident_type(id: &Ident, dusty_context: &DustyContext) -> String {
+  let var_type = &dusty_context.symbols[id.symbol.unwrap()].var_type;
+  match &id.field {
+      Some(field) => dusty_context.type_dir.get(var_type).unwrap().fields.iter()
+          .find(|(name, _)| name == field)
+          .map(|(_, field_type)| field_type.clone())
+          .unwrap(),
+      None => var_type.clone(),
+  }
+}
+
+// Name of the expression for error messages
+fn describe(expr: &Expr) -> String {
+  match &expr.kind {
+      ExprKind::Name(id) => id.name.clone(),
+      _ => expr.ty.clone(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::*;
+  use crate::compile;
+
+  fn compile_error(source: &str, strict_types: bool) -> String {
+      let mut dusty_context = DustyContext::new();
+      dusty_context.options.strict_types = strict_types;
+      compile(source, &mut dusty_context).expect_err("the program should not compile").to_string()
+  }
+
+  const OVERLOADS: &str = "program p;
+void pair(a: int, b: float) { print!(a, b); };
+void pair(a: float, b: int) { print!(a, b); };
+void show(a: int) { print!(a); };
+void show(a: float) { print!(a); };
+begin
+{
+  CALL;
+}
+end";
+
+  #[test]
+  fn calls_pick_the_overload_of_the_argument_types() {
+      let mut dusty_context = DustyContext::new();
+      compile(&fs::read_to_string("src/tests/test13.dusty").unwrap(), &mut dusty_context).unwrap();
+      let overloads = dusty_context.get_overloads("show");
+      assert_eq!(overloads, ["show(Point)", "show(float)", "show(int)", "show(int,float)"]);
+
+      // An exact match is preferred over int to float widening
+      let mut dusty_context = DustyContext::new();
+      dusty_context.options.strict_types = false;
+      compile(&OVERLOADS.replace("CALL", "show(1)"), &mut dusty_context).unwrap();
+      assert_eq!(dusty_context.resolve_overload("show", &["int".to_string()]), Ok("show(int)".to_string()));
+  }
+
+  #[test]
+  fn calls_without_a_matching_overload_are_errors() {
+      assert_eq!(
+          compile_error(&OVERLOADS.replace("CALL", "pair(1, 2)"), true),
+          "ERROR: No overload of function \"pair\" matches (int, int). Line: 8, Col: 3"
+      );
+      assert_eq!(
+          compile_error(&OVERLOADS.replace("CALL", "show(1, 2, 3)"), true),
+          "ERROR: No overload of function \"show\" matches (int, int, int). Line: 8, Col: 3"
+      );
+  }
+
+  #[test]
+  fn calls_that_widen_to_several_overloads_are_ambiguous() {
+      assert_eq!(
+          compile_error(&OVERLOADS.replace("CALL", "pair(1, 2)"), false),
+          "ERROR: Call to function \"pair\" with (int, int) is ambiguous between pair(float,int) and pair(int,float). Line: 8, Col: 3"
+      );
+  }
+
+  #[test]
+  fn int_arguments_of_builtins_are_widened_only_when_lenient() {
+      let source = OVERLOADS.replace("CALL", "print!(abs(0 - 5))");
+      assert_eq!(
+          compile_error(&source, true),
+          "ERROR: Type mismatch. Expected float but got int, line: 8, col: 14"
+      );
+
+      let mut dusty_context = DustyContext::new();
+      dusty_context.options.strict_types = false;
+      compile(&source, &mut dusty_context).unwrap();
+      let conversions = dusty_context.quadruples.iter().filter(|quadruple| quadruple[0].name == "float").count();
+      assert_eq!(conversions, 1);
+  }
+
+  #[test]
+  fn ints_are_assigned_to_floats_only_when_lenient() {
+      let source = "program p; vars x: float; begin { x = 3; } end";
+      assert_eq!(compile_error(source, true), "ERROR: Type mismatch. Cannot assign int to float, line: 1, col: 39");
+      let mut dusty_context = DustyContext::new();
+      dusty_context.options.strict_types = false;
+      compile(source, &mut dusty_context).unwrap();
+      assert_eq!(
+          compile_error("program p; enum Color { Red }; vars x: int; begin { x = int(Color.Red); } end", true),
+          "ERROR: Type mismatch. Cannot use int with Color, line: 1, col: 57"
+      );
+  }
+
+  #[test]
+  fn match_patterns_are_different_constants_of_the_scrutinee_type() {
+      // A constant and the variant it names are the same pattern, like 1 and 1.0
+      assert_eq!(
+          compile_error("program p; enum Light { Red, Green }; const R: Light = Light.Red; vars l: Light;
+begin { match (l) { Light.Red => { print!(1); }, R => { print!(2); } }; } end", true),
+          "ERROR: Match patterns must be different constants, line: 2, col: 50"
+      );
+      assert_eq!(
+          compile_error("program p; vars x: int; begin { match (x) { 1 => { print!(1); }, 1.0 => { print!(2); } }; } end", true),
+          "ERROR: Match patterns must be different constants, line: 1, col: 66"
+      );
+      assert_eq!(
+          compile_error("program p; vars x, y: int; begin { match (x) { y => { print!(1); } }; } end", true),
+          "ERROR: \"y\" is not a constant and cannot be used in a constant expression, line: 1, col: 48"
+      );
+  }
+
+  #[test]
+  fn enums_are_only_compared_with_their_own_variants() {
+      assert_eq!(
+          compile_error("program p; enum Light { Red }; enum Color { Red }; vars l: Light;
+begin { if (l == Color.Red) { print!(1); }; } end", true),
+          "ERROR: Type mismatch. Cannot use == with Light and Color, line: 2, col: 13"
+      );
+      assert_eq!(
+          compile_error("program p; enum Light { Red }; vars x: int; begin { x = Light.Red; } end", true),
+          "ERROR: Type mismatch. Cannot assign Light to int, line: 1, col: 57"
+      );
+  }
+}
@@ -1,5 +1,11 @@
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::Instant;
 
+use super::builtins::call_builtin;
+use super::profiler::Profile;
 use super::structs::virtual_memory::{
   GlobalMemory,
   LocalMemory,
@@ -8,9 +14,16 @@ use super::structs::virtual_memory::{
 use super::structs::{
   dusty_context::DustyContext,
   function_info::FunctionInfo,
-  var_info::VarInfo
+  quadruple_unit::QuadrupleUnit,
+  runtime_error::{RuntimeError, RuntimeFailure, StackFrame},
+  var_info::VarInfo,
+  vm_options::VmOptions,
+  value::Value
 };
 
+// Quadruples executed between checks of the time limit
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
 fn map_address(address: usize) -> Option<(MemorySegment, usize)> {
   match address {
       1000..=2999 => Some((MemorySegment::Ints, address - 1000)),
@@ -31,67 +44,122 @@ fn map_address(address: usize) -> Option<(MemorySegment, usize)> {
 }
 
 fn get_value(memory: &GlobalMemory, address: usize) -> Option<(String, &'static str)> {
-  if let Some((segment, offset)) = map_address(address) {
-      match segment {
-          MemorySegment::Ints => Some((memory.ints[offset].to_string(), "int")),
-          MemorySegment::Floats => Some((memory.floats[offset].to_string(), "float")),
-          MemorySegment::IntTemps => Some((memory.int_temps[offset].to_string(), "int")),
-          MemorySegment::FloatTemps => Some((memory.float_temps[offset].to_string(), "float")),
-
-          MemorySegment::IntLocal => Some((memory.memory_stack.last().unwrap().ints[offset].to_string(), "int")),
-          MemorySegment::FloatLocal => Some((memory.memory_stack.last().unwrap().floats[offset].to_string(), "float")),
-          MemorySegment::IntLocalTemps => Some((memory.memory_stack.last().unwrap().int_temps[offset].to_string(), "int")),
-          MemorySegment::FloatLocalTemps => Some((memory.memory_stack.last().unwrap().float_temps[offset].to_string(), "float")),
-
-          MemorySegment::IntConsts => Some((memory.int_consts[offset].to_string(), "int")),
-          MemorySegment::FloatConsts => Some((memory.float_consts[offset].to_string(), "float")),
-          MemorySegment::StringConsts => Some((memory.string_const[offset].to_string(), "string")),
+  // Offsets past the size of the segment are invalid addresses too
+  let (segment, offset) = map_address(address)?;
+  match segment {
+      MemorySegment::Ints => memory.ints.get(offset).map(|value| (value.to_string(), "int")),
+      MemorySegment::Floats => memory.floats.get(offset).map(|value| (value.to_string(), "float")),
+      MemorySegment::IntTemps => memory.int_temps.get(offset).map(|value| (value.to_string(), "int")),
+      MemorySegment::FloatTemps => memory.float_temps.get(offset).map(|value| (value.to_string(), "float")),
+
+      MemorySegment::IntLocal => memory.memory_stack.last()?.ints.get(offset).map(|value| (value.to_string(), "int")),
+      MemorySegment::FloatLocal => memory.memory_stack.last()?.floats.get(offset).map(|value| (value.to_string(), "float")),
+      MemorySegment::IntLocalTemps => memory.memory_stack.last()?.int_temps.get(offset).map(|value| (value.to_string(), "int")),
+      MemorySegment::FloatLocalTemps => memory.memory_stack.last()?.float_temps.get(offset).map(|value| (value.to_string(), "float")),
+
+      MemorySegment::IntConsts => memory.int_consts.get(offset).map(|value| (value.to_string(), "int")),
+      MemorySegment::FloatConsts => memory.float_consts.get(offset).map(|value| (value.to_string(), "float")),
+      MemorySegment::StringConsts => memory.string_const.get(offset).map(|value| (value.to_string(), "string")),
+  }
+}
+
+// Values are kept as text between quadruples, parsed for the segment they are stored in
+fn parse_value<T: FromStr>(value: &str) -> Result<T, RuntimeError> {
+  value.parse().map_err(|_| RuntimeError::InvalidValue(value.to_string()))
+}
+
+// Slot of an address in its segment, the initialized bits have the same size
+fn get_slot<T>(values: &mut [T], offset: usize, address: usize) -> Result<&mut T, RuntimeError> {
+  values.get_mut(offset).ok_or(RuntimeError::InvalidAddress(address as u32))
+}
+
+fn set_value(memory: &mut GlobalMemory, address: usize, value: String) -> Result<(), RuntimeError> {
+  let Some((segment, offset)) = map_address(address) else {
+      return Err(RuntimeError::InvalidAddress(address as u32));
+  };
+  match segment {
+      MemorySegment::Ints => {
+          *get_slot(&mut memory.ints, offset, address)? = parse_value(&value)?;
+          memory.ints_init[offset] = true;
       }
-  } else {
-      None // Invalid address
+      MemorySegment::Floats => {
+          *get_slot(&mut memory.floats, offset, address)? = parse_value(&value)?;
+          memory.floats_init[offset] = true;
+      }
+      MemorySegment::IntTemps => *get_slot(&mut memory.int_temps, offset, address)? = parse_value(&value)?,
+      MemorySegment::FloatTemps => *get_slot(&mut memory.float_temps, offset, address)? = parse_value(&value)?,
+
+      MemorySegment::IntLocal => {
+          let local_memory = memory.memory_stack.last_mut().ok_or(RuntimeError::InvalidAddress(address as u32))?;
+          *get_slot(&mut local_memory.ints, offset, address)? = parse_value(&value)?;
+          local_memory.ints_init[offset] = true;
+      }
+      MemorySegment::FloatLocal => {
+          let local_memory = memory.memory_stack.last_mut().ok_or(RuntimeError::InvalidAddress(address as u32))?;
+          *get_slot(&mut local_memory.floats, offset, address)? = parse_value(&value)?;
+          local_memory.floats_init[offset] = true;
+      }
+      MemorySegment::IntLocalTemps => {
+          let local_memory = memory.memory_stack.last_mut().ok_or(RuntimeError::InvalidAddress(address as u32))?;
+          *get_slot(&mut local_memory.int_temps, offset, address)? = parse_value(&value)?;
+      }
+      MemorySegment::FloatLocalTemps => {
+          let local_memory = memory.memory_stack.last_mut().ok_or(RuntimeError::InvalidAddress(address as u32))?;
+          *get_slot(&mut local_memory.float_temps, offset, address)? = parse_value(&value)?;
+      }
+
+      MemorySegment::IntConsts | MemorySegment::FloatConsts => return Err(RuntimeError::ConstantWrite(address as u32)),
+      MemorySegment::StringConsts => *get_slot(&mut memory.string_const, offset, address)? = value,
   }
+  Ok(())
 }
 
-fn set_value(memory: &mut GlobalMemory, address: usize, value: String) {
-  if let Some((segment, offset)) = map_address(address) {
-      match segment {
-          MemorySegment::Ints => memory.ints[offset] = value.parse().unwrap(),
-          MemorySegment::Floats => memory.floats[offset] = value.parse().unwrap(),
-          MemorySegment::IntTemps => memory.int_temps[offset] = value.parse().unwrap(),
-          MemorySegment::FloatTemps => memory.float_temps[offset] = value.parse().unwrap(),
-
-          MemorySegment::IntLocal => memory.memory_stack.last_mut().unwrap().ints[offset] = value.parse().unwrap(),
-          MemorySegment::FloatLocal => memory.memory_stack.last_mut().unwrap().floats[offset] = value.parse().unwrap(),
-          MemorySegment::IntLocalTemps => memory.memory_stack.last_mut().unwrap().int_temps[offset] = value.parse().unwrap(),
-          MemorySegment::FloatLocalTemps => memory.memory_stack.last_mut().unwrap().float_temps[offset] = value.parse().unwrap(),
-
-          MemorySegment::IntConsts => {
-              // IntConsts are usually immutable, handle this as an error if necessary
-              panic!("Cannot modify constants");
-          }
-          MemorySegment::FloatConsts => {
-              panic!("Cannot modify constants");
-          }
-          MemorySegment::StringConsts => memory.string_const[offset] = value,
+fn set_param_value(memory: &mut GlobalMemory, address: usize, value: String) -> Result<(), RuntimeError> {
+  // Parameters are written to the frame that was reserved by ERA
+  let local_memory = memory.pending_frames.last_mut().ok_or(RuntimeError::InvalidAddress(address as u32))?;
+  match map_address(address) {
+      Some((MemorySegment::IntLocal, offset)) => {
+          *get_slot(&mut local_memory.ints, offset, address)? = parse_value(&value)?;
+          local_memory.ints_init[offset] = true;
       }
-  } else {
-      panic!("Invalid address");
+      Some((MemorySegment::FloatLocal, offset)) => {
+          *get_slot(&mut local_memory.floats, offset, address)? = parse_value(&value)?;
+          local_memory.floats_init[offset] = true;
+      }
+      _ => return Err(RuntimeError::InvalidAddress(address as u32)),
   }
+  Ok(())
 }
 
-fn set_param_value(memory: &mut GlobalMemory, address: usize, value: String) {
-  if address < 13000 {
-      memory.memory_stack.last_mut().unwrap().ints[address] = value.parse().unwrap();
-  } else {
-      memory.memory_stack.last_mut().unwrap().floats[address] = value.parse().unwrap();
+fn is_initialized(memory: &GlobalMemory, address: usize) -> bool {
+  match map_address(address) {
+      Some((MemorySegment::Ints, offset)) => memory.ints_init.get(offset) != Some(&false),
+      Some((MemorySegment::Floats, offset)) => memory.floats_init.get(offset) != Some(&false),
+      Some((MemorySegment::IntLocal, offset)) => memory.memory_stack.last().is_none_or(|frame| frame.ints_init.get(offset) != Some(&false)),
+      Some((MemorySegment::FloatLocal, offset)) => memory.memory_stack.last().is_none_or(|frame| frame.floats_init.get(offset) != Some(&false)),
+      _ => true, // Temporals and constants are always written before being read, missing frames and offsets are invalid addresses
   }
 }
 
+// Marks count addresses as never assigned, starting at address
+fn reset_initialized(memory: &mut GlobalMemory, address: usize, count: usize) -> Result<(), RuntimeError> {
+  let invalid_address = RuntimeError::InvalidAddress(address as u32);
+  let init = match map_address(address) {
+      Some((MemorySegment::Ints, offset)) => memory.ints_init.get_mut(offset..offset + count),
+      Some((MemorySegment::Floats, offset)) => memory.floats_init.get_mut(offset..offset + count),
+      Some((MemorySegment::IntLocal, offset)) => memory.memory_stack.last_mut().and_then(|frame| frame.ints_init.get_mut(offset..offset + count)),
+      Some((MemorySegment::FloatLocal, offset)) => memory.memory_stack.last_mut().and_then(|frame| frame.floats_init.get_mut(offset..offset + count)),
+      _ => None,
+  };
+  init.ok_or(invalid_address)?.fill(false);
+  Ok(())
+}
+
 fn get_memory_size_main(function_info: &FunctionInfo, const_count: [u32; 3]) -> [usize; 7] {
   [
-      function_info.resources.int_count as usize,
+      function_info.resources.int_size() as usize,
       function_info.resources.temp_i_count as usize,
-      function_info.resources.float_count as usize,
+      function_info.resources.float_size() as usize,
       function_info.resources.temp_f_count as usize,
       const_count[0] as usize,
       const_count[1] as usize,
@@ -99,25 +167,20 @@ fn get_memory_size_main(function_info: &FunctionInfo, const_count: [u32; 3]) ->
   ]
 }
 
-fn fill_consts(const_dir: &HashMap<String, VarInfo>, virtual_memory: &mut GlobalMemory) {
-  for (key, value) in const_dir {
-      let memory = value.location as usize;
-      match value.var_type.as_str() {
-          "int" => {
-              virtual_memory.int_consts[memory - 21000] = key.parse::<i32>().unwrap();
-          }
-          "float" => {
-              virtual_memory.float_consts[memory - 23000] = key.parse::<f32>().unwrap();
-          }
-          "string" => {
-              virtual_memory.string_const[memory - 25000] = key.clone();
-          }
-          _ => {}
+fn fill_consts(const_dir: &HashMap<(String, String), VarInfo>, virtual_memory: &mut GlobalMemory) -> Result<(), RuntimeError> {
+  for ((_, key), value) in const_dir {
+      let address = value.location as usize;
+      match map_address(address) {
+          Some((MemorySegment::IntConsts, offset)) => *get_slot(&mut virtual_memory.int_consts, offset, address)? = parse_value(key)?,
+          Some((MemorySegment::FloatConsts, offset)) => *get_slot(&mut virtual_memory.float_consts, offset, address)? = parse_value(key)?,
+          Some((MemorySegment::StringConsts, offset)) => *get_slot(&mut virtual_memory.string_const, offset, address)? = key.clone(),
+          _ => return Err(RuntimeError::InvalidAddress(value.location)),
       }
   }
+  Ok(())
 }
 
-fn bool_to_int(value: bool) -> i32 {
+fn bool_to_int(value: bool) -> i64 {
   if value {
       1
   } else {
@@ -125,186 +188,734 @@ fn bool_to_int(value: bool) -> i32 {
   }
 }
 
-fn allocate_to_stack(virtual_memory: &mut GlobalMemory, dusty_context: &DustyContext, func_name: &str) {
-  virtual_memory.memory_stack.push(LocalMemory::new(
-      dusty_context.func_dir.get(func_name).unwrap().resources.int_count as usize,
-      dusty_context.func_dir.get(func_name).unwrap().resources.temp_i_count as usize,
-      dusty_context.func_dir.get(func_name).unwrap().resources.float_count as usize,
-      dusty_context.func_dir.get(func_name).unwrap().resources.temp_f_count as usize,
+fn compare<T: PartialOrd>(left: T, right: T, operator: &str) -> bool {
+  match operator {
+      ">" => left > right,
+      "<" => left < right,
+      "==" => left == right,
+      _ => left != right,
+  }
+}
+
+// Int result of + - *, wrapped around or an Overflow error when it doesn't fit
+fn int_operation(left: i64, right: i64, operator: &str, wrapping: bool) -> Result<i64, RuntimeError> {
+  if wrapping {
+      return Ok(match operator {
+          "+" => left.wrapping_add(right),
+          "-" => left.wrapping_sub(right),
+          _ => left.wrapping_mul(right),
+      });
+  }
+  let result = match operator {
+      "+" => left.checked_add(right),
+      "-" => left.checked_sub(right),
+      _ => left.checked_mul(right),
+  };
+  result.ok_or_else(|| RuntimeError::Overflow(format!("{} {} {}", left, operator, right)))
+}
+
+// Int of a conversion from a float that was already truncated or rounded.
+// Wrapping keeps the low 64 bits of the integer part, NaN becomes 0
+fn float_to_int(value: f64, conversion: &str, operand: f64, wrapping: bool) -> Result<i64, RuntimeError> {
+  if wrapping {
+      return Ok(value as i128 as i64);
+  }
+  // i64::MAX is not exact as a float, 2^63 is the first value out of range
+  if value.is_nan() || value < i64::MIN as f64 || value >= 9223372036854775808.0 {
+      return Err(RuntimeError::Overflow(format!("{}({})", conversion, operand)));
+  }
+  Ok(value as i64)
+}
+
+// Instruction and time limits of a run, checked before every quadruple
+fn check_limits(options: &VmOptions, executed: u64, start: Instant) -> Result<(), RuntimeError> {
+  if let Some(max) = options.max_instructions.filter(|max| executed >= *max) {
+      return Err(RuntimeError::InstructionLimit(max));
+  }
+  // Reading the clock on every quadruple would slow down the VM
+  if executed.is_multiple_of(TIME_CHECK_INTERVAL) {
+      if let Some(max) = options.max_time.filter(|max| start.elapsed() > *max) {
+          return Err(RuntimeError::TimeLimit(max));
+      }
+  }
+  Ok(())
+}
+
+fn allocate_to_stack(virtual_memory: &mut GlobalMemory, dusty_context: &DustyContext, func_name: &str) -> Result<(), RuntimeError> {
+  let function_info = dusty_context.func_dir.get(func_name).ok_or(RuntimeError::UnknownFunction(func_name.to_string()))?;
+  virtual_memory.pending_frames.push(LocalMemory::new(
+      function_info.resources.int_size() as usize,
+      function_info.resources.temp_i_count as usize,
+      function_info.resources.float_size() as usize,
+      function_info.resources.temp_f_count as usize,
   ));
+  Ok(())
 }
 
-pub fn run_virtual_machine(dusty_context: &DustyContext) {
-  let main_memory_size = get_memory_size_main(
-      dusty_context.func_dir.get("global").unwrap(),
-      dusty_context.constants,
-  );
-  let mut virtual_memory = GlobalMemory::new(
-      main_memory_size[0], 
-      main_memory_size[1], 
-      main_memory_size[2], 
-      main_memory_size[3], 
-      main_memory_size[4], 
-      main_memory_size[5], 
-      main_memory_size[6]
-  );
-  fill_consts(&dusty_context.const_dir, &mut virtual_memory);
-
-  let mut intruction_pointer = 0;
-
-//   println!("{:#?}", virtual_memory);
-
-//   println!("################### OUTPUT WINDOW ###################\n");
-  while intruction_pointer < dusty_context.quadruples.len() {
-      let quadruple = &dusty_context.quadruples[intruction_pointer];
+// State of a running program, kept between runs so the REPL can resume it
+// after appending new quadruples to the context
+pub struct VirtualMachine {
+  pub memory: GlobalMemory,
+  pub instruction_pointer: usize,
+  pub call_stack: Vec<String>, // Signatures of the functions being run, innermost last
+  pub trace_output: Option<Box<dyn Write>>, // Where run logs the quadruples, opened by the first traced run
+  pub profile: Option<Profile>, // Collects executions and times when set
+}
+
+impl VirtualMachine {
+  pub fn new(dusty_context: &DustyContext) -> Result<Self, RuntimeError> {
+      let mut virtual_machine = VirtualMachine {
+          memory: GlobalMemory::new(0, 0, 0, 0, 0, 0, 0),
+          instruction_pointer: 0,
+          call_stack: Vec::new(),
+          trace_output: None,
+          profile: None,
+      };
+      virtual_machine.load(dusty_context)?;
+      Ok(virtual_machine)
+  }
+
+  // Grows the global segments to the sizes of the context and stores the new constants
+  pub fn load(&mut self, dusty_context: &DustyContext) -> Result<(), RuntimeError> {
+      let global = dusty_context.func_dir.get("global").ok_or(RuntimeError::UnknownFunction("global".to_string()))?;
+      self.memory.grow(get_memory_size_main(global, dusty_context.constants));
+      fill_consts(&dusty_context.const_dir, &mut self.memory)
+  }
+
+  // Runs from the current instruction until the end quad or the last quadruple
+  pub fn run(&mut self, dusty_context: &DustyContext, options: &VmOptions) -> Result<(), RuntimeFailure> {
+      if options.trace && self.trace_output.is_none() {
+          self.trace_output = Some(match &options.trace_file {
+              Some(path) => match File::create(path) {
+                  Ok(file) => Box::new(file),
+                  Err(error) => return Err(self.failure(dusty_context, RuntimeError::Trace(error.to_string()))),
+              },
+              None => Box::new(io::stderr()),
+          });
+      }
+      self.check_memory(options).map_err(|error| self.failure(dusty_context, error))?;
+      let start = Instant::now();
+      let mut executed: u64 = 0;
+      loop {
+          let pointer = self.instruction_pointer;
+          if pointer < dusty_context.quadruples.len() {
+              check_limits(options, executed, start).map_err(|error| self.failure(dusty_context, error))?;
+              executed += 1;
+          }
+          let traced = self.trace_output.is_some() && self.is_traced(dusty_context, options, pointer);
+          let profiled = self.profile.is_some() && pointer < dusty_context.quadruples.len();
+          let function = if traced || profiled {
+              self.call_stack.last().map_or("global", |function| function.as_str()).to_string()
+          } else {
+              String::new()
+          };
+          let reads = if traced { self.trace_reads(dusty_context, pointer) } else { Vec::new() };
+
+          let result = self.step(dusty_context, options);
+          if traced {
+              self.write_trace(dusty_context, pointer, &function, reads, result.is_err())
+                  .map_err(|error| self.failure(dusty_context, error))?;
+          }
+          if profiled {
+              let profile = self.profile.as_mut().unwrap();
+              profile.count(pointer, &function);
+              match dusty_context.quadruples[pointer][0].name.as_str() {
+                  "gosub" if result.is_ok() => profile.enter(self.call_stack.last().unwrap()),
+                  "endfunc" => profile.exit(),
+                  _ => {}
+              }
+          }
+          let running = result.map_err(|error| self.failure(dusty_context, error))?;
+          if !running {
+              return Ok(());
+          }
+      }
+  }
+
+  // Executes the quadruple at the instruction pointer, false when the program ended
+  pub fn step(&mut self, dusty_context: &DustyContext, options: &VmOptions) -> Result<bool, RuntimeError> {
+      if self.instruction_pointer >= dusty_context.quadruples.len() {
+          return Ok(false);
+      }
+      let quadruple = &dusty_context.quadruples[self.instruction_pointer];
       let operator = &quadruple[0].name;
-    //   println!("POINTER: {}", intruction_pointer);
+    //   println!("POINTER: {}", self.instruction_pointer);
       match operator.to_string().as_str() {
           "goto" => {
-              intruction_pointer = quadruple[3].memory as usize - 1;
+              self.instruction_pointer = quadruple[3].memory as usize - 1;
               // println!("GOTO: {}", quadruple[3].memory - 1);
           }
           "gotof" => {
-              let (value, _) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
+              let (value, _) = self.read_value(&quadruple[1], options)?;
               if value == "0" {
-                  intruction_pointer = quadruple[3].memory as usize;
-                //   println!("GOTOF: {:#?}, Som {}", dusty_context.quadruples[intruction_pointer], intruction_pointer);
+                  self.instruction_pointer = quadruple[3].memory as usize - 1;
+                //   println!("GOTOF: {:#?}, Som {}", dusty_context.quadruples[self.instruction_pointer], self.instruction_pointer);
               } else {
-                  intruction_pointer += 1;
+                  self.instruction_pointer += 1;
               }
           }
           "era" => {
-              allocate_to_stack(&mut virtual_memory, dusty_context, quadruple[3].name.as_str());
-              intruction_pointer += 1;
+              allocate_to_stack(&mut self.memory, dusty_context, quadruple[3].name.as_str())?;
+              self.memory.frames_size += self.memory.pending_frames.last().map_or(0, |frame| frame.size());
+              self.check_memory(options)?;
+              self.instruction_pointer += 1;
           }
           "param" => {
-              let (value, _) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              set_param_value(&mut virtual_memory, quadruple[3].memory as usize, value);
-              intruction_pointer += 1;
+              let (value, value_type) = self.read_value(&quadruple[1], options)?;
+              if map_address(quadruple[3].memory as usize).is_some() {
+                  set_param_value(&mut self.memory, quadruple[3].memory as usize, value)?;
+              } else {
+                  // Arguments of builtin and host functions are collected until CALLB
+                  self.memory.call_args.push(Value::parse(&value, value_type)?);
+              }
+              self.instruction_pointer += 1;
+          }
+          "callb" => {
+              let arg_count = quadruple[2].memory as usize;
+              let Some(first_arg) = self.memory.call_args.len().checked_sub(arg_count) else {
+                  return Err(RuntimeError::Internal(format!("\"{}\" expects {} arguments, {} were passed", quadruple[1].name, arg_count, self.memory.call_args.len())));
+              };
+              let args = self.memory.call_args.split_off(first_arg);
+              let builtin = dusty_context.builtin_dir.get(&quadruple[1].name)
+                  .ok_or(RuntimeError::UnknownFunction(quadruple[1].name.clone()))?;
+              let result = match &builtin.native {
+                  Some(native) => native(&args).map_err(|error| match error {
+                      RuntimeError::Native(message) => RuntimeError::Native(format!("{} (in \"{}\")", message, quadruple[1].name)),
+                      error => error,
+                  })?,
+                  None => call_builtin(&quadruple[1].name, &args)?,
+              };
+              // Void functions have no result address
+              if quadruple[3].memory != 0 {
+                  let result = match (result, builtin.return_type.as_str()) {
+                      (Value::Int(value), "float") => Value::Float(value as f64),
+                      (Value::Float(_), "int") => return Err(RuntimeError::InvalidReturn(quadruple[1].name.clone())),
+                      (result, _) => result,
+                  };
+                  set_value(&mut self.memory, quadruple[3].memory as usize, result.to_string())?;
+              }
+              self.instruction_pointer += 1;
           }
           "gosub" => {
-              let current_pointer = intruction_pointer + 1;
-              virtual_memory.jump_stack.push(current_pointer);
-              intruction_pointer = quadruple[3].memory as usize - 1;
+              if self.call_stack.len() >= options.max_call_depth {
+                  return Err(RuntimeError::StackOverflow(options.max_call_depth));
+              }
+              let local_memory = self.memory.pending_frames.pop()
+                  .ok_or(RuntimeError::Internal(format!("call to \"{}\" without a frame", quadruple[3].name)))?;
+              self.call_stack.push(quadruple[3].name.clone());
+              let current_pointer = self.instruction_pointer + 1;
+              self.memory.jump_stack.push(current_pointer);
+              self.memory.memory_stack.push(local_memory);
+              self.instruction_pointer = quadruple[3].memory as usize - 1;
             //   println!("GOSUB: {}", quadruple[3].memory - 1);
           }
           "endfunc" => {
               // println!("ENDFUNC");
-              let return_pointer = virtual_memory.jump_stack.pop().unwrap();
-              intruction_pointer = return_pointer;
+              let (Some(return_pointer), Some(local_memory)) = (self.memory.jump_stack.pop(), self.memory.memory_stack.pop()) else {
+                  return Err(RuntimeError::Internal("end of a function that was not called".to_string()));
+              };
+              self.call_stack.pop();
+              self.memory.frames_size -= local_memory.size();
+              self.instruction_pointer = return_pointer;
           }
-          "end" => {
-              break;
+          "end" => return Ok(false),
+          "reset" => {
+              reset_initialized(&mut self.memory, quadruple[1].memory as usize, quadruple[2].memory as usize)?;
+              self.instruction_pointer += 1;
           }
           "=" => {
               // println!("{:#?}", quadruple);
-              let (value, _) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, value);
-              intruction_pointer += 1;
+              let (value, _) = self.read_value(&quadruple[1], options)?;
+              set_value(&mut self.memory, quadruple[3].memory as usize, value)?;
+              self.instruction_pointer += 1;
           }
-          "+" => {
-              let (left_value, left_type) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              let (right_value, right_type) = get_value(&virtual_memory, quadruple[2].memory as usize).unwrap();
+          "+" | "-" | "*" => {
+              let (left_value, left_type) = self.read_value(&quadruple[1], options)?;
+              let (right_value, right_type) = self.read_value(&quadruple[2], options)?;
 
               let result = if left_type == "float" || right_type == "float" {
                   // Coerce to float if either operand is a float
-                  let left = left_value.parse::<f32>().unwrap();
-                  let right = right_value.parse::<f32>().unwrap();
-                  (left + right).to_string()
+                  let left: f64 = parse_value(&left_value)?;
+                  let right: f64 = parse_value(&right_value)?;
+                  match operator.as_str() {
+                      "+" => (left + right).to_string(),
+                      "-" => (left - right).to_string(),
+                      _ => (left * right).to_string(),
+                  }
               } else {
                   // Both operands are integers
-                  let left = left_value.parse::<i32>().unwrap();
-                  let right = right_value.parse::<i32>().unwrap();
-                  (left + right).to_string()
+                  let left: i64 = parse_value(&left_value)?;
+                  let right: i64 = parse_value(&right_value)?;
+                //   println!("LEFT: {:#?}, RIGHT: {:#?}", left, right);
+                  int_operation(left, right, operator, options.wrapping_arithmetic)?.to_string()
               };
 
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, (result).to_string());
-              intruction_pointer += 1;
+              set_value(&mut self.memory, quadruple[3].memory as usize, result)?;
+              self.instruction_pointer += 1;
+          }
+          "/" => {
+              let (left_value, _) = self.read_value(&quadruple[1], options)?;
+              let (right_value, _) = self.read_value(&quadruple[2], options)?;
+
+              // Division always results in a float
+              let left: f64 = parse_value(&left_value)?;
+              let right: f64 = parse_value(&right_value)?;
+              if right == 0.0 {
+                  return Err(RuntimeError::DivisionByZero);
+              }
+              let result = (left / right).to_string();
+
+              set_value(&mut self.memory, quadruple[3].memory as usize, result)?;
+              self.instruction_pointer += 1;
           }
-          "-" => {
-              let (left_value, left_type) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              let (right_value, right_type) = get_value(&virtual_memory, quadruple[2].memory as usize).unwrap();
+          ">" | "<" | "==" | "!=" => {
+              let (left_value, left_type) = self.read_value(&quadruple[1], options)?;
+              let (right_value, right_type) = self.read_value(&quadruple[2], options)?;
 
               let result = if left_type == "float" || right_type == "float" {
-                  // Coerce to float if either operand is a float
-                  let left = left_value.parse::<f32>().unwrap();
-                  let right = right_value.parse::<f32>().unwrap();
-                  (left - right).to_string()
+                  compare(parse_value::<f64>(&left_value)?, parse_value(&right_value)?, operator)
               } else {
-                  // Both operands are integers
-                  let left = left_value.parse::<i32>().unwrap();
-                  let right = right_value.parse::<i32>().unwrap();
-                  (left - right).to_string()
+                  compare(parse_value::<i64>(&left_value)?, parse_value(&right_value)?, operator)
               };
 
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, (result).to_string());
-              intruction_pointer += 1;
+              set_value(&mut self.memory, quadruple[3].memory as usize, bool_to_int(result).to_string())?;
+              self.instruction_pointer += 1;
           }
-          "*" => {
-              let (left_value, left_type) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              let (right_value, right_type) = get_value(&virtual_memory, quadruple[2].memory as usize).unwrap();
-
-              let result = if left_type == "float" || right_type == "float" {
-                  // Coerce to float if either operand is a float
-                  let left = left_value.parse::<f32>().unwrap();
-                  let right = right_value.parse::<f32>().unwrap();
-                  (left * right).to_string()
-              } else {
-                  // Both operands are integers
-                  let left = left_value.parse::<i32>().unwrap();
-                  let right = right_value.parse::<i32>().unwrap();
-                //   println!("LEFT: {:#?}, RIGHT: {:#?}", left, right);
-                  (left * right).to_string()
+          "int" | "float" | "round" | "floor" | "ceil" => {
+              let (value, _) = self.read_value(&quadruple[1], options)?;
+              let value: f64 = parse_value(&value)?;
+
+              let wrapping = options.wrapping_arithmetic;
+              let result = match operator.as_str() {
+                  "int" => float_to_int(value.trunc(), operator, value, wrapping)?.to_string(),
+                  "float" => value.to_string(),
+                  "round" => float_to_int(value.round(), operator, value, wrapping)?.to_string(),
+                  "floor" => float_to_int(value.floor(), operator, value, wrapping)?.to_string(),
+                  _ => float_to_int(value.ceil(), operator, value, wrapping)?.to_string(),
               };
 
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, (result).to_string());
-              intruction_pointer += 1;
+              set_value(&mut self.memory, quadruple[3].memory as usize, result)?;
+              self.instruction_pointer += 1;
           }
-          "/" => {
-              let (left_value, _) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              let (right_value, _) = get_value(&virtual_memory, quadruple[2].memory as usize).unwrap();
+          "print" => {
+              let (value, _) = self.read_value(&quadruple[3], options)?;
+              match dusty_context.enum_dir.get(&quadruple[1].name) {
+                  Some(enum_info) => {
+                      let variant = parse_value::<usize>(&value)?;
+                      println!("{}", enum_info.variants.get(variant).ok_or(RuntimeError::InvalidValue(value))?);
+                  }
+                  None => println!("{}", value),
+              }
+              self.instruction_pointer += 1;
+          }
+          _ => {self.instruction_pointer += 1;}
+      }
+      Ok(true)
+  }
 
-              // Both operands are integers
-              let left = left_value.parse::<i32>().unwrap();
-              let right = right_value.parse::<i32>().unwrap();
-              let result = (left / right).to_string();
+  fn check_memory(&self, options: &VmOptions) -> Result<(), RuntimeError> {
+      match options.max_memory {
+          Some(max) if self.memory.global_size() + self.memory.frames_size > max => Err(RuntimeError::OutOfMemory(max)),
+          _ => Ok(()),
+      }
+  }
 
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, (result).to_string());
-              intruction_pointer += 1;
-          }
-          ">" => {
-              let (left_value, _) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              let (right_value, _) = get_value(&virtual_memory, quadruple[2].memory as usize).unwrap();
+  fn read_value(&self, unit: &QuadrupleUnit, options: &VmOptions) -> Result<(String, &'static str), RuntimeError> {
+      if options.check_uninitialized && !is_initialized(&self.memory, unit.memory as usize) {
+          return Err(RuntimeError::Uninitialized(unit.name.clone()));
+      }
+      get_value(&self.memory, unit.memory as usize).ok_or(RuntimeError::InvalidAddress(unit.memory))
+  }
 
-              // Both operands are integers
-              let left = left_value.parse::<i32>().unwrap();
-              let right = right_value.parse::<i32>().unwrap();
-              let result = (bool_to_int(left > right)).to_string();
+  // Whether the quadruple at the pointer passes the function and address filters
+  fn is_traced(&self, dusty_context: &DustyContext, options: &VmOptions, pointer: usize) -> bool {
+      let Some(quadruple) = dusty_context.quadruples.get(pointer) else {
+          return false;
+      };
+      let function = self.call_stack.last().map_or("global", |function| function.as_str());
+      // Functions are matched by signature or by name, with or without the namespace
+      let function_name = function.split('(').next().unwrap_or(function);
+      let in_function = options.trace_function.as_ref().is_none_or(|name| {
+          function == name || function_name == name || function_name.rsplit('.').next() == Some(name.as_str())
+      });
+      let in_addresses = options.trace_addresses.is_none_or(|(from, to)| {
+          quadruple[1..].iter().any(|unit| map_address(unit.memory as usize).is_some() && (from..=to).contains(&unit.memory))
+      });
+      in_function && in_addresses
+  }
 
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, (result).to_string());
-              intruction_pointer += 1;
-          }
-          "<" => {
-              let (left_value, _) = get_value(&virtual_memory, quadruple[1].memory as usize).unwrap();
-              let (right_value, _) = get_value(&virtual_memory, quadruple[2].memory as usize).unwrap();
+  // Addresses the quadruple reads and their values, taken before it runs
+  fn trace_reads(&self, dusty_context: &DustyContext, pointer: usize) -> Vec<(u32, String)> {
+      let quadruple = &dusty_context.quadruples[pointer];
+      let operands: &[usize] = match quadruple[0].name.as_str() {
+          "print" => &[3],
+          "goto" | "era" | "gosub" | "endfunc" | "callb" | "end" | "reset" => &[],
+          _ => &[1, 2],
+      };
+      operands.iter()
+          .map(|operand| quadruple[*operand].memory)
+          .filter(|address| map_address(*address as usize).is_some())
+          .map(|address| (address, get_value(&self.memory, address as usize).map_or("?".to_string(), |(value, _)| value)))
+          .collect()
+  }
 
-              // Both operands are integers
-              let left = left_value.parse::<i32>().unwrap();
-              let right = right_value.parse::<i32>().unwrap();
-              let result = (bool_to_int(left < right)).to_string();
+  // One line per quadruple: "<quad> <function> <operator> [<name>] <address>=<value>...",
+  // followed by "-> <address>=<value>" for the value written, "-> quad <n>"
+  // for the next quadruple of a jump or "-> error" when it raised a runtime error
+  fn write_trace(
+      &mut self,
+      dusty_context: &DustyContext,
+      pointer: usize,
+      function: &str,
+      reads: Vec<(u32, String)>,
+      failed: bool
+  ) -> Result<(), RuntimeError> {
+      let quadruple = &dusty_context.quadruples[pointer];
+      let operator = quadruple[0].name.as_str();
+      let mut line = format!("{} {} {}", pointer + 1, function, operator);
+      match operator {
+          "era" | "gosub" => line.push_str(&format!(" {}", quadruple[3].name)),
+          "callb" => line.push_str(&format!(" {}", quadruple[1].name)),
+          _ => {}
+      }
+      for (address, value) in reads.iter() {
+          line.push_str(&format!(" {}={}", address, value));
+      }
 
-              set_value(&mut virtual_memory, quadruple[3].memory as usize, (result).to_string());
-              intruction_pointer += 1;
-          }
-          "print" => {
-              let (value, _) = get_value(&virtual_memory, quadruple[3].memory as usize).unwrap();
-              println!("{}", value);
-              intruction_pointer += 1;
+      let target = quadruple[3].memory;
+      let written = match operator {
+          _ if failed => Some("error".to_string()),
+          "goto" | "gotof" | "gosub" | "endfunc" => Some(format!("quad {}", self.instruction_pointer + 1)),
+          // The parameter is in the frame of the call, not the current one
+          "param" if map_address(target as usize).is_some() => reads.first().map(|(_, value)| format!("{}={}", target, value)),
+          "print" | "param" | "era" | "end" | "reset" => None,
+          _ => get_value(&self.memory, target as usize).map(|(value, _)| format!("{}={}", target, value)),
+      };
+      if let Some(written) = written {
+          line.push_str(&format!(" -> {}", written));
+      }
+      let Some(output) = self.trace_output.as_mut() else {
+          return Ok(());
+      };
+      writeln!(output, "{}", line).map_err(|error| RuntimeError::Trace(error.to_string()))
+  }
+
+  // The error with the functions being run, each one where it stopped: the
+  // innermost at the current quadruple, the callers at their GOSUB (the quad
+  // before their return address)
+  pub fn failure(&self, dusty_context: &DustyContext, error: RuntimeError) -> RuntimeFailure {
+      let functions = self.call_stack.iter().rev().map(|function| function.as_str()).chain(["global"]);
+      let pointers = std::iter::once(self.instruction_pointer)
+          .chain(self.memory.jump_stack.iter().rev().map(|return_pointer| return_pointer - 1));
+      let stack = functions.zip(pointers).map(|(function, pointer)| {
+          let location = dusty_context.quad_locations.get(pointer);
+          StackFrame {
+              function: function.to_string(),
+              span: location.map(|location| location.span),
+              file: location.and_then(|location| dusty_context.modules.get(&location.module).cloned()),
           }
-          _ => {intruction_pointer += 1;}
+      }).collect();
+      RuntimeFailure {
+          error,
+          stack,
+      }
+  }
+
+  // Value at an address of the globals, the current frame or the constants,
+  // None when it was never assigned
+  pub fn read(&self, address: u32) -> Option<String> {
+      if !is_initialized(&self.memory, address as usize) {
+          return None;
       }
+      get_value(&self.memory, address as usize).map(|(value, _)| value)
+  }
+
+  // Value of a variable as print! shows it, enum values by their variant name
+  pub fn describe(&self, var: &VarInfo, dusty_context: &DustyContext) -> String {
+      match (self.read(var.location), dusty_context.enum_dir.get(&var.var_type)) {
+          (Some(value), Some(enum_info)) => value.parse::<usize>().ok()
+              .and_then(|variant| enum_info.variants.get(variant).cloned())
+              .unwrap_or(value),
+          (Some(value), None) => value,
+          (None, _) => "unassigned".to_string(),
+      }
+  }
+
+  // Drops the frames of a run that stopped inside a function
+  pub fn reset(&mut self, dusty_context: &DustyContext) {
+      self.memory.memory_stack.clear();
+      self.memory.pending_frames.clear();
+      self.memory.call_args.clear();
+      self.memory.jump_stack.clear();
+      self.memory.frames_size = 0;
+      self.call_stack.clear();
+      self.instruction_pointer = dusty_context.quadruples.len();
   }
-//   println!("\n################### OUTPUT WINDOW ###################\n");
+}
+
+pub fn run_virtual_machine(dusty_context: &DustyContext, options: &VmOptions) -> Result<(), RuntimeFailure> {
+  let mut virtual_machine = VirtualMachine::new(dusty_context).map_err(|error| RuntimeFailure { error, stack: Vec::new() })?;
+  virtual_machine.run(dusty_context, options)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::*;
+  use crate::compile;
 
-//   println!("{:#?}", virtual_memory);
-}
\ No newline at end of file
+  fn compiled(source: &str) -> DustyContext {
+      let mut dusty_context = DustyContext::new();
+      compile(source, &mut dusty_context).unwrap();
+      dusty_context
+  }
+
+  // Program made of the quadruples, (operator, left, right, result) each
+  fn with_quadruples(quadruples: &[(&str, u32, u32, &str, u32)]) -> DustyContext {
+      let mut dusty_context = compiled("program p;\nbegin\n{\n  print!(1);\n}\nend");
+      dusty_context.quadruples = quadruples.iter().map(|(operator, left, right, result_name, result)| [
+          QuadrupleUnit::new(operator.to_string(), 0),
+          QuadrupleUnit::new(String::new(), *left),
+          QuadrupleUnit::new(String::new(), *right),
+          QuadrupleUnit::new(result_name.to_string(), *result),
+      ]).collect();
+      dusty_context
+  }
+
+  // Runs the program and reads the values of its globals
+  fn run_globals(dusty_context: &DustyContext, options: &VmOptions, names: &[&str]) -> Vec<String> {
+      let mut virtual_machine = VirtualMachine::new(dusty_context).unwrap();
+      virtual_machine.run(dusty_context, options).unwrap();
+      names.iter().map(|name| {
+          let symbol = dusty_context.symbols.iter().find(|symbol| symbol.name == *name).unwrap();
+          virtual_machine.read(symbol.var.as_ref().unwrap().location).unwrap()
+      }).collect()
+  }
+
+  fn run_error(dusty_context: &DustyContext) -> RuntimeError {
+      run_virtual_machine(dusty_context, &VmOptions::new()).unwrap_err().error
+  }
+
+  #[test]
+  fn calls_without_a_frame_or_a_caller_are_internal_errors() {
+      let gosub = with_quadruples(&[("gosub", 0, 0, "f()", 1)]);
+      assert!(matches!(run_error(&gosub), RuntimeError::Internal(_)));
+      let endfunc = with_quadruples(&[("endfunc", 0, 0, "", 0)]);
+      assert!(matches!(run_error(&endfunc), RuntimeError::Internal(_)));
+      let callb = with_quadruples(&[("callb", 0, 2, "", 0)]);
+      assert_eq!(run_error(&callb).exit_code(), 17);
+      let era = with_quadruples(&[("era", 0, 0, "missing()", 0)]);
+      assert_eq!(run_error(&era), RuntimeError::UnknownFunction("missing()".to_string()));
+  }
+
+  #[test]
+  fn addresses_past_their_segment_are_invalid() {
+      // The program has no globals, 1000 is in the int segment but past its size
+      let dusty_context = with_quadruples(&[("=", 21000, 0, "x", 1000)]);
+      assert_eq!(run_error(&dusty_context), RuntimeError::InvalidAddress(1000));
+      assert_eq!(run_error(&with_quadruples(&[("print", 0, 0, "", 9500)])), RuntimeError::InvalidAddress(9500));
+  }
+
+  #[test]
+  fn constants_that_dont_parse_are_invalid_values() {
+      let mut dusty_context = compiled("program p;\nbegin\n{\n  print!(1);\n}\nend");
+      dusty_context.const_dir.insert(("int".to_string(), "one".to_string()), VarInfo::new("one".to_string(), "int".to_string(), 21001));
+      dusty_context.constants[0] += 1;
+      assert_eq!(VirtualMachine::new(&dusty_context).err(), Some(RuntimeError::InvalidValue("one".to_string())));
+      assert_eq!(Value::parse("1.5", "int"), Err(RuntimeError::InvalidValue("1.5".to_string())));
+  }
+
+  #[test]
+  fn trace_files_that_cant_be_created_are_runtime_errors() {
+      let dusty_context = compiled("program p;\nbegin\n{\n  print!(1);\n}\nend");
+      let mut options = VmOptions::new();
+      options.trace = true;
+      options.trace_file = Some("/nonexistent/trace.txt".into());
+      let failure = run_virtual_machine(&dusty_context, &options).unwrap_err();
+      assert!(matches!(failure.error, RuntimeError::Trace(_)));
+      assert_eq!(failure.error.exit_code(), 16);
+  }
+
+  #[test]
+  fn reads_before_assignment_are_errors_with_check_uninit() {
+      let dusty_context = compiled(&std::fs::read_to_string("src/tests/test6.dusty").unwrap());
+      assert!(run_virtual_machine(&dusty_context, &VmOptions::new()).is_ok());
+      let mut options = VmOptions::new();
+      options.check_uninitialized = true;
+      let failure = run_virtual_machine(&dusty_context, &options).unwrap_err();
+      assert_eq!(failure.error, RuntimeError::Uninitialized("ratio".to_string()));
+      assert_eq!(failure.error.exit_code(), 4);
+      assert_eq!(failure.to_string(), "RUNTIME ERROR: Variable \"ratio\" was read before being assigned, line: 23, col: 3\n    at global, line: 23, col: 3");
+  }
+
+  #[test]
+  fn conversions_truncate_round_and_widen() {
+      let dusty_context = compiled("program p;
+vars ratio, widened: float; truncated, rounded, floored, ceiled: int;
+begin
+{
+  ratio = 0.0 - 2.5;
+  truncated = int(ratio);
+  rounded = round(ratio);
+  floored = floor(ratio);
+  ceiled = ceil(ratio);
+  widened = float(7) / 2.0;
+}
+end");
+      assert_eq!(
+          run_globals(&dusty_context, &VmOptions::new(), &["truncated", "rounded", "floored", "ceiled", "widened"]),
+          ["-2", "-3", "-3", "-2", "3.5"]
+      );
+  }
+
+  #[test]
+  fn deep_recursion_is_a_stack_overflow() {
+      let dusty_context = compiled("program p;
+void f(n: int) {
+  f(n + 1);
+};
+begin
+{
+  f(0);
+}
+end");
+      let mut options = VmOptions::new();
+      options.max_call_depth = 50;
+      let failure = run_virtual_machine(&dusty_context, &options).unwrap_err();
+      assert_eq!(failure.error, RuntimeError::StackOverflow(50));
+      assert_eq!(failure.error.exit_code(), 5);
+      assert_eq!(failure.stack.len(), 51);
+      assert!(failure.to_string().ends_with("at f(int), line: 3, col: 3 (50 times)\n    at global, line: 7, col: 3"));
+  }
+
+  // Lines of the trace of the program, with the filters set by the closure
+  fn trace(name: &str, filter: impl Fn(&mut VmOptions)) -> Vec<String> {
+      let dusty_context = compiled(&std::fs::read_to_string("src/tests/test4.dusty").unwrap());
+      let path = std::env::temp_dir().join(format!("dusty_trace_{}.txt", name));
+      let mut options = VmOptions::new();
+      options.trace = true;
+      options.trace_file = Some(path.clone());
+      filter(&mut options);
+      run_virtual_machine(&dusty_context, &options).unwrap();
+      std::fs::read_to_string(path).unwrap().lines().map(|line| line.to_string()).collect()
+  }
+
+  #[test]
+  fn traces_show_the_values_read_and_written() {
+      let lines = trace("all", |_| {});
+      assert_eq!(lines[..4], [
+          "1 global goto -> quad 5",
+          "5 global - 21000=100 21003=1 -> 5000=99",
+          "6 global = 5000=99 -> 1000=99",
+          "7 global print 1000=99",
+      ]);
+      assert!(lines.contains(&"11 global param 21000=100 -> 11000=100".to_string()));
+  }
+
+  #[test]
+  fn traces_keep_the_quadruples_of_a_function_or_an_address_range() {
+      assert_eq!(trace("function", |options| options.trace_function = Some("show_limit".to_string())), [
+          "2 show_limit(int) print 11000=100",
+          "3 show_limit(int) print 21002=181",
+          "4 show_limit(int) endfunc -> quad 13",
+      ]);
+      assert_eq!(trace("addresses", |options| options.trace_addresses = Some((1000, 1000))), [
+          "6 global = 5000=99 -> 1000=99",
+          "7 global print 1000=99",
+      ]);
+  }
+
+  const LOOP: &str = "program p;
+vars i: int;
+void f(n: int) {
+  vars a, b: int;
+  f(n + 1);
+};
+begin
+{
+  i = 0;
+  while (i < 10) do {
+    i = i + 0;
+  };
+}
+end";
+
+  #[test]
+  fn runs_stop_at_their_limits() {
+      let dusty_context = compiled(LOOP);
+      let mut options = VmOptions::new();
+      options.max_instructions = Some(100);
+      let failure = run_virtual_machine(&dusty_context, &options).unwrap_err();
+      assert_eq!((failure.error.exit_code(), failure.error), (13, RuntimeError::InstructionLimit(100)));
+      assert_eq!(failure.stack[0].function, "global");
+
+      let mut options = VmOptions::new();
+      options.max_time = Some(Duration::from_millis(20));
+      let failure = run_virtual_machine(&dusty_context, &options).unwrap_err();
+      assert_eq!((failure.error.exit_code(), failure.error), (14, RuntimeError::TimeLimit(Duration::from_millis(20))));
+
+      let dusty_context = compiled(&LOOP.replace("i = 0;", "f(0);"));
+      let mut options = VmOptions::new();
+      options.max_memory = Some(500);
+      let failure = run_virtual_machine(&dusty_context, &options).unwrap_err();
+      assert_eq!((failure.error.exit_code(), failure.error), (15, RuntimeError::OutOfMemory(500)));
+  }
+
+  const OVERFLOWS: &str = "program p;
+vars big, sum, product, negated, truncated: int; huge: float;
+begin
+{
+  big = 9223372036854775807;
+  huge = 10000000000000000000.0;
+  sum = big + 1;
+  product = big * 2;
+  negated = 0 - big - 2;
+  truncated = int(huge);
+}
+end";
+
+  #[test]
+  fn int_overflow_is_an_error_by_default() {
+      let dusty_context = compiled(OVERFLOWS);
+      let failure = run_virtual_machine(&dusty_context, &VmOptions::new()).unwrap_err();
+      assert_eq!(failure.error, RuntimeError::Overflow("9223372036854775807 + 1".to_string()));
+      assert_eq!(failure.error.exit_code(), 3);
+      assert_eq!(failure.stack[0].span.unwrap().start(), (7, 9));
+  }
+
+  #[test]
+  fn int_overflow_wraps_around_with_wrapping() {
+      let dusty_context = compiled(OVERFLOWS);
+      let mut options = VmOptions::new();
+      options.wrapping_arithmetic = true;
+      assert_eq!(
+          run_globals(&dusty_context, &options, &["sum", "product", "negated", "truncated"]),
+          ["-9223372036854775808", "-2", "9223372036854775807", "-8446744073709551616"]
+      );
+  }
+
+  #[test]
+  fn int_literals_that_dont_fit_are_compile_errors() {
+      let mut dusty_context = DustyContext::new();
+      let error = compile("program p; vars x: int; begin { x = 9223372036854775808; } end", &mut dusty_context).unwrap_err();
+      assert_eq!(error.to_string(), "ERROR: Int literal 9223372036854775808 does not fit in an int, line: 1, col: 37");
+  }
+
+  #[test]
+  fn memory_keeps_64_bit_ints_and_double_precision_floats() {
+      let dusty_context = compiled("program p;
+vars big: int; third, sum: float;
+begin
+{
+  big = 3000000000 * 3;
+  third = 1.0 / 3.0;
+  sum = 0.1 + 0.2;
+}
+end");
+      assert_eq!(
+          run_globals(&dusty_context, &VmOptions::new(), &["big", "third", "sum"]),
+          ["9000000000", "0.3333333333333333", "0.30000000000000004"]
+      );
+  }
+}